@@ -0,0 +1,366 @@
+use alloc::vec::Vec;
+use core::future::Future;
+
+use crate::{
+    config::AudioConfig,
+    diagnostics::{LoadMeter, AUDIO_STATS},
+    filters::traits::Filter,
+    i2s::{Sample, CHUNK_SAMPLES},
+    master::MasterBus,
+    oscillators::traits::Generator,
+    stereo::Stereo,
+};
+
+/// The sink could not keep up and ran out of samples, e.g. the DMA caught up with the writer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Underrun;
+
+/// Destination of the rendered samples
+pub trait AudioSink {
+    /// Write the first samples of `samples`, waits until the sink accepts at least one
+    ///
+    /// Returns how many samples were taken.
+    fn write(&mut self, samples: &[Sample]) -> impl Future<Output = Result<usize, Underrun>>;
+}
+
+/// The source was not read in time and dropped samples, e.g. the DMA overwrote unread data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Overrun;
+
+/// Origin of recorded samples, e.g. an I2S ADC
+pub trait AudioSource {
+    /// Read samples into the start of `samples`, waits until at least one is available
+    ///
+    /// Returns how many samples were read.
+    fn read(&mut self, samples: &mut [Sample]) -> impl Future<Output = Result<usize, Overrun>>;
+}
+
+/// Output of a generator that the engine can play, mono or stereo
+pub trait Frame {
+    fn to_stereo(self) -> Stereo;
+}
+
+impl Frame for f32 {
+    fn to_stereo(self) -> Stereo {
+        [self, self]
+    }
+}
+
+impl Frame for Stereo {
+    fn to_stereo(self) -> Stereo {
+        self
+    }
+}
+
+/// Render loop from float frames to an [`AudioSink`]
+///
+/// Owns the chunk buffer and the [`MasterBus`], which scales and quantizes the frames. Every
+/// round, fill [`Engine::frames`] and call [`Engine::push`], or let [`Engine::render`] do both
+/// with a generator.
+///
+/// The chunks have the size of the [`AudioConfig`], up to [`CHUNK_SAMPLES`] frames. The sink may
+/// take less than a whole chunk. The rest stays in the buffer and goes out first in
+/// the next round, so only as many frames are rendered as the sink took. Underruns are counted
+/// and rendering just continues.
+///
+/// The engine measures the time between two pushes, i.e. the time to render the frames, and
+/// reports the load and the underruns to [`AUDIO_STATS`].
+pub struct Engine<S> {
+    sink: S,
+    pub master: MasterBus,
+    frames: [Stereo; CHUNK_SAMPLES],
+    buffer: [Sample; CHUNK_SAMPLES],
+    /// frames per chunk
+    chunk_size: usize,
+    /// samples at the start of `buffer` that the sink has not taken yet
+    pending: usize,
+    underruns: u32,
+    meter: LoadMeter,
+}
+
+impl<S: AudioSink> Engine<S> {
+    pub fn new(sink: S) -> Self {
+        Self {
+            sink,
+            master: MasterBus::new(),
+            frames: [[0.; 2]; CHUNK_SAMPLES],
+            buffer: [[0; 2]; CHUNK_SAMPLES],
+            chunk_size: AudioConfig::current().chunk_size,
+            pending: 0,
+            underruns: 0,
+            meter: LoadMeter::new(),
+        }
+    }
+
+    /// The frames to render before the next [`Engine::push`]
+    pub fn frames(&mut self) -> &mut [Stereo] {
+        &mut self.frames[..self.chunk_size - self.pending]
+    }
+
+    /// Convert the rendered frames and write as many samples as the sink takes
+    pub async fn push(&mut self) {
+        let free = self.chunk_size - self.pending;
+        for (sample, frame) in self.buffer[self.pending..self.chunk_size]
+            .iter_mut()
+            .zip(&self.frames[..free])
+        {
+            *sample = self.master.filter(*frame);
+        }
+        self.meter.rendered(free);
+
+        let written = match self.sink.write(&self.buffer[..self.chunk_size]).await {
+            Ok(written) => written.min(self.chunk_size),
+            Err(Underrun) => {
+                self.underruns = self.underruns.wrapping_add(1);
+                AUDIO_STATS.underrun();
+                0
+            }
+        };
+        self.meter.start();
+
+        // W: written, S: skipped
+        // [ W W W W W W W W W W W W W W W W S S S S ]
+        // [ S S S S _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ ]
+        //           ^ pending
+        self.buffer[..self.chunk_size].rotate_left(written);
+        self.pending = self.chunk_size - written;
+    }
+
+    /// Render the frames with `generator` and push them
+    pub async fn render<G>(&mut self, generator: &mut G)
+    where
+        G: Generator,
+        G::Out: Frame,
+    {
+        for frame in self.frames() {
+            *frame = generator.generate().to_stereo();
+        }
+        self.push().await;
+    }
+
+    /// Number of underruns of the sink since the start
+    pub fn underruns(&self) -> u32 {
+        self.underruns
+    }
+
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+}
+
+/// Full scale of a [`Sample`] as a float frame
+const SAMPLE_SCALE: f32 = 1. / 2_147_483_648.;
+
+/// Reads an [`AudioSource`] into float frames, the input side of the [`Engine`]
+///
+/// On a full-duplex I2S both sides run on the same clock, so pulling as many frames as the engine
+/// renders keeps them in step. Overruns are counted, the samples that were lost are skipped.
+pub struct Capture<S> {
+    source: S,
+    buffer: [Sample; CHUNK_SAMPLES],
+    overruns: u32,
+}
+
+impl<S: AudioSource> Capture<S> {
+    pub fn new(source: S) -> Self {
+        Self {
+            source,
+            buffer: [[0; 2]; CHUNK_SAMPLES],
+            overruns: 0,
+        }
+    }
+
+    /// Fill `frames` with the next input frames, waits until there are enough of them
+    pub async fn pull(&mut self, frames: &mut [Stereo]) {
+        let mut filled = 0;
+        while filled < frames.len() {
+            let len = (frames.len() - filled).min(CHUNK_SAMPLES);
+            match self.source.read(&mut self.buffer[..len]).await {
+                Ok(read) => {
+                    let read = read.min(len);
+                    for (frame, sample) in frames[filled..].iter_mut().zip(&self.buffer[..read]) {
+                        *frame = sample.map(|x| x as f32 * SAMPLE_SCALE);
+                    }
+                    filled += read;
+                }
+                Err(Overrun) => {
+                    self.overruns = self.overruns.wrapping_add(1);
+                    AUDIO_STATS.overrun();
+                }
+            }
+        }
+    }
+
+    /// Number of overruns of the source since the start
+    pub fn overruns(&self) -> u32 {
+        self.overruns
+    }
+
+    pub fn source(&mut self) -> &mut S {
+        &mut self.source
+    }
+}
+
+/// Sink that collects the samples in memory, to run the engine on the host
+pub struct MemorySink {
+    pub samples: Vec<Sample>,
+    /// most samples taken per write, like a DMA buffer that is partly full
+    pub max_write: usize,
+    /// report an underrun on the next write instead of taking samples
+    pub underrun_next: bool,
+}
+
+impl MemorySink {
+    pub fn new(max_write: usize) -> Self {
+        Self {
+            samples: Vec::new(),
+            max_write,
+            underrun_next: false,
+        }
+    }
+}
+
+impl AudioSink for MemorySink {
+    async fn write(&mut self, samples: &[Sample]) -> Result<usize, Underrun> {
+        if core::mem::take(&mut self.underrun_next) {
+            return Err(Underrun);
+        }
+        let n = samples.len().min(self.max_write);
+        self.samples.extend_from_slice(&samples[..n]);
+        Ok(n)
+    }
+}
+
+/// Source that plays samples from memory, to run a [`Capture`] on the host
+pub struct MemorySource {
+    pub samples: Vec<Sample>,
+    /// next sample to read
+    pub position: usize,
+    /// most samples returned per read, like a DMA buffer that is partly full
+    pub max_read: usize,
+    /// report an overrun on the next read instead of returning samples
+    pub overrun_next: bool,
+}
+
+impl MemorySource {
+    pub fn new(samples: Vec<Sample>, max_read: usize) -> Self {
+        Self {
+            samples,
+            position: 0,
+            max_read,
+            overrun_next: false,
+        }
+    }
+}
+
+impl AudioSource for MemorySource {
+    /// Reads silence once all samples were played
+    async fn read(&mut self, samples: &mut [Sample]) -> Result<usize, Overrun> {
+        if core::mem::take(&mut self.overrun_next) {
+            return Err(Overrun);
+        }
+        let n = samples.len().min(self.max_read);
+        for sample in &mut samples[..n] {
+            *sample = self.samples.get(self.position).copied().unwrap_or([0; 2]);
+            self.position += 1;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{
+        pin::pin,
+        task::{Context, Poll, Waker},
+    };
+
+    use super::*;
+
+    /// Poll `future` to the end, the memory sink never makes it wait
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = pin!(future);
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    /// Distinct frames, so that every sample shows where it came from
+    struct Ramp(u32);
+
+    impl Generator for Ramp {
+        type Out = Stereo;
+
+        fn generate(&mut self) -> Stereo {
+            self.0 += 1;
+            let x = (self.0 % 1000) as f32 * 1e-4;
+            [x, -x]
+        }
+    }
+
+    /// The first `len` samples of the ramp through a fresh master bus
+    fn expected(len: usize) -> Vec<Sample> {
+        let mut ramp = Ramp(0);
+        let mut master = MasterBus::new();
+        (0..len).map(|_| master.filter(ramp.generate())).collect()
+    }
+
+    fn chunk_size() -> usize {
+        AudioConfig::current().chunk_size
+    }
+
+    #[test]
+    fn writes_whole_chunks_in_order() {
+        let chunk = chunk_size();
+        let mut engine = Engine::new(MemorySink::new(usize::MAX));
+        let mut ramp = Ramp(0);
+        for round in 1..=3 {
+            assert_eq!(engine.frames().len(), chunk);
+            block_on(engine.render(&mut ramp));
+            assert_eq!(engine.sink().samples.len(), round * chunk);
+        }
+        assert_eq!(engine.sink().samples, expected(3 * chunk));
+        assert_eq!(engine.underruns(), 0);
+    }
+
+    #[test]
+    fn partial_write_goes_out_first_in_the_next_round() {
+        let chunk = chunk_size();
+        let max_write = chunk / 3 + 1;
+        let mut engine = Engine::new(MemorySink::new(max_write));
+        let mut ramp = Ramp(0);
+        block_on(engine.render(&mut ramp));
+        assert_eq!(engine.sink().samples.len(), max_write);
+        // only as many frames are rendered as the sink took
+        assert_eq!(engine.frames().len(), max_write);
+
+        for _ in 1..10 {
+            block_on(engine.render(&mut ramp));
+        }
+        assert_eq!(ramp.0 as usize, chunk + 9 * max_write);
+        assert_eq!(engine.sink().samples, expected(10 * max_write));
+    }
+
+    #[test]
+    fn counts_underruns_and_keeps_the_chunk() {
+        let chunk = chunk_size();
+        let mut engine = Engine::new(MemorySink::new(usize::MAX));
+        let mut ramp = Ramp(0);
+        block_on(engine.render(&mut ramp));
+
+        engine.sink().underrun_next = true;
+        block_on(engine.render(&mut ramp));
+        assert_eq!(engine.underruns(), 1);
+        assert_eq!(engine.sink().samples.len(), chunk);
+        assert!(engine.frames().is_empty());
+
+        // the chunk that was not taken goes out in the next round, nothing is lost
+        block_on(engine.render(&mut ramp));
+        block_on(engine.render(&mut ramp));
+        assert_eq!(engine.underruns(), 1);
+        assert_eq!(engine.sink().samples, expected(3 * chunk));
+    }
+}
@@ -13,8 +13,9 @@ use esp_hal::{
 };
 use esp_println::println;
 use synth::{
-    i2s,
-    oscillators::{scales::REFERENCE_FREQ, traits::Generator, *},
+    audio::Engine,
+    i2s::{self, I2sSink},
+    oscillators::{scales::REFERENCE_FREQ, *},
 };
 
 #[esp_hal_embassy::main]
@@ -45,29 +46,14 @@ async fn main(_spawner: Spawner) {
         .build();
 
     let tx_buffer = i2s::take_tx_buffer();
-    let mut transfer = i2s_tx.write_dma_circular_async(tx_buffer).unwrap();
+    let transfer = i2s_tx.write_dma_circular_async(tx_buffer).unwrap();
 
     // GEN =============================
     let mut oscillator = SineOscillator::new(REFERENCE_FREQ);
 
-    // Initialize a buffer to generate samples into before writing them to the DMA channel
-    let mut buffer = i2s::new_chunk_buffer();
-    let mut start = 0;
+    // The engine scales the samples and pushes them to the i2s DMA
+    let mut engine = Engine::new(I2sSink::new(transfer));
     loop {
-        for sample in &mut buffer[start..] {
-            let a = oscillator.generate();
-            let b = (a * i16::MAX as f32) as i16 / 2;
-            *sample = [b, b];
-        }
-
-        // W: written, S: skipped
-        // [ W W W W W W W W W W W W W W W W S S S S ]
-        //                                   ^ written
-        let written = i2s::push(&mut transfer, &buffer).await;
-
-        // [ S S S S _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ ]
-        //           ^ start
-        buffer.rotate_left(written);
-        start = buffer.len() - written;
+        engine.render(&mut oscillator).await;
     }
 }
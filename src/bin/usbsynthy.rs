@@ -5,64 +5,109 @@ extern crate alloc;
 
 use alloc::vec;
 use embassy_executor::Spawner;
-use embassy_futures::join::join3;
-use embassy_sync::{blocking_mutex::raw::NoopRawMutex, mutex::Mutex};
-use embassy_time::Duration;
+use embassy_futures::{
+    join::{join, join4},
+    select::{select, Either},
+};
+use embassy_time::{Duration, Instant, Timer};
 use esp_backtrace as _;
+#[cfg(feature = "line-in")]
+use esp_hal::i2s::{asynch::I2sReadDmaAsync, I2sRx};
 use esp_hal::{
-    cpu_control::{CpuControl, Stack},
+    clock::CpuClock,
     dma::{Dma, DmaPriority},
-    gpio::{Io, Level, Output},
+    gpio::{Input, Io, Level, Output, Pull},
     i2s::{asynch::I2sWriteDmaAsync, I2sTx},
+    interrupt::software::SoftwareInterruptControl,
     otg_fs::Usb,
     timer::timg::TimerGroup,
+    uart::UartRx,
 };
-use esp_hal_embassy::Executor;
 use esp_println::println;
-use static_cell::StaticCell;
+use esp_storage::FlashStorage;
+#[cfg(feature = "line-in")]
+use synth::{audio::Capture, i2s::I2sSource, line_in::LineIn};
 use synth::{
-    i2s,
-    input::{produce_midi_on_analog_input_change, AnalogInputBuilder, AnalogInputConfig},
-    midi::{sequencer::sequencer, usb::handle_usb, MIDI_EVENTS},
-    voice::Voice,
+    audio::Engine,
+    config::{AudioConfig, BitDepth, SampleRate},
+    cores::start_audio_core,
+    diagnostics::{handle_console, log_diagnostics},
+    effects::Effects,
+    filters::traits::Filter,
+    i2s::{self, I2sSink},
+    input::{
+        produce_midi_on_analog_input_change, produce_midi_on_button_press, AnalogInputBuilder,
+        AnalogInputConfig,
+    },
+    link::Link,
+    midi::{
+        clock::MidiClock, learn::LEARN_CC, schedule::EventScheduler, sequencer::sequencer,
+        usb::handle_usb, ControlMode, MIDI_EVENTS,
+    },
+    mpe::MpeSynth,
+    preset::{
+        storage::{NorFlashPartition, PRESET_PARTITION_OFFSET, PRESET_PARTITION_SIZE},
+        PresetBank,
+    },
+    usb_audio::{self, UsbTap},
+    voice::Instrument,
+};
+
+const HEAP_BASE: usize = 16384;
+
+/// Format of the audio output
+const AUDIO_CONFIG: AudioConfig = AudioConfig {
+    sample_rate: SampleRate::Hz48000,
+    bit_depth: BitDepth::Bits16,
+    chunk_size: i2s::CHUNK_SAMPLES,
 };
 
-static APP_CORE_STACK: StaticCell<Stack<8192>> = StaticCell::new();
+/// Length of the delay line in seconds, every second takes 384 kB at 48 kHz
+#[cfg(feature = "psram")]
+const MAX_DELAY: f32 = 2.;
+#[cfg(not(feature = "psram"))]
+const MAX_DELAY: f32 = 0.25;
+
+/// The delay line and the reverb live on the heap, unless they can go to PSRAM
+///
+/// Both grow with the sample rate, the reverb takes about 1.4 bytes per Hz.
+#[cfg(feature = "psram")]
+const HEAP_SIZE: usize = HEAP_BASE;
+#[cfg(not(feature = "psram"))]
+const HEAP_SIZE: usize =
+    HEAP_BASE + ((MAX_DELAY * 8. + 1.4) * AUDIO_CONFIG.sample_rate.hz() as f32) as usize;
+
+/// Interval of the audio diagnostics on the console
+const DIAGNOSTICS_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Chunks between two reports of the MIDI timing, about one second
+const REPORT_CHUNKS: u32 = AUDIO_CONFIG.chunks_per_second();
 
 #[esp_hal_embassy::main]
-async fn main(_spawner: Spawner) {
-    let peripherals = esp_hal::init(esp_hal::Config::default());
+async fn main(spawner: Spawner) {
+    // everything that depends on the sample rate takes it from the applied configuration
+    AUDIO_CONFIG.apply();
+    // the load in the diagnostics is measured against `diagnostics::CPU_HZ`
+    let mut config = esp_hal::Config::default();
+    config.cpu_clock = CpuClock::max();
+    let peripherals = esp_hal::init(config);
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     esp_hal_embassy::init(timg0.timer0);
-    esp_alloc::heap_allocator!(8192);
+    esp_alloc::heap_allocator!(HEAP_SIZE);
+    // With the `psram` feature the external PSRAM extends the heap, large buffers like the delay
+    // line end up there
+    #[cfg(feature = "psram")]
+    esp_alloc::psram_allocator!(peripherals.PSRAM, esp_hal::psram);
 
     println!("Booting Rust Synth");
     let io = Io::new(peripherals.GPIO, peripherals.IO_MUX);
 
-    // I2S =============================
-    // Set up DMA (direct memory access) buffers.
-    let dma = Dma::new(peripherals.DMA);
-    let dma_channel = dma
-        .channel0
-        .configure_for_async(false, DmaPriority::Priority9);
-    let i2s = i2s::new_i2s(peripherals.I2S0, dma_channel);
-
-    // Create the i2s transfer channel and define pins
-    // The channel is used to control data flow to the DMA transaction
-    let i2s_tx: I2sTx<_, _> = i2s
-        .i2s_tx
-        .with_bclk(io.pins.gpio35)
-        .with_dout(io.pins.gpio36)
-        .with_ws(io.pins.gpio37)
-        .build();
-
-    let tx_buffer = i2s::take_tx_buffer();
-    let mut transfer = i2s_tx.write_dma_circular_async(tx_buffer).unwrap();
-
-    // USB MIDI =============================
+    // USB MIDI AND AUDIO ===================
     // Define the USB peripheral and the D+ and D- pins
     // GPIO19 and GPIO20 are connected to the second USB-C connector
     let usb = Usb::new(peripherals.USB0, io.pins.gpio20, io.pins.gpio19);
+    // The synth is a USB audio input as well, the audio task copies what it plays to the host
+    let (usb_frames, usb_stream) = usb_audio::take();
 
     // ANALOG INPUTS ========================
     let analog_input_config = AnalogInputConfig {
@@ -72,8 +117,9 @@ async fn main(_spawner: Spawner) {
     };
     let (mut adc, mut analog_inputs) = AnalogInputBuilder::new(analog_input_config)
         .add(io.pins.gpio7, 14)
-        .add(io.pins.gpio6, 15)
-        .add(io.pins.gpio5, 17)
+        // filter cutoffs use 14-bit control changes for smooth sweeps
+        .add_with_mode(io.pins.gpio6, ControlMode::Cc14(15))
+        .add_with_mode(io.pins.gpio5, ControlMode::Cc14(17))
         .add(io.pins.gpio4, 23)
         .build(peripherals.ADC1);
 
@@ -83,52 +129,163 @@ async fn main(_spawner: Spawner) {
         Duration::from_millis(10),
     );
 
-    // Spin up the second (APP) core with the `handle_usb` task
-    let mut cpu_control = CpuControl::new(peripherals.CPU_CTRL);
-    let _guard = cpu_control
-        .start_app_core(APP_CORE_STACK.init(Stack::new()), move || {
-            static EXECUTOR: StaticCell<Executor> = StaticCell::new();
-            let executor = EXECUTOR.init(Executor::new());
-            executor.run(|spawner| {
-                spawner.spawn(handle_usb(usb)).ok();
-            });
-        })
-        .unwrap();
+    // USB runs on the PRO core with the other control tasks
+    spawner.must_spawn(handle_usb(usb, usb_stream));
+
+    // MIDI LEARN ===========================
+    // The BOOT button toggles learn mode: press it, move a knob, then move the control of the
+    // parameter the knob should be bound to. Learned bindings are stored with the presets.
+    let mut learn_button = Input::new(io.pins.gpio0, Pull::Up);
+    let learn_fut = produce_midi_on_button_press(&mut learn_button, LEARN_CC);
 
-    // GEN =============================
-    let voice = Mutex::<NoopRawMutex, _>::new(Voice::new());
+    // PRESETS ==============================
+    // Presets are stored in the `presets` partition of the on-board flash, see `partitions.csv`.
+    // Program Change events recall a preset, CC 102 stores the current parameters.
+    let flash = NorFlashPartition::new(
+        FlashStorage::new(),
+        PRESET_PARTITION_OFFSET,
+        PRESET_PARTITION_SIZE,
+    );
+    let mut presets = PresetBank::mount(flash).unwrap();
+    // USB controllers may speak MPE, every note gets its own voice
+    let mut voice = MpeSynth::new(4);
+    presets.restore_learn(&mut voice);
 
-    let midi_fut = async {
+    // CONTROL =========================
+    // The control task collects the events of all sources, follows the MIDI clock and handles
+    // the presets. It passes the events on to the audio task through a lock-free `Link`, so
+    // neither MIDI bursts nor flash writes hold up the audio.
+    let (mut control, mut audio) = Link::take();
+    let control_fut = async {
+        let mut clock = MidiClock::new();
         loop {
-            let event = MIDI_EVENTS.receive().await;
-            voice.lock().await.handle_midi(event);
+            // time out now and then to store learned bindings and log the state of the audio task
+            let timeout = Timer::after(Duration::from_millis(100));
+            if let Either::First(event) = select(MIDI_EVENTS.receive(), timeout).await {
+                clock.handle_midi(&event);
+                if let Some(patch) = presets.handle_midi(&event.msg, &control.patch()) {
+                    control.load(patch);
+                }
+                control.send(event).await;
+            }
+            if let Some(bindings) = control.take_learn() {
+                presets.store_learn(bindings);
+            }
+            control.log();
         }
     };
 
-    let gen_fut = async {
-        // Initialize a buffer to generate samples into before writing them to the DMA channel
-        let mut buffer = i2s::new_chunk_buffer();
-        let mut start = 0;
+    // AUDIO ===========================
+    // The APP core only renders audio. It owns the voice, applies the events of the control task,
+    // renders a chunk of frames by calling `voice.generate_block()` and lets the engine push them
+    // to the i2s DMA. The I2S is set up on the APP core, so that its interrupts run there.
+    let (dma, i2s0) = (peripherals.DMA, peripherals.I2S0);
+    let (bclk, dout, ws) = (io.pins.gpio35, io.pins.gpio36, io.pins.gpio37);
+    #[cfg(feature = "line-in")]
+    let din = io.pins.gpio38;
+    let audio_task = move || async move {
+        // Set up DMA (direct memory access) buffers.
+        let dma = Dma::new(dma);
+        let dma_channel = dma
+            .channel0
+            .configure_for_async(false, DmaPriority::Priority9);
+        let i2s = i2s::new_i2s(i2s0, dma_channel);
+
+        // Create the i2s transfer channel and define pins
+        // The channel is used to control data flow to the DMA transaction
+        let i2s_tx: I2sTx<_, _> = i2s
+            .i2s_tx
+            .with_bclk(bclk)
+            .with_dout(dout)
+            .with_ws(ws)
+            .build();
+
+        let tx_buffer = i2s::take_tx_buffer();
+        let transfer = i2s_tx.write_dma_circular_async(tx_buffer).unwrap();
+
+        // LINE IN =========================
+        // With the `line-in` feature an I2S ADC on GPIO38 is read in step with the output. The
+        // input goes through the filter and VCA of `LineIn`, then through the effects together
+        // with the synth. Notes open the VCA when it is gated.
+        #[cfg(feature = "line-in")]
+        let mut capture = {
+            let i2s_rx: I2sRx<_, _> = i2s.i2s_rx.with_din(din).build();
+            let rx_buffer = i2s::take_rx_buffer();
+            Capture::new(I2sSource::new(
+                i2s_rx.read_dma_circular_async(rx_buffer).unwrap(),
+            ))
+        };
+        #[cfg(feature = "line-in")]
+        let mut line_in = LineIn::new();
+        #[cfg(feature = "line-in")]
+        let mut input_frames = [[0.; 2]; i2s::CHUNK_SAMPLES];
+
+        // The effects only run in the audio task, their parameters come from the patch
+        let mut effects = Effects::new(MAX_DELAY);
+
+        let mut engine = Engine::new(UsbTap::new(I2sSink::new(transfer), usb_frames));
+        let mut scheduler = EventScheduler::new();
+        let mut chunks = 0;
         loop {
-            for sample in &mut buffer[start..] {
-                let mut voice = voice.lock().await;
-                let a = voice.generate();
-                let b = (a * i16::MAX as f32) as i16 / 2;
-                *sample = [b, b];
-                drop(voice);
+            #[cfg(feature = "line-in")]
+            let input = &mut input_frames[..engine.frames().len()];
+            #[cfg(feature = "line-in")]
+            capture.pull(input).await;
+
+            // MIDI events are applied on the frame of their time stamp, which is up to a chunk
+            // later than they arrived
+            let now = Instant::now();
+            audio.receive(&mut scheduler, &mut voice);
+            scheduler.render(now, &mut voice, engine.frames(), |voice, msg| {
+                #[cfg(feature = "line-in")]
+                line_in.handle_midi(msg);
+                voice.handle_midi(msg.clone());
+            });
+            audio.publish(&mut voice);
+            let patch = voice.patch();
+
+            // the gate of the line input follows the notes once per chunk
+            #[cfg(feature = "line-in")]
+            {
+                line_in.process_block(input);
+                for (frame, x) in engine.frames().iter_mut().zip(input.iter()) {
+                    frame[0] += x[0];
+                    frame[1] += x[1];
+                }
             }
 
-            // W: written, S: skipped
-            // [ W W W W W W W W W W W W W W W W S S S S ]
-            //                                   ^ written
-            let written = i2s::push(&mut transfer, &buffer).await;
+            effects.update(&patch);
+            engine.master.update(&patch);
+            effects.process_block(engine.frames());
+            engine.push().await;
 
-            // [ S S S S _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ _ ]
-            //           ^ start
-            buffer.rotate_left(written);
-            start = buffer.len() - written;
+            chunks += 1;
+            if chunks == REPORT_CHUNKS {
+                chunks = 0;
+                audio.report(scheduler.take_timing());
+            }
+        }
+    };
+    let software_interrupts = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
+    let _audio_core = start_audio_core(
+        peripherals.CPU_CTRL,
+        software_interrupts.software_interrupt1,
+        audio_task,
+    )
+    .unwrap();
+
+    // Typing on the serial console prints the audio diagnostics, see `handle_console`. The
+    // console is UART0, its RX is on GPIO44.
+    let mut console = UartRx::new_async(peripherals.UART0, io.pins.gpio44).unwrap();
+    let console_fut = async {
+        let mut buf = [0; 16];
+        loop {
+            if let Ok(n) = console.read_async(&mut buf).await {
+                handle_console(&buf[..n]);
+            }
         }
     };
 
-    join3(midi_fut, gen_fut, analog_fut).await;
+    let diagnostics_fut = join(log_diagnostics(DIAGNOSTICS_INTERVAL), console_fut);
+    join4(control_fut, analog_fut, learn_fut, diagnostics_fut).await;
 }
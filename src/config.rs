@@ -0,0 +1,131 @@
+use core::sync::atomic::{AtomicU32, AtomicU8, AtomicUsize, Ordering};
+
+use crate::i2s::CHUNK_SAMPLES;
+
+/// Sample rates the I2S can run at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleRate {
+    Hz32000,
+    Hz44100,
+    Hz48000,
+    Hz96000,
+}
+
+impl SampleRate {
+    pub const fn hz(self) -> u32 {
+        match self {
+            SampleRate::Hz32000 => 32_000,
+            SampleRate::Hz44100 => 44_100,
+            SampleRate::Hz48000 => 48_000,
+            SampleRate::Hz96000 => 96_000,
+        }
+    }
+
+    const fn from_hz(hz: u32) -> Self {
+        match hz {
+            32_000 => SampleRate::Hz32000,
+            44_100 => SampleRate::Hz44100,
+            96_000 => SampleRate::Hz96000,
+            _ => SampleRate::Hz48000,
+        }
+    }
+}
+
+/// Bits per sample on the I2S bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BitDepth {
+    Bits16,
+    /// 24-bit samples in 32-bit slots
+    Bits24,
+    Bits32,
+}
+
+impl BitDepth {
+    pub const fn bits(self) -> u32 {
+        match self {
+            BitDepth::Bits16 => 16,
+            BitDepth::Bits24 => 24,
+            BitDepth::Bits32 => 32,
+        }
+    }
+
+    /// Bytes of a stereo frame in the DMA buffer
+    pub const fn frame_bytes(self) -> usize {
+        match self {
+            BitDepth::Bits16 => 4,
+            BitDepth::Bits24 | BitDepth::Bits32 => 8,
+        }
+    }
+
+    const fn from_bits(bits: u8) -> Self {
+        match bits {
+            24 => BitDepth::Bits24,
+            32 => BitDepth::Bits32,
+            _ => BitDepth::Bits16,
+        }
+    }
+}
+
+/// Format and timing of the audio output
+///
+/// The configuration is applied once at startup with [`AudioConfig::apply`]. The I2S, the engine
+/// and the event scheduler read it with [`AudioConfig::current`], oscillators, filters and
+/// envelopes take the sample rate from [`dt`] or [`sample_rate`] when they are created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AudioConfig {
+    pub sample_rate: SampleRate,
+    pub bit_depth: BitDepth,
+    /// frames per chunk, range = [1, `i2s::CHUNK_SAMPLES`]
+    pub chunk_size: usize,
+}
+
+static SAMPLE_RATE_HZ: AtomicU32 = AtomicU32::new(AudioConfig::DEFAULT.sample_rate.hz());
+static BIT_DEPTH: AtomicU8 = AtomicU8::new(AudioConfig::DEFAULT.bit_depth.bits() as u8);
+static CHUNK_SIZE: AtomicUsize = AtomicUsize::new(AudioConfig::DEFAULT.chunk_size);
+
+impl AudioConfig {
+    pub const DEFAULT: Self = Self {
+        sample_rate: SampleRate::Hz48000,
+        bit_depth: BitDepth::Bits16,
+        chunk_size: CHUNK_SAMPLES,
+    };
+
+    /// Make this the configuration of the audio
+    ///
+    /// Call it first thing in `main`, before the instrument, the effects and the I2S are created,
+    /// they keep the sample rate they were created with.
+    pub fn apply(&self) {
+        assert!(
+            (1..=CHUNK_SAMPLES).contains(&self.chunk_size),
+            "chunk size {} out of range",
+            self.chunk_size
+        );
+        SAMPLE_RATE_HZ.store(self.sample_rate.hz(), Ordering::Relaxed);
+        BIT_DEPTH.store(self.bit_depth.bits() as u8, Ordering::Relaxed);
+        CHUNK_SIZE.store(self.chunk_size, Ordering::Relaxed);
+    }
+
+    /// The configuration from the last [`AudioConfig::apply`], or [`AudioConfig::DEFAULT`]
+    pub fn current() -> Self {
+        Self {
+            sample_rate: SampleRate::from_hz(SAMPLE_RATE_HZ.load(Ordering::Relaxed)),
+            bit_depth: BitDepth::from_bits(BIT_DEPTH.load(Ordering::Relaxed)),
+            chunk_size: CHUNK_SIZE.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Chunks per second, rounded down
+    pub const fn chunks_per_second(&self) -> u32 {
+        self.sample_rate.hz() / self.chunk_size as u32
+    }
+}
+
+/// The configured sample rate in Hz
+pub fn sample_rate() -> f32 {
+    SAMPLE_RATE_HZ.load(Ordering::Relaxed) as f32
+}
+
+/// Time between two samples at the configured sample rate, in seconds
+pub fn dt() -> f32 {
+    1. / sample_rate()
+}
@@ -0,0 +1,59 @@
+use alloc::boxed::Box;
+use core::{future::Future, pin::Pin};
+use esp_hal::{
+    cpu_control::{AppCoreGuard, CpuControl, Error, Stack},
+    interrupt::{software::SoftwareInterrupt, Priority},
+    peripherals::CPU_CTRL,
+};
+use esp_hal_embassy::InterruptExecutor;
+use esp_println::println;
+use static_cell::StaticCell;
+
+/// Stack size of the APP core in bytes, the audio future itself lives on the heap
+const APP_CORE_STACK_SIZE: usize = 8192;
+
+/// Priority of the audio executor
+///
+/// The executor runs in a software interrupt of the APP core. Interrupts of the same or a lower
+/// priority on the APP core wait until the audio task yields.
+pub const AUDIO_PRIORITY: Priority = Priority::Priority2;
+
+static APP_CORE_STACK: StaticCell<Stack<APP_CORE_STACK_SIZE>> = StaticCell::new();
+static AUDIO_EXECUTOR: StaticCell<InterruptExecutor<1>> = StaticCell::new();
+
+#[embassy_executor::task]
+async fn run_audio(audio: Pin<Box<dyn Future<Output = ()> + Send>>) {
+    println!("starting audio on {:?}", esp_hal::get_core());
+    audio.await;
+}
+
+/// Dedicate the APP core, the second core of the ESP32-S3, to the audio
+///
+/// `setup` is called on the APP core and returns the audio future, which runs in an interrupt
+/// executor with [`AUDIO_PRIORITY`]. Peripherals that are set up in `setup`, like the I2S and
+/// its DMA channel, have their interrupts on the APP core as well.
+///
+/// Everything else, i.e. MIDI, USB, the inputs and the presets, stays on the PRO core, which runs
+/// `main`. The cores talk through a [`crate::link::Link`] from [`crate::link::Link::take`].
+///
+/// The APP core stops when the returned guard is dropped, so keep it until the end of `main`.
+pub fn start_audio_core<S, F>(
+    cpu_ctrl: CPU_CTRL,
+    software_interrupt: SoftwareInterrupt<1>,
+    setup: S,
+) -> Result<AppCoreGuard<'static>, Error>
+where
+    S: FnOnce() -> F + Send + 'static,
+    F: Future<Output = ()> + Send + 'static,
+{
+    let mut cpu_control = CpuControl::new(cpu_ctrl);
+    cpu_control.start_app_core(APP_CORE_STACK.init(Stack::new()), move || {
+        let executor = AUDIO_EXECUTOR.init(InterruptExecutor::new(software_interrupt));
+        let spawner = executor.start(AUDIO_PRIORITY);
+        spawner.must_spawn(run_audio(Box::pin(setup())));
+        // the audio runs in the interrupt, there is nothing left to do in thread mode
+        loop {
+            core::hint::spin_loop();
+        }
+    })
+}
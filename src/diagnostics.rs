@@ -0,0 +1,202 @@
+use core::{
+    fmt,
+    sync::atomic::{AtomicU32, Ordering},
+};
+use embassy_time::{Duration, Timer};
+
+use crate::{config, println};
+
+/// CPU clock the load is measured against, the bins run at `CpuClock::max()`
+pub const CPU_HZ: u32 = 240_000_000;
+
+/// Key that prints the [`AUDIO_STATS`] when it is typed on the serial console
+pub const CONSOLE_DIAGNOSTICS_KEY: u8 = b'd';
+
+/// The smoothed load moves by 1/LOAD_SMOOTHING of the difference per chunk, about 0.1 s
+const LOAD_SMOOTHING: f32 = 16.;
+
+/// Current value of the CPU cycle counter, it wraps around every 18 s
+#[cfg(feature = "hw")]
+pub fn cycle_count() -> u32 {
+    esp_hal::xtensa_lx::timer::get_cycle_count()
+}
+
+/// There is no cycle counter on the host, the load reads as 0
+#[cfg(not(feature = "hw"))]
+pub fn cycle_count() -> u32 {
+    0
+}
+
+/// Counters of the audio engine
+///
+/// They are atomics, so the engine can update them on the audio core while the other core reads
+/// them. There is only one engine, so there is one set of counters in [`AUDIO_STATS`].
+pub struct AudioStats {
+    /// smoothed load, as f32 bits
+    load: AtomicU32,
+    /// highest load of a single chunk since the last [`log_diagnostics`] output, as f32 bits
+    peak_load: AtomicU32,
+    chunks: AtomicU32,
+    late: AtomicU32,
+    underruns: AtomicU32,
+    overruns: AtomicU32,
+}
+
+pub static AUDIO_STATS: AudioStats = AudioStats::new();
+
+impl AudioStats {
+    const fn new() -> Self {
+        Self {
+            load: AtomicU32::new(0),
+            peak_load: AtomicU32::new(0),
+            chunks: AtomicU32::new(0),
+            late: AtomicU32::new(0),
+            underruns: AtomicU32::new(0),
+            overruns: AtomicU32::new(0),
+        }
+    }
+
+    /// Record the load of one chunk, i.e. render time / play time
+    ///
+    /// Only the audio task records, so the read-modify-write needs no compare-exchange.
+    fn record(&self, load: f32) {
+        let smoothed = f32::from_bits(self.load.load(Ordering::Relaxed));
+        let smoothed = smoothed + (load - smoothed) / LOAD_SMOOTHING;
+        self.load.store(smoothed.to_bits(), Ordering::Relaxed);
+        let peak = f32::from_bits(self.peak_load.load(Ordering::Relaxed));
+        if load > peak {
+            self.peak_load.store(load.to_bits(), Ordering::Relaxed);
+        }
+        self.chunks.fetch_add(1, Ordering::Relaxed);
+        if load > 1. {
+            self.late.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    pub fn underrun(&self) {
+        self.underruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn overrun(&self) {
+        self.overruns.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Read the counters, the peak load keeps growing until the next [`log_diagnostics`] output
+    pub fn read(&self) -> Diagnostics {
+        Diagnostics {
+            load: f32::from_bits(self.load.load(Ordering::Relaxed)),
+            peak_load: f32::from_bits(self.peak_load.load(Ordering::Relaxed)),
+            chunks: self.chunks.load(Ordering::Relaxed),
+            late: self.late.load(Ordering::Relaxed),
+            underruns: self.underruns.load(Ordering::Relaxed),
+            overruns: self.overruns.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Read the counters and start over with the peak load, only [`log_diagnostics`] does this
+    fn take(&self) -> Diagnostics {
+        let diagnostics = self.read();
+        let peak_load = f32::from_bits(self.peak_load.swap(0, Ordering::Relaxed));
+        Diagnostics {
+            peak_load,
+            ..diagnostics
+        }
+    }
+}
+
+/// A reading of the [`AudioStats`]
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Diagnostics {
+    /// share of the time the audio task spends rendering, 1 is the whole time
+    pub load: f32,
+    /// highest load of a single chunk since the last [`log_diagnostics`] output
+    pub peak_load: f32,
+    /// chunks rendered since the start
+    pub chunks: u32,
+    /// chunks that took longer to render than to play since the start
+    pub late: u32,
+    /// times the DMA ran out of samples since the start
+    pub underruns: u32,
+    /// times the input DMA dropped samples since the start, see [`crate::audio::Capture`]
+    pub overruns: u32,
+}
+
+impl fmt::Display for Diagnostics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "audio: load {:.1} % (peak {:.1} %), {} chunks, {} late, {} underruns, {} overruns",
+            100. * self.load,
+            100. * self.peak_load,
+            self.chunks,
+            self.late,
+            self.underruns,
+            self.overruns
+        )
+    }
+}
+
+/// Measures the load of the audio task with the CPU cycle counter
+///
+/// The render time of a chunk runs from the end of one push to the start of the next one, the
+/// play time follows from the number of frames.
+pub struct LoadMeter {
+    chunk_start: u32,
+    /// CPU cycles per frame at the configured sample rate
+    cycles_per_frame: f32,
+}
+
+impl LoadMeter {
+    pub fn new() -> Self {
+        Self {
+            chunk_start: cycle_count(),
+            cycles_per_frame: CPU_HZ as f32 / config::sample_rate(),
+        }
+    }
+
+    /// Rendering `frames` frames is done
+    pub fn rendered(&mut self, frames: usize) {
+        if frames > 0 {
+            let cycles = cycle_count().wrapping_sub(self.chunk_start);
+            AUDIO_STATS.record(cycles as f32 / (frames as f32 * self.cycles_per_frame));
+        }
+    }
+
+    /// Rendering of the next chunk starts
+    pub fn start(&mut self) {
+        self.chunk_start = cycle_count();
+    }
+}
+
+/// Print the [`AUDIO_STATS`] to the console every `interval`, the peak load starts over each time
+pub async fn log_diagnostics(interval: Duration) {
+    loop {
+        Timer::after(interval).await;
+        println!("{}", AUDIO_STATS.take());
+    }
+}
+
+/// Handle the bytes typed on the serial console, [`CONSOLE_DIAGNOSTICS_KEY`] prints the
+/// [`AUDIO_STATS`]
+pub fn handle_console(bytes: &[u8]) {
+    if bytes.contains(&CONSOLE_DIAGNOSTICS_KEY) {
+        println!("{}", AUDIO_STATS.read());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_take_starts_over_with_the_peak_load() {
+        let stats = AudioStats::new();
+        stats.record(0.5);
+        stats.record(0.25);
+        assert_eq!(stats.read().peak_load, 0.5);
+        assert_eq!(stats.read().peak_load, 0.5);
+        assert_eq!(stats.take().peak_load, 0.5);
+        assert_eq!(stats.read().peak_load, 0.);
+        assert_eq!(stats.read().chunks, 2);
+    }
+}
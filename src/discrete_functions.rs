@@ -1,4 +1,4 @@
-use core::f32::consts::{FRAC_PI_2, TAU};
+use core::f32::consts::{FRAC_PI_2, LN_2, SQRT_2, TAU};
 use micromath::F32Ext;
 
 const TABLE_SIZE: usize = 64;
@@ -80,3 +80,36 @@ pub fn sin(phi: f32) -> f32 {
 pub fn cos(phi: f32) -> f32 {
     sin(phi + FRAC_PI_2)
 }
+
+/// Rational approximation of the hyperbolic tangent
+///
+/// Exact at 0 and smooth up to |x| = 3, where it reaches ±1 and is clamped. Used as a soft
+/// saturation.
+pub fn tanh(x: f32) -> f32 {
+    let x = x.clamp(-3., 3.);
+    let x2 = x * x;
+    x * (27. + x2) / (27. + 9. * x2)
+}
+
+/// Natural logarithm, accurate to about 1e-6
+///
+/// Splits `x` into mantissa and exponent and evaluates the atanh series of the mantissa, which is
+/// precise enough to convert levels to dB. Returns -inf for `x <= 0`.
+pub fn ln(x: f32) -> f32 {
+    if x <= 0. {
+        return f32::NEG_INFINITY;
+    }
+    let bits = x.to_bits();
+    let mut exponent = ((bits >> 23) & 0xFF) as i32 - 127;
+    // mantissa in [1, 2)
+    let mut m = f32::from_bits((bits & 0x007F_FFFF) | 0x3F80_0000);
+    // move it to [sqrt(1/2), sqrt(2)), where the series converges fast
+    if m > SQRT_2 {
+        m *= 0.5;
+        exponent += 1;
+    }
+    let s = (m - 1.) / (m + 1.);
+    let s2 = s * s;
+    let series = 2. * s * (1. + s2 * (1. / 3. + s2 * (1. / 5. + s2 * (1. / 7.))));
+    series + exponent as f32 * LN_2
+}
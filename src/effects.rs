@@ -0,0 +1,163 @@
+pub mod bitcrusher;
+pub mod chorus;
+pub mod delay;
+pub mod distortion;
+pub mod dynamics;
+pub mod phaser;
+pub mod reverb;
+
+pub use bitcrusher::*;
+pub use chorus::*;
+pub use delay::*;
+pub use distortion::*;
+pub use dynamics::*;
+pub use phaser::*;
+pub use reverb::*;
+
+use crate::{
+    filters::traits::Filter,
+    midi::clock::synced_time,
+    params::{ParamId, Patch},
+    stereo::{Stereo, Width},
+};
+
+/// The effect in the modulation slot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ModulationEffect {
+    Off,
+    Chorus,
+    Flanger,
+    Phaser,
+}
+
+impl ModulationEffect {
+    /// Decode the value of [`ParamId::ModType`]
+    pub fn from_param(value: f32) -> Self {
+        match value as u8 {
+            0 => ModulationEffect::Off,
+            1 => ModulationEffect::Chorus,
+            2 => ModulationEffect::Flanger,
+            _ => ModulationEffect::Phaser,
+        }
+    }
+}
+
+/// The effects after the instrument, in signal order
+///
+/// The effect parameters are part of the instrument's [`Patch`], so they are set through the
+/// same CCs, NRPNs and presets as the sound parameters. Pass the patch to [`Effects::update`]
+/// once per rendered chunk.
+///
+/// Chorus, flanger and phaser share one slot and its rate, depth, feedback and mix parameters.
+pub struct Effects {
+    pub distortion: Distortion,
+    pub bitcrusher: Bitcrusher,
+    pub modulation: ModulationEffect,
+    pub chorus: Chorus,
+    pub phaser: Phaser,
+    pub delay: Delay,
+    pub reverb: Reverb,
+    pub width: Width,
+}
+
+impl Effects {
+    /// Create the effects, `max_delay` is the length of the delay line in seconds
+    pub fn new(max_delay: f32) -> Self {
+        Self {
+            distortion: Distortion::new(),
+            bitcrusher: Bitcrusher::new(),
+            modulation: ModulationEffect::Off,
+            chorus: Chorus::new(ChorusMode::Chorus),
+            phaser: Phaser::new(),
+            delay: Delay::new(max_delay),
+            reverb: Reverb::new(),
+            width: Width::new(1.),
+        }
+    }
+
+    /// Apply the effect parameters of `patch` and the current tempo
+    pub fn update(&mut self, patch: &Patch) {
+        self.distortion.shape = Shape::from_param(patch.get(ParamId::DistShape));
+        self.distortion.oversampling = Oversampling::from_param(patch.get(ParamId::Oversampling));
+        self.distortion.set_drive(patch.get(ParamId::DistDrive));
+        self.distortion.set_tone(patch.get(ParamId::DistTone));
+        self.bitcrusher.set_bits(patch.get(ParamId::CrushBits));
+        self.bitcrusher.set_rate(patch.get(ParamId::CrushRate));
+
+        self.update_modulation(patch);
+
+        let time =
+            synced_time(patch.get(ParamId::DelayDivision)).unwrap_or(patch.get(ParamId::DelayTime));
+        self.delay.set_time(time);
+        self.delay.set_feedback(patch.get(ParamId::DelayFeedback));
+        self.delay.set_mix(patch.get(ParamId::DelayMix));
+        self.delay.ping_pong = patch.get(ParamId::DelayPingPong) >= 0.5;
+        self.delay.set_high_cut(patch.get(ParamId::DelayHighCut));
+        self.delay.set_low_cut(patch.get(ParamId::DelayLowCut));
+
+        self.reverb.set_room_size(patch.get(ParamId::ReverbSize));
+        self.reverb.set_damping(patch.get(ParamId::ReverbDamping));
+        self.reverb
+            .set_pre_delay(patch.get(ParamId::ReverbPreDelay));
+        self.reverb.set_mix(patch.get(ParamId::ReverbMix));
+
+        self.width.set_width(patch.get(ParamId::OutputWidth));
+    }
+
+    fn update_modulation(&mut self, patch: &Patch) {
+        let modulation = ModulationEffect::from_param(patch.get(ParamId::ModType));
+        if modulation != self.modulation {
+            // don't play out what is left from the last time the effect was on
+            self.chorus.clear();
+            self.phaser.clear();
+            self.modulation = modulation;
+        }
+
+        // a synced LFO does one cycle per note division
+        let rate = synced_time(patch.get(ParamId::ModDivision))
+            .map_or(patch.get(ParamId::ModRate), |period| 1. / period);
+        let depth = patch.get(ParamId::ModDepth);
+        let feedback = patch.get(ParamId::ModFeedback);
+        let mix = patch.get(ParamId::ModMix);
+        match modulation {
+            ModulationEffect::Off => {}
+            ModulationEffect::Chorus | ModulationEffect::Flanger => {
+                self.chorus.set_mode(match modulation {
+                    ModulationEffect::Flanger => ChorusMode::Flanger,
+                    _ => ChorusMode::Chorus,
+                });
+                self.chorus.set_rate(rate);
+                self.chorus.set_depth(depth);
+                self.chorus.set_feedback(feedback);
+                self.chorus.set_mix(mix);
+            }
+            ModulationEffect::Phaser => {
+                self.phaser.set_rate(rate);
+                self.phaser.set_depth(depth);
+                self.phaser.set_feedback(feedback);
+                self.phaser.set_mix(mix);
+            }
+        }
+    }
+}
+
+impl Filter for Effects {
+    type In = Stereo;
+    type Out = Stereo;
+
+    fn filter(&mut self, x: Stereo) -> Stereo {
+        let x = self.distortion.filter(x);
+        let x = match self.bitcrusher.is_active() {
+            true => self.bitcrusher.filter(x),
+            false => x,
+        };
+        let x = match self.modulation {
+            ModulationEffect::Off => x,
+            ModulationEffect::Chorus | ModulationEffect::Flanger => self.chorus.filter(x),
+            ModulationEffect::Phaser => self.phaser.filter(x),
+        };
+        let x = self.delay.filter(x);
+        let x = self.reverb.filter(x);
+        self.width.filter(x)
+    }
+}
@@ -0,0 +1,65 @@
+use micromath::F32Ext;
+
+use crate::{config::sample_rate, filters::traits::Filter};
+
+/// Resolution at which the bitcrusher stops quantizing
+const MAX_BITS: f32 = 16.;
+
+/// Bit depth and sample rate reducer
+///
+/// The signal is quantized to a number of bits and held for several samples, which brings back
+/// the grit of early samplers. The rate reduction deliberately aliases, so the bitcrusher runs
+/// without oversampling.
+pub struct Bitcrusher {
+    /// quantization levels per unit, `None` = full resolution
+    levels: Option<f32>,
+    /// target rate relative to the sample rate, range = (0, 1]
+    ratio: f32,
+    /// time to the next sample in target samples, range = [0, 1)
+    phase: f32,
+    held: [f32; 2],
+}
+
+impl Bitcrusher {
+    pub fn new() -> Self {
+        Self {
+            levels: None,
+            ratio: 1.,
+            phase: 0.,
+            held: [0.; 2],
+        }
+    }
+
+    /// Set the resolution in bits, range = [1, 16], 16 bits turn quantization off
+    pub fn set_bits(&mut self, bits: f32) {
+        let bits = bits.clamp(1., MAX_BITS);
+        self.levels = (bits < MAX_BITS).then(|| 2f32.powf(bits - 1.));
+    }
+
+    /// Set the sample rate in Hz, the sample rate of the synth or above turns the reduction off
+    pub fn set_rate(&mut self, rate: f32) {
+        self.ratio = (rate / sample_rate()).clamp(1e-3, 1.);
+    }
+
+    /// Whether the bitcrusher changes the signal at all
+    pub fn is_active(&self) -> bool {
+        self.levels.is_some() || self.ratio < 1.
+    }
+}
+
+impl Filter for Bitcrusher {
+    type In = [f32; 2];
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: [f32; 2]) -> [f32; 2] {
+        self.phase += self.ratio;
+        if self.phase >= 1. {
+            self.phase -= 1.;
+            self.held = match self.levels {
+                Some(levels) => x.map(|x| (x * levels).round() / levels),
+                None => x,
+            };
+        }
+        self.held
+    }
+}
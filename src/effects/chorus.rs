@@ -0,0 +1,127 @@
+use alloc::{vec, vec::Vec};
+use core::f32::consts::FRAC_PI_2;
+
+use crate::{
+    config::sample_rate,
+    discrete_functions::sin,
+    filters::traits::Filter,
+    oscillators::{phaser::PhaseGenerator, scales::REFERENCE_FREQ},
+};
+
+/// Length of the delay line in seconds, longest chorus delay plus some headroom
+const MAX_TIME: f32 = 0.025;
+/// Highest absolute feedback
+const MAX_FEEDBACK: f32 = 0.95;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChorusMode {
+    /// 5 to 20 ms delay, thickens the sound
+    Chorus,
+    /// 0.5 to 5 ms delay, sweeps a comb filter through the sound
+    Flanger,
+}
+
+impl ChorusMode {
+    /// Shortest delay and sweep range in seconds
+    fn times(self) -> (f32, f32) {
+        match self {
+            ChorusMode::Chorus => (0.005, 0.015),
+            ChorusMode::Flanger => (0.0005, 0.0045),
+        }
+    }
+}
+
+/// Stereo chorus and flanger
+///
+/// Both mix the input with a copy that runs through a delay line with an LFO-modulated delay time.
+/// The LFO of the right channel is a quarter cycle ahead of the left one, which spreads the
+/// effect across the stereo field.
+pub struct Chorus {
+    buffer: Vec<[f32; 2]>,
+    write: usize,
+    lfo: PhaseGenerator,
+    mode: ChorusMode,
+    /// range = [0, 1]
+    depth: f32,
+    /// range = [-MAX_FEEDBACK, MAX_FEEDBACK]
+    feedback: f32,
+    /// range = [0, 1], 0 = dry
+    mix: f32,
+}
+
+impl Chorus {
+    pub fn new(mode: ChorusMode) -> Self {
+        let mut lfo = PhaseGenerator::new(REFERENCE_FREQ);
+        lfo.set_frequency(0.5);
+        Self {
+            buffer: vec![[0.; 2]; (MAX_TIME * sample_rate()) as usize + 2],
+            write: 0,
+            lfo,
+            mode,
+            depth: 0.5,
+            feedback: 0.,
+            mix: 0.5,
+        }
+    }
+
+    pub fn set_mode(&mut self, mode: ChorusMode) {
+        self.mode = mode;
+    }
+
+    /// Set the LFO rate in Hz
+    pub fn set_rate(&mut self, rate: f32) {
+        self.lfo.set_frequency(rate);
+    }
+
+    /// Set how far the LFO sweeps the delay time, range = [0, 1]
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0., 1.);
+    }
+
+    /// Set the feedback, negative values invert the feedback path
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK);
+    }
+
+    /// Set the wet/dry mix, range = [0, 1]
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0., 1.);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill([0.; 2]);
+    }
+
+    /// Read `channel` of the delay line `time` samples behind the write position
+    fn read(&self, channel: usize, time: f32) -> f32 {
+        let len = self.buffer.len();
+        let i = time as usize;
+        let frac = time - i as f32;
+        let a = self.buffer[(self.write + len - i) % len][channel];
+        let b = self.buffer[(self.write + len - i - 1) % len][channel];
+        a + frac * (b - a)
+    }
+}
+
+impl Filter for Chorus {
+    type In = [f32; 2];
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: [f32; 2]) -> [f32; 2] {
+        let phi = self.lfo.generate();
+        let (min, range) = self.mode.times();
+
+        let mut y = [0.; 2];
+        let mut feed = [0.; 2];
+        for channel in 0..2 {
+            let lfo = 0.5 + 0.5 * sin(phi + channel as f32 * FRAC_PI_2);
+            let time = (min + self.depth * range * lfo) * sample_rate();
+            let wet = self.read(channel, time.max(1.));
+            feed[channel] = x[channel] + self.feedback * wet;
+            y[channel] = x[channel] + self.mix * (wet - x[channel]);
+        }
+        self.buffer[self.write] = feed;
+        self.write = (self.write + 1) % self.buffer.len();
+        y
+    }
+}
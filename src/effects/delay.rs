@@ -0,0 +1,132 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    config::sample_rate,
+    filters::{traits::Filter, BiquadHighPassFilter, BiquadLowPassFilter},
+};
+
+/// Time constant in seconds with which the delay time follows changes
+///
+/// Changing the delay time bends the pitch of the echoes like a tape delay instead of clicking.
+const TIME_SMOOTHING: f32 = 0.05;
+/// Highest feedback, keeps the echoes from building up forever
+const MAX_FEEDBACK: f32 = 0.98;
+
+/// Stereo delay with filtered feedback
+///
+/// The delay time is fractional, the delay line is read with linear interpolation. The feedback
+/// passes through a high-pass and a low-pass filter, so repeated echoes get thinner and darker.
+///
+/// The delay line is allocated on the heap. With the `psram` feature the heap extends into the
+/// external PSRAM, which holds delay lines of several seconds.
+pub struct Delay {
+    buffer: Vec<[f32; 2]>,
+    write: usize,
+    /// current delay time in samples, follows `target`
+    time: f32,
+    /// delay time in samples
+    target: f32,
+    /// range = [0, MAX_FEEDBACK]
+    feedback: f32,
+    /// range = [0, 1], 0 = dry
+    mix: f32,
+    /// the echoes alternate between left and right
+    pub ping_pong: bool,
+    lp: [BiquadLowPassFilter; 2],
+    hp: [BiquadHighPassFilter; 2],
+    smoothing: f32,
+}
+
+impl Delay {
+    /// Create a delay for delay times up to `max_time` seconds
+    pub fn new(max_time: f32) -> Self {
+        let len = (max_time * sample_rate()) as usize + 2;
+        let mut delay = Self {
+            buffer: vec![[0.; 2]; len],
+            write: 0,
+            time: 1.,
+            target: 1.,
+            feedback: 0.,
+            mix: 0.,
+            ping_pong: false,
+            lp: [BiquadLowPassFilter::new(), BiquadLowPassFilter::new()],
+            hp: [BiquadHighPassFilter::new(), BiquadHighPassFilter::new()],
+            smoothing: 1. / (TIME_SMOOTHING * sample_rate()),
+        };
+        delay.set_high_cut(8000.);
+        delay.set_low_cut(60.);
+        delay
+    }
+
+    /// The longest possible delay time in seconds
+    pub fn max_time(&self) -> f32 {
+        (self.buffer.len() - 2) as f32 / sample_rate()
+    }
+
+    /// Set the delay time in seconds, it is clamped to the length of the delay line
+    pub fn set_time(&mut self, time: f32) {
+        let max = (self.buffer.len() - 2) as f32;
+        self.target = (time * sample_rate()).clamp(1., max);
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0., MAX_FEEDBACK);
+    }
+
+    /// Set the wet/dry mix, range = [0, 1]
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0., 1.);
+    }
+
+    /// Set the cutoff of the low-pass in the feedback path
+    pub fn set_high_cut(&mut self, cutoff: f32) {
+        self.lp.iter_mut().for_each(|lp| lp.set_cutoff(cutoff));
+    }
+
+    /// Set the cutoff of the high-pass in the feedback path
+    pub fn set_low_cut(&mut self, cutoff: f32) {
+        self.hp.iter_mut().for_each(|hp| hp.set_cutoff(cutoff));
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill([0.; 2]);
+    }
+
+    /// Read the delay line `time` samples behind the write position
+    fn read(&self, time: f32) -> [f32; 2] {
+        let len = self.buffer.len();
+        let i = time as usize;
+        let frac = time - i as f32;
+        let a = self.buffer[(self.write + len - i) % len];
+        let b = self.buffer[(self.write + len - i - 1) % len];
+        [a[0] + frac * (b[0] - a[0]), a[1] + frac * (b[1] - a[1])]
+    }
+}
+
+impl Filter for Delay {
+    type In = [f32; 2];
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: [f32; 2]) -> [f32; 2] {
+        self.time += (self.target - self.time) * self.smoothing;
+        let wet = self.read(self.time);
+
+        let mut fb = [0.; 2];
+        for (i, fb) in fb.iter_mut().enumerate() {
+            let filtered = self.hp[i].filter(self.lp[i].filter(wet[i]));
+            *fb = self.feedback * filtered;
+        }
+        self.buffer[self.write] = if self.ping_pong {
+            // the input enters on the left, the echoes cross over on every repeat
+            [0.5 * (x[0] + x[1]) + fb[1], fb[0]]
+        } else {
+            [x[0] + fb[0], x[1] + fb[1]]
+        };
+        self.write = (self.write + 1) % self.buffer.len();
+
+        [
+            x[0] + self.mix * (wet[0] - x[0]),
+            x[1] + self.mix * (wet[1] - x[1]),
+        ]
+    }
+}
@@ -0,0 +1,194 @@
+use core::f32::consts::FRAC_PI_2;
+use micromath::F32Ext;
+
+use crate::{
+    discrete_functions::{sin, tanh},
+    filters::{
+        traits::Filter, BiquadLowPassFilter, HalfBandDownsampler, HalfBandUpsampler,
+        HALF_BAND_SHORT, HALF_BAND_STEEP,
+    },
+};
+
+/// Number of points of the transfer curve of [`Shape::Table`], spread evenly over [-1, 1]
+pub const TABLE_SIZE: usize = 33;
+/// Offset of the tube curve, makes it asymmetric
+const TUBE_BIAS: f32 = 0.3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Shape {
+    /// Cubic soft clipper, hard clips above ±1
+    SoftClip,
+    Tanh,
+    /// Reflects the signal back at ±1, gets brighter and brighter with drive
+    Foldback,
+    /// Asymmetric saturation, adds even harmonics
+    Tube,
+    /// Arbitrary transfer curve, see [`Distortion::set_table`]
+    Table,
+}
+
+impl Shape {
+    /// Decode the value of [`crate::params::ParamId::DistShape`], `None` turns distortion off
+    pub fn from_param(value: f32) -> Option<Self> {
+        match value as u8 {
+            0 => None,
+            1 => Some(Shape::SoftClip),
+            2 => Some(Shape::Tanh),
+            3 => Some(Shape::Foldback),
+            4 => Some(Shape::Tube),
+            _ => Some(Shape::Table),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Oversampling {
+    X1,
+    X2,
+    X4,
+}
+
+impl Oversampling {
+    /// Decode the value of [`crate::params::ParamId::Oversampling`]
+    pub fn from_param(value: f32) -> Self {
+        match value as u8 {
+            0 => Oversampling::X1,
+            1 => Oversampling::X2,
+            _ => Oversampling::X4,
+        }
+    }
+}
+
+/// Filters of one channel
+struct Channel {
+    /// 1x to 2x and 2x to 4x
+    up: [HalfBandUpsampler; 2],
+    /// 2x to 1x and 4x to 2x
+    down: [HalfBandDownsampler; 2],
+    tone: BiquadLowPassFilter,
+}
+
+impl Channel {
+    fn new() -> Self {
+        Self {
+            up: [
+                HalfBandUpsampler::new(&HALF_BAND_STEEP),
+                HalfBandUpsampler::new(&HALF_BAND_SHORT),
+            ],
+            down: [
+                HalfBandDownsampler::new(&HALF_BAND_STEEP),
+                HalfBandDownsampler::new(&HALF_BAND_SHORT),
+            ],
+            tone: BiquadLowPassFilter::new(),
+        }
+    }
+}
+
+/// Waveshaping distortion with oversampling
+///
+/// The shaper creates harmonics far above the input frequency, which would alias back into the
+/// audible range. With oversampling the shaper runs at 2x or 4x the sample rate between polyphase
+/// half-band filters, which remove most of the harmonics above the original Nyquist frequency. A
+/// low-pass after the shaper sets the tone.
+pub struct Distortion {
+    /// `None` = bypassed
+    pub shape: Option<Shape>,
+    pub oversampling: Oversampling,
+    /// gain in front of the shaper, range = [1, inf)
+    drive: f32,
+    table: [f32; TABLE_SIZE],
+    channels: [Channel; 2],
+}
+
+impl Distortion {
+    pub fn new() -> Self {
+        // a sine shaper as the default table
+        let table = core::array::from_fn(|i| {
+            let x = 2. * i as f32 / (TABLE_SIZE - 1) as f32 - 1.;
+            sin(FRAC_PI_2 * x)
+        });
+        let mut distortion = Self {
+            shape: None,
+            oversampling: Oversampling::X2,
+            drive: 1.,
+            table,
+            channels: [Channel::new(), Channel::new()],
+        };
+        distortion.set_tone(16000.);
+        distortion
+    }
+
+    /// Set the gain in front of the shaper, range = [1, inf)
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(1.);
+    }
+
+    /// Set the cutoff of the low-pass after the shaper
+    pub fn set_tone(&mut self, cutoff: f32) {
+        self.channels
+            .iter_mut()
+            .for_each(|channel| channel.tone.set_cutoff(cutoff));
+    }
+
+    /// Set the transfer curve of [`Shape::Table`], from input -1 to input 1
+    pub fn set_table(&mut self, table: [f32; TABLE_SIZE]) {
+        self.table = table;
+    }
+
+    fn shape(&self, shape: Shape, x: f32) -> f32 {
+        match shape {
+            Shape::SoftClip => {
+                let x = x.clamp(-1., 1.);
+                1.5 * x - 0.5 * x * x * x
+            }
+            Shape::Tanh => tanh(x),
+            Shape::Foldback => {
+                // triangle wave of the input, with slope 1 around zero
+                let t = 0.25 * (x + 1.);
+                let t = t - t.floor();
+                1. - 4. * (t - 0.5).max(0.5 - t)
+            }
+            Shape::Tube => tanh(x + TUBE_BIAS) - tanh(TUBE_BIAS),
+            Shape::Table => {
+                let d = (x.clamp(-1., 1.) + 1.) * 0.5 * (TABLE_SIZE - 1) as f32;
+                let i = (d as usize).min(TABLE_SIZE - 2);
+                let frac = d - i as f32;
+                self.table[i] + frac * (self.table[i + 1] - self.table[i])
+            }
+        }
+    }
+
+    fn process(&mut self, shape: Shape, channel: usize, x: f32) -> f32 {
+        let x = self.drive * x;
+        let y = match self.oversampling {
+            Oversampling::X1 => self.shape(shape, x),
+            Oversampling::X2 => {
+                let [a, b] = self.channels[channel].up[0].filter(x);
+                let shaped = [self.shape(shape, a), self.shape(shape, b)];
+                self.channels[channel].down[0].filter(shaped)
+            }
+            Oversampling::X4 => {
+                let mut half = [0.; 2];
+                for (y, x) in half.iter_mut().zip(self.channels[channel].up[0].filter(x)) {
+                    let [a, b] = self.channels[channel].up[1].filter(x);
+                    let shaped = [self.shape(shape, a), self.shape(shape, b)];
+                    *y = self.channels[channel].down[1].filter(shaped);
+                }
+                self.channels[channel].down[0].filter(half)
+            }
+        };
+        self.channels[channel].tone.filter(y)
+    }
+}
+
+impl Filter for Distortion {
+    type In = [f32; 2];
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: [f32; 2]) -> [f32; 2] {
+        match self.shape {
+            Some(shape) => [self.process(shape, 0, x[0]), self.process(shape, 1, x[1])],
+            None => x,
+        }
+    }
+}
@@ -0,0 +1,196 @@
+use core::f32::consts::LOG10_E;
+use micromath::F32Ext;
+
+use crate::{config::sample_rate, discrete_functions::ln, filters::traits::Filter, stereo::Stereo};
+
+/// Number of samples the limiter looks ahead, about 0.8 ms
+pub const LOOKAHEAD: usize = 32;
+/// Level below which the compressor's detector counts as silent
+const SILENCE: f32 = 1e-5;
+
+pub fn db_to_gain(db: f32) -> f32 {
+    10f32.powf(db / 20.)
+}
+
+pub fn gain_to_db(gain: f32) -> f32 {
+    20. * LOG10_E * ln(gain)
+}
+
+/// Coefficient of a one-pole smoother that covers about 63 % of a step in `time` seconds
+fn smoothing(time: f32) -> f32 {
+    1. - (-1. / (time * sample_rate()).max(1.)).exp()
+}
+
+/// Peak level of both channels, the channels are linked so the stereo image doesn't shift
+fn peak([l, r]: Stereo) -> f32 {
+    l.max(-l).max(r).max(-r)
+}
+
+/// Feed-forward peak compressor with linked channels
+///
+/// A ratio of 1 turns the compressor off.
+pub struct Compressor {
+    threshold: f32,
+    /// range = [1, inf)
+    ratio: f32,
+    attack: f32,
+    release: f32,
+    /// linear gain after compression
+    makeup: f32,
+    /// detected level
+    envelope: f32,
+    /// gain reduction of the last sample in dB, range = (-inf, 0]
+    reduction: f32,
+}
+
+impl Compressor {
+    pub fn new() -> Self {
+        let mut compressor = Self {
+            threshold: -12.,
+            ratio: 1.,
+            attack: 0.,
+            release: 0.,
+            makeup: 1.,
+            envelope: 0.,
+            reduction: 0.,
+        };
+        compressor.set_attack(0.005);
+        compressor.set_release(0.1);
+        compressor
+    }
+
+    /// Set the threshold in dBFS
+    pub fn set_threshold(&mut self, db: f32) {
+        self.threshold = db;
+    }
+
+    pub fn set_ratio(&mut self, ratio: f32) {
+        self.ratio = ratio.max(1.);
+        if !self.is_active() {
+            self.reduction = 0.;
+        }
+    }
+
+    /// Set the attack time in seconds
+    pub fn set_attack(&mut self, time: f32) {
+        self.attack = smoothing(time);
+    }
+
+    /// Set the release time in seconds
+    pub fn set_release(&mut self, time: f32) {
+        self.release = smoothing(time);
+    }
+
+    /// Set the makeup gain in dB
+    pub fn set_makeup(&mut self, db: f32) {
+        self.makeup = db_to_gain(db);
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.ratio > 1.
+    }
+
+    /// Gain reduction of the last sample in dB, 0 or negative
+    pub fn reduction(&self) -> f32 {
+        self.reduction
+    }
+}
+
+impl Filter for Compressor {
+    type In = Stereo;
+    type Out = Stereo;
+
+    fn filter(&mut self, x: Stereo) -> Stereo {
+        let level = peak(x);
+        let coefficient = match level > self.envelope {
+            true => self.attack,
+            false => self.release,
+        };
+        self.envelope += (level - self.envelope) * coefficient;
+
+        let over = gain_to_db(self.envelope.max(SILENCE)) - self.threshold;
+        self.reduction = match over > 0. {
+            true => -over * (1. - 1. / self.ratio),
+            false => 0.,
+        };
+        let gain = db_to_gain(self.reduction) * self.makeup;
+        x.map(|x| x * gain)
+    }
+}
+
+/// Look-ahead peak limiter with linked channels
+///
+/// The output is delayed by `LOOKAHEAD - 1` samples. The gain needed for every incoming sample is
+/// held as a minimum over the look-ahead window and then averaged over the same window, so the
+/// gain ramps down smoothly and reaches its target just when the peak leaves the delay line.
+/// Peaks never exceed the ceiling.
+pub struct Limiter {
+    ceiling: f32,
+    release: f32,
+    /// gain needed for the incoming samples, with release applied
+    gain: f32,
+    delay: [Stereo; LOOKAHEAD],
+    gains: [f32; LOOKAHEAD],
+    held: [f32; LOOKAHEAD],
+    index: usize,
+    /// gain applied to the last sample, range = (0, 1]
+    applied: f32,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        let mut limiter = Self {
+            ceiling: 1.,
+            release: 0.,
+            gain: 1.,
+            delay: [[0.; 2]; LOOKAHEAD],
+            gains: [1.; LOOKAHEAD],
+            held: [1.; LOOKAHEAD],
+            index: 0,
+            applied: 1.,
+        };
+        limiter.set_ceiling(-0.3);
+        limiter.set_release(0.05);
+        limiter
+    }
+
+    /// Set the highest output level in dBFS
+    pub fn set_ceiling(&mut self, db: f32) {
+        self.ceiling = db_to_gain(db);
+    }
+
+    /// Set the release time in seconds
+    pub fn set_release(&mut self, time: f32) {
+        self.release = smoothing(time);
+    }
+
+    /// Gain reduction of the last sample in dB, 0 or negative
+    pub fn reduction(&self) -> f32 {
+        gain_to_db(self.applied)
+    }
+}
+
+impl Filter for Limiter {
+    type In = Stereo;
+    type Out = Stereo;
+
+    fn filter(&mut self, x: Stereo) -> Stereo {
+        let level = peak(x);
+        let target = match level > self.ceiling {
+            true => self.ceiling / level,
+            false => 1.,
+        };
+        // fall instantly, recover with the release time
+        self.gain = target.min(self.gain + (1. - self.gain) * self.release);
+
+        self.delay[self.index] = x;
+        self.gains[self.index] = self.gain;
+        self.held[self.index] = self.gains.iter().fold(1f32, |a, &b| a.min(b));
+        self.index = (self.index + 1) % LOOKAHEAD;
+
+        self.applied = self.held.iter().sum::<f32>() / LOOKAHEAD as f32;
+        // the oldest sample, the average has fully ramped down to its gain
+        let delayed = self.delay[self.index];
+        delayed.map(|x| x * self.applied)
+    }
+}
@@ -0,0 +1,104 @@
+use core::f32::consts::{FRAC_PI_2, PI};
+use micromath::F32Ext;
+
+use crate::{
+    config::sample_rate,
+    discrete_functions::sin,
+    filters::traits::Filter,
+    oscillators::{phaser::PhaseGenerator, scales::REFERENCE_FREQ},
+};
+
+/// Number of first-order all-pass stages, every two stages make one notch
+const STAGES: usize = 6;
+/// Lowest and highest frequency of the sweep
+const MIN_FREQ: f32 = 100.;
+const MAX_FREQ: f32 = 4000.;
+/// Highest absolute feedback
+const MAX_FEEDBACK: f32 = 0.9;
+
+/// Stereo phaser
+///
+/// A chain of first-order all-pass filters, whose break frequency is swept by an LFO. Mixed with
+/// the input, the phase shift of the chain turns into notches that move through the spectrum. The
+/// sweep is exponential, and the LFO of the right channel is a quarter cycle ahead of the left.
+pub struct Phaser {
+    /// states of the all-pass stages
+    s: [[f32; STAGES]; 2],
+    /// output of the chain, fed back into its input
+    last: [f32; 2],
+    lfo: PhaseGenerator,
+    /// range = [0, 1]
+    depth: f32,
+    /// range = [-MAX_FEEDBACK, MAX_FEEDBACK]
+    feedback: f32,
+    /// range = [0, 1], 0 = dry, 0.5 = deepest notches
+    mix: f32,
+}
+
+impl Phaser {
+    pub fn new() -> Self {
+        let mut lfo = PhaseGenerator::new(REFERENCE_FREQ);
+        lfo.set_frequency(0.5);
+        Self {
+            s: [[0.; STAGES]; 2],
+            last: [0.; 2],
+            lfo,
+            depth: 0.5,
+            feedback: 0.,
+            mix: 0.5,
+        }
+    }
+
+    /// Set the LFO rate in Hz
+    pub fn set_rate(&mut self, rate: f32) {
+        self.lfo.set_frequency(rate);
+    }
+
+    /// Set how far the LFO sweeps the notches, range = [0, 1]
+    pub fn set_depth(&mut self, depth: f32) {
+        self.depth = depth.clamp(0., 1.);
+    }
+
+    /// Set the feedback, negative values invert the feedback path
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(-MAX_FEEDBACK, MAX_FEEDBACK);
+    }
+
+    /// Set the wet/dry mix, range = [0, 1]
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0., 1.);
+    }
+
+    pub fn clear(&mut self) {
+        self.s = [[0.; STAGES]; 2];
+        self.last = [0.; 2];
+    }
+}
+
+impl Filter for Phaser {
+    type In = [f32; 2];
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: [f32; 2]) -> [f32; 2] {
+        let phi = self.lfo.generate();
+
+        let mut y = [0.; 2];
+        for channel in 0..2 {
+            let lfo = 0.5 + 0.5 * sin(phi + channel as f32 * FRAC_PI_2);
+            let freq = MIN_FREQ * (MAX_FREQ / MIN_FREQ).powf(self.depth * lfo);
+            // bilinear all-pass coefficient, tan(w) ~ w is close enough below MAX_FREQ
+            let w = PI * freq / sample_rate();
+            let a = (w - 1.) / (w + 1.);
+
+            let mut v = x[channel] + self.feedback * self.last[channel];
+            for s in &mut self.s[channel] {
+                let out = a * v + *s;
+                *s = v - a * out;
+                v = out;
+            }
+            self.last[channel] = v;
+            y[channel] = x[channel] + self.mix * (v - x[channel]);
+        }
+        y
+    }
+}
@@ -0,0 +1,143 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    config::sample_rate,
+    filters::{traits::Filter, AllPassFilter, CombFilter},
+};
+
+/// Comb filter lengths of Freeverb at 44.1 kHz, the longer half of the original eight
+const COMB_TUNING: [usize; 4] = [1116, 1188, 1277, 1356];
+/// All-pass filter lengths of Freeverb at 44.1 kHz
+const ALLPASS_TUNING: [usize; 2] = [556, 441];
+/// Offset of the right channel's filter lengths, decorrelates the channels
+const STEREO_SPREAD: usize = 23;
+/// Longest pre-delay in seconds
+pub const MAX_PRE_DELAY: f32 = 0.05;
+
+/// Gain of the input into the comb filters, keeps their sum in range
+const INPUT_GAIN: f32 = 0.03;
+/// Gain of the reverberated signal
+const WET_GAIN: f32 = 2.;
+
+/// Scale a Freeverb filter length to the sample rate
+fn tuning(len: usize) -> usize {
+    len * sample_rate() as usize / 44_100
+}
+
+/// One channel of the reverb: parallel comb filters followed by all-pass filters in series
+struct Tank {
+    combs: [CombFilter; COMB_TUNING.len()],
+    allpasses: [AllPassFilter; ALLPASS_TUNING.len()],
+}
+
+impl Tank {
+    fn new(spread: usize) -> Self {
+        Self {
+            combs: COMB_TUNING.map(|len| CombFilter::new(tuning(len + spread))),
+            allpasses: ALLPASS_TUNING.map(|len| AllPassFilter::new(tuning(len + spread))),
+        }
+    }
+}
+
+impl Filter for Tank {
+    type In = f32;
+    type Out = f32;
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let sum = self.combs.iter_mut().map(|comb| comb.filter(x)).sum();
+        self.allpasses.iter_mut().fold(sum, |y, ap| ap.filter(y))
+    }
+}
+
+/// Stereo reverb after Freeverb
+///
+/// The input is summed to mono, delayed by the pre-delay and fed into two tanks of four comb and
+/// two all-pass filters each. The tanks of the left and right channel differ slightly in length,
+/// so the tail is wide even for a mono input. With half the comb filters of the original Freeverb
+/// the reverb takes about 55 kB of memory and fits the CPU budget of a voice.
+pub struct Reverb {
+    pre_delay: Vec<f32>,
+    /// pre-delay in samples
+    pre_delay_time: usize,
+    index: usize,
+    tanks: [Tank; 2],
+    /// range = [0, 1], 0 = dry
+    mix: f32,
+}
+
+impl Reverb {
+    pub fn new() -> Self {
+        let mut reverb = Self {
+            pre_delay: vec![0.; (MAX_PRE_DELAY * sample_rate()) as usize + 1],
+            pre_delay_time: 0,
+            index: 0,
+            tanks: [Tank::new(0), Tank::new(STEREO_SPREAD)],
+            mix: 0.,
+        };
+        reverb.set_room_size(0.5);
+        reverb.set_damping(0.5);
+        reverb
+    }
+
+    /// Set the size of the room, range = [0, 1], longer tails for bigger rooms
+    pub fn set_room_size(&mut self, size: f32) {
+        let feedback = 0.7 + 0.28 * size.clamp(0., 1.);
+        self.combs_mut()
+            .for_each(|comb| comb.set_feedback(feedback));
+    }
+
+    /// Set how fast high frequencies decay, range = [0, 1]
+    pub fn set_damping(&mut self, damping: f32) {
+        let damping = 0.4 * damping.clamp(0., 1.);
+        self.combs_mut().for_each(|comb| comb.set_damping(damping));
+    }
+
+    /// Set the pre-delay in seconds, range = [0, MAX_PRE_DELAY]
+    pub fn set_pre_delay(&mut self, time: f32) {
+        let time = (time * sample_rate()) as usize;
+        self.pre_delay_time = time.min(self.pre_delay.len() - 1);
+    }
+
+    /// Set the wet/dry mix, range = [0, 1]
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0., 1.);
+    }
+
+    pub fn clear(&mut self) {
+        self.pre_delay.fill(0.);
+        for tank in &mut self.tanks {
+            tank.combs.iter_mut().for_each(CombFilter::clear);
+            tank.allpasses.iter_mut().for_each(AllPassFilter::clear);
+        }
+    }
+
+    fn combs_mut(&mut self) -> impl Iterator<Item = &mut CombFilter> {
+        self.tanks.iter_mut().flat_map(|tank| tank.combs.iter_mut())
+    }
+}
+
+impl Filter for Reverb {
+    type In = [f32; 2];
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: [f32; 2]) -> [f32; 2] {
+        if self.mix == 0. {
+            // bypassed, saves the time of the tanks
+            return x;
+        }
+
+        let len = self.pre_delay.len();
+        self.pre_delay[self.index] = INPUT_GAIN * (x[0] + x[1]);
+        let input = self.pre_delay[(self.index + len - self.pre_delay_time) % len];
+        self.index = (self.index + 1) % len;
+
+        let wet = [
+            WET_GAIN * self.tanks[0].filter(input),
+            WET_GAIN * self.tanks[1].filter(input),
+        ];
+        [
+            x[0] + self.mix * (wet[0] - x[0]),
+            x[1] + self.mix * (wet[1] - x[1]),
+        ]
+    }
+}
@@ -1,4 +1,6 @@
-use crate::{filters::traits::Filter, oscillators::phaser::DT};
+use micromath::F32Ext;
+
+use crate::{config, filters::traits::Filter};
 
 pub trait Envelope: Filter {
     fn note_on(&mut self, note: u8, velocity: u8);
@@ -13,6 +15,10 @@ pub struct ADSREnvelope {
     pub release_time: f32,
     stage: ADSRStage,
     level: f32,
+    /// level at the note off, the release falls from it to 0 within the release time
+    release_start: f32,
+    /// time between two samples, from the configured sample rate
+    dt: f32,
 }
 
 #[derive(Debug)]
@@ -32,8 +38,36 @@ impl ADSREnvelope {
             release_time,
             stage: ADSRStage::Idle,
             level: 0.0,
+            release_start: 0.0,
+            dt: config::dt(),
         }
     }
+
+    /// Whether the envelope is silent, i.e. the release of the last note is over
+    pub fn is_idle(&self) -> bool {
+        matches!(self.stage, ADSRStage::Idle)
+    }
+
+    /// Move the level by `step` per sample until it reaches `target`, then switch to `next`
+    ///
+    /// Returns how many samples of `block` were processed.
+    fn ramp(&mut self, block: &mut [f32], step: f32, target: f32, next: ADSRStage) -> usize {
+        for (i, x) in block.iter_mut().enumerate() {
+            self.level += step;
+            let reached = match step > 0. {
+                true => self.level >= target,
+                false => self.level <= target,
+            };
+            if reached {
+                self.level = target;
+                self.stage = next;
+                *x *= target;
+                return i + 1;
+            }
+            *x *= self.level;
+        }
+        block.len()
+    }
 }
 
 impl Envelope for ADSREnvelope {
@@ -42,6 +76,8 @@ impl Envelope for ADSREnvelope {
     }
 
     fn note_off(&mut self, _: u8, _: u8) {
+        // a note released during attack or decay falls from where it is, even with sustain 0
+        self.release_start = self.level;
         self.stage = ADSRStage::Release;
     }
 }
@@ -53,17 +89,17 @@ impl Filter for ADSREnvelope {
     fn filter(&mut self, x: f32) -> f32 {
         match self.stage {
             ADSRStage::Attack => {
-                self.level += DT / self.attack_time;
+                self.level += self.dt / self.attack_time;
                 if self.level >= 1.0 {
                     self.level = 1.0;
                     self.stage = ADSRStage::DecaySustain;
                 }
             }
             ADSRStage::DecaySustain => {
-                self.level -= DT * (self.level - self.sustain_level) / self.decay_time;
+                self.level -= self.dt * (self.level - self.sustain_level) / self.decay_time;
             }
             ADSRStage::Release => {
-                self.level -= DT * self.sustain_level / self.release_time;
+                self.level -= self.dt * self.release_start / self.release_time;
                 if self.level <= 0.0 {
                     self.level = 0.0;
                     self.stage = ADSRStage::Idle;
@@ -73,4 +109,100 @@ impl Filter for ADSREnvelope {
         }
         x * self.level
     }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        // every pass runs one stage with its rate computed once, until the stage changes
+        let mut done = 0;
+        while done < block.len() {
+            let rest = &mut block[done..];
+            done += match self.stage {
+                ADSRStage::Attack => {
+                    let step = self.dt / self.attack_time;
+                    self.ramp(rest, step, 1.0, ADSRStage::DecaySustain)
+                }
+                ADSRStage::DecaySustain => {
+                    let rate = self.dt / self.decay_time;
+                    for x in rest.iter_mut() {
+                        self.level -= rate * (self.level - self.sustain_level);
+                        *x *= self.level;
+                    }
+                    rest.len()
+                }
+                ADSRStage::Release => {
+                    let step = -self.dt * self.release_start / self.release_time;
+                    self.ramp(rest, step, 0.0, ADSRStage::Idle)
+                }
+                ADSRStage::Idle => {
+                    for x in rest.iter_mut() {
+                        *x *= self.level;
+                    }
+                    rest.len()
+                }
+            };
+        }
+    }
+}
+
+/// Tracks the amplitude of a signal, e.g. to turn an audio input into a modulation source
+///
+/// The level moves towards the rectified input, within the attack time when the input is louder
+/// and within the release time when it is quieter. The output is the level, 1 for a full-scale
+/// input.
+#[derive(Debug)]
+pub struct EnvelopeFollower {
+    /// one-pole coefficients of attack and release
+    attack: f32,
+    release: f32,
+    level: f32,
+    /// time between two samples, from the configured sample rate
+    dt: f32,
+}
+
+impl EnvelopeFollower {
+    pub fn new(attack_time: f32, release_time: f32) -> Self {
+        let mut follower = Self {
+            attack: 1.,
+            release: 1.,
+            level: 0.,
+            dt: config::dt(),
+        };
+        follower.set_attack(attack_time);
+        follower.set_release(release_time);
+        follower
+    }
+
+    /// Coefficient that covers about 63 % of a step in `time` seconds
+    fn coefficient(&self, time: f32) -> f32 {
+        1. - (-self.dt / time.max(self.dt)).exp()
+    }
+
+    /// Set the attack time in seconds
+    pub fn set_attack(&mut self, time: f32) {
+        self.attack = self.coefficient(time);
+    }
+
+    /// Set the release time in seconds
+    pub fn set_release(&mut self, time: f32) {
+        self.release = self.coefficient(time);
+    }
+
+    /// The level after the last sample
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+}
+
+impl Filter for EnvelopeFollower {
+    type In = f32;
+    type Out = f32;
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let x = x.max(-x);
+        let coefficient = match x > self.level {
+            true => self.attack,
+            false => self.release,
+        };
+        self.level += coefficient * (x - self.level);
+        self.level
+    }
 }
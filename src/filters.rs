@@ -1,8 +1,14 @@
 pub mod traits;
 
+pub mod comb;
+pub mod halfband;
+pub mod ladder;
 pub mod pass;
 pub mod volume;
 
+pub use comb::*;
+pub use halfband::*;
+pub use ladder::*;
 pub use pass::*;
 pub use volume::*;
 
@@ -0,0 +1,99 @@
+use alloc::{vec, vec::Vec};
+
+use super::traits::Filter;
+
+/// Feedback comb filter with a one-pole low-pass in the feedback loop
+///
+/// The building block of Schroeder and Freeverb style reverbs. The low-pass makes high
+/// frequencies decay faster than low ones, like in a real room.
+pub struct CombFilter {
+    buffer: Vec<f32>,
+    index: usize,
+    /// state of the damping low-pass
+    store: f32,
+    /// range = [0, 1)
+    feedback: f32,
+    /// range = [0, 1], 0 = no damping
+    damping: f32,
+}
+
+impl CombFilter {
+    /// Create a comb filter with a delay of `len` samples
+    pub fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.; len.max(1)],
+            index: 0,
+            store: 0.,
+            feedback: 0.5,
+            damping: 0.,
+        }
+    }
+
+    pub fn set_feedback(&mut self, feedback: f32) {
+        self.feedback = feedback.clamp(0., 0.999);
+    }
+
+    pub fn set_damping(&mut self, damping: f32) {
+        self.damping = damping.clamp(0., 1.);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0.);
+        self.store = 0.;
+    }
+}
+
+impl Filter for CombFilter {
+    type In = f32;
+    type Out = f32;
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let y = self.buffer[self.index];
+        self.store = y + self.damping * (self.store - y);
+        self.buffer[self.index] = x + self.feedback * self.store;
+        self.index = (self.index + 1) % self.buffer.len();
+        y
+    }
+}
+
+/// Schroeder all-pass filter
+///
+/// Passes all frequencies with the same gain but smears the phase, which turns the echoes of the
+/// comb filters into a dense tail. With the usual gain of 0.5 the response is only approximately
+/// flat, which is how Freeverb uses it.
+pub struct AllPassFilter {
+    buffer: Vec<f32>,
+    index: usize,
+    gain: f32,
+}
+
+impl AllPassFilter {
+    /// Create an all-pass filter with a delay of `len` samples
+    pub fn new(len: usize) -> Self {
+        Self {
+            buffer: vec![0.; len.max(1)],
+            index: 0,
+            gain: 0.5,
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.clamp(-0.99, 0.99);
+    }
+
+    pub fn clear(&mut self) {
+        self.buffer.fill(0.);
+    }
+}
+
+impl Filter for AllPassFilter {
+    type In = f32;
+    type Out = f32;
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.index];
+        self.buffer[self.index] = x + self.gain * delayed;
+        self.index = (self.index + 1) % self.buffer.len();
+        delayed - x
+    }
+}
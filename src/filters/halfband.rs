@@ -0,0 +1,111 @@
+use super::traits::Filter;
+
+/// Longest supported half-band filter, in coefficients per side
+const MAX_TAPS: usize = 8;
+
+/// Kaiser-windowed half-band filter with 31 taps
+///
+/// Flat to 0.18 and -60 dB from 0.32 of the oversampled rate, for the first 2x stage.
+pub const HALF_BAND_STEEP: [f32; 8] = [
+    0.314440966,
+    -0.0949999614,
+    0.0465914822,
+    -0.0242523503,
+    0.0119896864,
+    -0.00520900577,
+    0.00176781121,
+    -0.000315605575,
+];
+
+/// Kaiser-windowed half-band filter with 15 taps
+///
+/// Flat to 0.1 and -56 dB from 0.4 of the oversampled rate. Enough for the second stage of 4x
+/// oversampling, where the signal only occupies the lower quarter of the band.
+pub const HALF_BAND_SHORT: [f32; 4] = [0.297828278, -0.056935395, 0.00939858692, -0.00026969468];
+
+/// Sum of the symmetric taps of a half-band filter over `history`, newest sample first
+///
+/// A half-band filter has a centre tap of 0.5 and every other tap is zero, so only the
+/// `coefficients` of the odd taps are stored and multiplied.
+fn symmetric_sum(coefficients: &[f32], history: &[f32; 2 * MAX_TAPS]) -> f32 {
+    let k = coefficients.len();
+    coefficients
+        .iter()
+        .enumerate()
+        .map(|(j, c)| c * (history[k - 1 - j] + history[k + j]))
+        .sum()
+}
+
+/// Shift `x` into `history`, newest sample first
+fn push(history: &mut [f32; 2 * MAX_TAPS], x: f32) {
+    history.copy_within(..2 * MAX_TAPS - 1, 1);
+    history[0] = x;
+}
+
+/// Polyphase half-band interpolator, doubles the sample rate
+///
+/// Every input sample produces two output samples, in time order. The zero-stuffed input is never
+/// formed: the even phase is the centre tap, the odd phase the symmetric taps.
+pub struct HalfBandUpsampler {
+    coefficients: &'static [f32],
+    history: [f32; 2 * MAX_TAPS],
+}
+
+impl HalfBandUpsampler {
+    pub fn new(coefficients: &'static [f32]) -> Self {
+        assert!(coefficients.len() <= MAX_TAPS);
+        Self {
+            coefficients,
+            history: [0.; 2 * MAX_TAPS],
+        }
+    }
+}
+
+impl Filter for HalfBandUpsampler {
+    type In = f32;
+    type Out = [f32; 2];
+
+    fn filter(&mut self, x: f32) -> [f32; 2] {
+        push(&mut self.history, x);
+        let k = self.coefficients.len();
+        [
+            2. * symmetric_sum(self.coefficients, &self.history),
+            self.history[k - 1],
+        ]
+    }
+}
+
+/// Polyphase half-band decimator, halves the sample rate
+///
+/// Takes two input samples in time order and produces one output sample. The filter only runs at
+/// the output rate.
+pub struct HalfBandDownsampler {
+    coefficients: &'static [f32],
+    /// the first sample of every input pair
+    even: [f32; 2 * MAX_TAPS],
+    /// the second sample of every input pair
+    odd: [f32; 2 * MAX_TAPS],
+}
+
+impl HalfBandDownsampler {
+    pub fn new(coefficients: &'static [f32]) -> Self {
+        assert!(coefficients.len() <= MAX_TAPS);
+        Self {
+            coefficients,
+            even: [0.; 2 * MAX_TAPS],
+            odd: [0.; 2 * MAX_TAPS],
+        }
+    }
+}
+
+impl Filter for HalfBandDownsampler {
+    type In = [f32; 2];
+    type Out = f32;
+
+    fn filter(&mut self, [a, b]: [f32; 2]) -> f32 {
+        push(&mut self.even, a);
+        push(&mut self.odd, b);
+        let k = self.coefficients.len();
+        0.5 * self.even[k - 1] + symmetric_sum(self.coefficients, &self.odd)
+    }
+}
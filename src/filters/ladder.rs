@@ -0,0 +1,127 @@
+use core::f32::consts::PI;
+
+use super::traits::Filter;
+use crate::{
+    config::sample_rate,
+    discrete_functions::{cos, sin, tanh},
+    oscillators::scales::REFERENCE_FREQ,
+};
+
+/// Resonance at which the linear filter starts to self-oscillate
+const SELF_OSCILLATION: f32 = 4.;
+/// Highest cutoff relative to the sample rate, the prewarping breaks down at Nyquist
+const MAX_CUTOFF_RATIO: f32 = 0.45;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LadderMode {
+    /// 2-pole output, 12 dB per octave
+    Pole2,
+    /// 4-pole output, 24 dB per octave
+    Pole4,
+}
+
+/// Moog-style 4-pole ladder low-pass filter
+///
+/// Zero-delay-feedback implementation with four trapezoidal one-pole stages. The feedback loop is
+/// solved for the linear filter and the input of the ladder is saturated with a tanh, which keeps
+/// the filter stable at and above self-oscillation.
+///
+/// Setting the cutoff is cheap, so it can be modulated every sample.
+pub struct LadderFilter {
+    /// states of the one-pole stages
+    s: [f32; 4],
+    /// one-pole gain g / (1 + g), where g is the prewarped cutoff
+    gain: f32,
+    cutoff_freq: f32,
+    /// feedback, range = [0, 1], 1 = self-oscillation
+    resonance: f32,
+    /// gain in front of the saturation, range = [1, inf)
+    drive: f32,
+    mode: LadderMode,
+}
+
+impl LadderFilter {
+    pub fn new() -> Self {
+        let mut ladder = Self {
+            s: [0.; 4],
+            gain: 0.,
+            cutoff_freq: REFERENCE_FREQ,
+            resonance: 0.,
+            drive: 1.,
+            mode: LadderMode::Pole4,
+        };
+        ladder.set_cutoff(REFERENCE_FREQ);
+        ladder
+    }
+
+    pub fn set_cutoff(&mut self, cutoff_freq: f32) {
+        let sample_rate = sample_rate();
+        let max = MAX_CUTOFF_RATIO * sample_rate;
+        self.cutoff_freq = cutoff_freq.clamp(1., max);
+        let omega = PI * self.cutoff_freq / sample_rate;
+        let g = sin(omega) / cos(omega);
+        self.gain = g / (1. + g);
+    }
+
+    /// Set the resonance, range = [0, 1.1], self-oscillation starts at 1
+    pub fn set_resonance(&mut self, resonance: f32) {
+        self.resonance = resonance.clamp(0., 1.1);
+    }
+
+    /// Set the gain into the saturation, range = [1, inf)
+    pub fn set_drive(&mut self, drive: f32) {
+        self.drive = drive.max(1.);
+    }
+
+    pub fn set_mode(&mut self, mode: LadderMode) {
+        self.mode = mode;
+    }
+
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff_freq
+    }
+
+    pub fn resonance(&self) -> f32 {
+        self.resonance
+    }
+
+    pub fn reset(&mut self) {
+        self.s = [0.; 4];
+    }
+
+    /// Run one trapezoidal one-pole stage
+    fn stage(&mut self, i: usize, x: f32) -> f32 {
+        let v = (x - self.s[i]) * self.gain;
+        let y = v + self.s[i];
+        self.s[i] = y + v;
+        y
+    }
+}
+
+impl Filter for LadderFilter {
+    type In = f32;
+    type Out = f32;
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let g = self.gain;
+        let k = SELF_OSCILLATION * self.resonance;
+
+        // the output of the last stage is g^4 * u + sigma, solve the feedback loop for u
+        let sigma = self.s.iter().fold(0., |acc, s| acc * g + (1. - g) * s);
+        let g4 = g * g * g * g;
+        // make up for the loss of passband gain at high resonance
+        let x = x * (1. + 0.5 * k);
+        let u = (x - k * sigma) / (1. + k * g4);
+
+        let u = tanh(self.drive * u);
+        let y1 = self.stage(0, u);
+        let y2 = self.stage(1, y1);
+        let y3 = self.stage(2, y2);
+        let y4 = self.stage(3, y3);
+
+        match self.mode {
+            LadderMode::Pole2 => y2,
+            LadderMode::Pole4 => y4,
+        }
+    }
+}
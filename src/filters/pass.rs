@@ -1,10 +1,14 @@
 use super::traits::Filter;
 use crate::{
+    config,
     discrete_functions::{cos, sin},
-    oscillators::{phaser::DT, scales::REFERENCE_FREQ},
+    oscillators::scales::REFERENCE_FREQ,
 };
 use core::f32::consts::TAU;
 
+/// Highest cutoff relative to the sample rate, the biquads get unstable towards Nyquist
+const MAX_CUTOFF_RATIO: f32 = 0.45;
+
 /// Generic filter in Direct Form II
 ///
 /// Generic parameter is Order + 1
@@ -38,8 +42,7 @@ impl DF2Filter {
         self.b2 = b[2] / a[0];
         self.a1 = a[1] / a[0];
         self.a2 = a[2] / a[0];
-        self.z1 = 0.;
-        self.z2 = 0.;
+        // the state is kept, so that the coefficients can be modulated without clicks
     }
 }
 
@@ -48,11 +51,23 @@ impl Filter for DF2Filter {
     type Out = f32;
 
     fn filter(&mut self, x: Self::In) -> Self::Out {
-        let y = self.b0 * x - self.a1 * self.z1 - self.a2 * self.z2;
+        let w = x - self.a1 * self.z1 - self.a2 * self.z2;
+        let y = self.b0 * w + self.b1 * self.z1 + self.b2 * self.z2;
         self.z2 = self.z1;
-        self.z1 = y;
+        self.z1 = w;
+        y
+    }
 
-        y + self.b1 * self.z1 + self.b2 * self.z2
+    fn process_block(&mut self, block: &mut [f32]) {
+        let (mut z1, mut z2) = (self.z1, self.z2);
+        for x in block.iter_mut() {
+            let w = *x - self.a1 * z1 - self.a2 * z2;
+            *x = self.b0 * w + self.b1 * z1 + self.b2 * z2;
+            z2 = z1;
+            z1 = w;
+        }
+        self.z1 = z1;
+        self.z2 = z2;
     }
 }
 
@@ -61,34 +76,47 @@ pub struct BiquadLowPassFilter {
     df2: DF2Filter,
     cutoff_freq: f32,
     q: f32,
+    /// time between two samples, from the configured sample rate
+    dt: f32,
 }
 
 impl BiquadLowPassFilter {
     pub fn new() -> Self {
         let cutoff_freq = REFERENCE_FREQ;
         let q = 0.72;
-        let (a, b) = Self::get_coefficients(cutoff_freq, q);
+        let dt = config::dt();
+        let (a, b) = Self::get_coefficients(cutoff_freq, q, dt);
         BiquadLowPassFilter {
             df2: DF2Filter::new(a, b),
             cutoff_freq,
             q,
+            dt,
         }
     }
 
     pub fn set_cutoff(&mut self, cutoff_freq: f32) {
         self.cutoff_freq = cutoff_freq;
-        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q);
+        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q, self.dt);
         self.df2.set_coefficients(a, b);
     }
 
     pub fn set_q(&mut self, q: f32) {
         self.q = q;
-        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q);
+        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q, self.dt);
         self.df2.set_coefficients(a, b);
     }
 
-    fn get_coefficients(cutoff_freq: f32, q: f32) -> ([f32; 3], [f32; 3]) {
-        let omega = TAU * cutoff_freq * DT;
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff_freq
+    }
+
+    pub fn q(&self) -> f32 {
+        self.q
+    }
+
+    fn get_coefficients(cutoff_freq: f32, q: f32, dt: f32) -> ([f32; 3], [f32; 3]) {
+        let cutoff_freq = cutoff_freq.clamp(1., MAX_CUTOFF_RATIO / dt);
+        let omega = TAU * cutoff_freq * dt;
         let alpha = sin(omega) / (2.0 * q);
         let omega_cos = cos(omega);
 
@@ -110,6 +138,10 @@ impl Filter for BiquadLowPassFilter {
     fn filter(&mut self, x: Self::In) -> Self::Out {
         self.df2.filter(x)
     }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        self.df2.process_block(block);
+    }
 }
 
 // https://pytorch.org/audio/main/_modules/torchaudio/functional/filtering.html#highpass_biquad
@@ -117,34 +149,47 @@ pub struct BiquadHighPassFilter {
     df2: DF2Filter,
     cutoff_freq: f32,
     q: f32,
+    /// time between two samples, from the configured sample rate
+    dt: f32,
 }
 
 impl BiquadHighPassFilter {
     pub fn new() -> Self {
         let cutoff_freq = REFERENCE_FREQ;
         let q = 0.72;
-        let (a, b) = Self::get_coefficients(cutoff_freq, q);
+        let dt = config::dt();
+        let (a, b) = Self::get_coefficients(cutoff_freq, q, dt);
         BiquadHighPassFilter {
             df2: DF2Filter::new(a, b),
             cutoff_freq,
             q,
+            dt,
         }
     }
 
     pub fn set_cutoff(&mut self, cutoff_freq: f32) {
         self.cutoff_freq = cutoff_freq;
-        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q);
+        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q, self.dt);
         self.df2.set_coefficients(a, b);
     }
 
     pub fn set_q(&mut self, q: f32) {
         self.q = q;
-        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q);
+        let (a, b) = Self::get_coefficients(self.cutoff_freq, self.q, self.dt);
         self.df2.set_coefficients(a, b);
     }
 
-    fn get_coefficients(cutoff_freq: f32, q: f32) -> ([f32; 3], [f32; 3]) {
-        let omega = TAU * cutoff_freq * DT;
+    pub fn cutoff(&self) -> f32 {
+        self.cutoff_freq
+    }
+
+    pub fn q(&self) -> f32 {
+        self.q
+    }
+
+    fn get_coefficients(cutoff_freq: f32, q: f32, dt: f32) -> ([f32; 3], [f32; 3]) {
+        let cutoff_freq = cutoff_freq.clamp(1., MAX_CUTOFF_RATIO / dt);
+        let omega = TAU * cutoff_freq * dt;
         let alpha = sin(omega) / (2.0 * q);
         let omega_cos = cos(omega);
 
@@ -166,4 +211,43 @@ impl Filter for BiquadHighPassFilter {
     fn filter(&mut self, x: Self::In) -> Self::Out {
         self.df2.filter(x)
     }
+
+    fn process_block(&mut self, block: &mut [f32]) {
+        self.df2.process_block(block);
+    }
+}
+
+/// First-order DC blocking filter
+///
+/// A high-pass with a zero at DC and a pole just inside the unit circle. It removes offsets, e.g.
+/// from asymmetric waveshaping, without touching the audible range.
+#[derive(Debug)]
+pub struct DcBlocker {
+    /// pole radius, range = (0, 1)
+    r: f32,
+    x1: f32,
+    y1: f32,
+}
+
+impl DcBlocker {
+    /// Create a DC blocker with a -3 dB point at `cutoff_freq`
+    pub fn new(cutoff_freq: f32) -> Self {
+        Self {
+            r: 1. - TAU * cutoff_freq * config::dt(),
+            x1: 0.,
+            y1: 0.,
+        }
+    }
+}
+
+impl Filter for DcBlocker {
+    type In = f32;
+    type Out = f32;
+
+    fn filter(&mut self, x: f32) -> f32 {
+        let y = x - self.x1 + self.r * self.y1;
+        self.x1 = x;
+        self.y1 = y;
+        y
+    }
 }
@@ -2,6 +2,21 @@ pub trait Filter: Sized {
     type In;
     type Out;
     fn filter(&mut self, x: Self::In) -> Self::Out;
+
+    /// Filter a block of samples in place
+    ///
+    /// The default filters sample by sample. Filters override it with a loop that keeps their
+    /// state in registers.
+    fn process_block(&mut self, block: &mut [Self::In])
+    where
+        Self::In: Copy,
+        Self::Out: Into<Self::In>,
+    {
+        for x in block.iter_mut() {
+            *x = self.filter(*x).into();
+        }
+    }
+
     fn chain<Other: Filter<In = Self::Out>>(self, other: Other) -> ChainFilter<Self, Other> {
         ChainFilter {
             f1: self,
@@ -0,0 +1,205 @@
+use esp_hal::{
+    dma::{Channel, DmaChannelConvert, DmaDescriptor, DmaError, ReadBuffer, WriteBuffer},
+    i2s::{
+        asynch::{I2sReadDmaTransferAsync, I2sWriteDmaTransferAsync},
+        DataFormat, Error, I2s, RegisterAccess, Standard,
+    },
+    peripheral::Peripheral,
+    prelude::*,
+    Mode,
+};
+use static_cell::StaticCell;
+
+use super::{Sample, CHUNK_SAMPLES, DMA_NUM};
+use crate::{
+    audio::{AudioSink, AudioSource, Overrun, Underrun},
+    config::{AudioConfig, BitDepth},
+};
+
+/// Bytes of a chunk at the largest bit depth
+const CHUNK_BYTES: usize = BitDepth::Bits32.frame_bytes() * CHUNK_SAMPLES;
+const DMA_BYTES: usize = DMA_NUM * CHUNK_BYTES;
+static TX_BUFFER: StaticCell<[u8; DMA_BYTES]> = StaticCell::new();
+static RX_BUFFER: StaticCell<[u8; DMA_BYTES]> = StaticCell::new();
+static TX_DESCRIPTORS: StaticCell<[DmaDescriptor; DMA_NUM]> = StaticCell::new();
+static RX_DESCRIPTORS: StaticCell<[DmaDescriptor; DMA_NUM]> = StaticCell::new();
+
+fn data_format(bit_depth: BitDepth) -> DataFormat {
+    match bit_depth {
+        BitDepth::Bits16 => DataFormat::Data16Channel16,
+        BitDepth::Bits24 => DataFormat::Data32Channel24,
+        BitDepth::Bits32 => DataFormat::Data32Channel32,
+    }
+}
+
+/// Set up the I2S with the sample rate and bit depth of the [`AudioConfig`]
+///
+/// The I2S is full-duplex: TX and RX run on the same clock, so an input read with an
+/// [`I2sSource`] stays in step with the output.
+pub fn new_i2s<'d, I, CH, DmaMode>(
+    i2s: impl Peripheral<P = I> + 'd,
+    dma_channel: Channel<'d, CH, DmaMode>,
+) -> I2s<'d, I, DmaMode>
+where
+    I: RegisterAccess,
+    CH: DmaChannelConvert<I::Dma>,
+    DmaMode: Mode,
+{
+    let config = AudioConfig::current();
+    // initialize descriptors
+    // see convenience macro [dma_buffer_chunk_size!] from esp-hal for reference
+    let tx_descriptors = TX_DESCRIPTORS.init([DmaDescriptor::EMPTY; DMA_NUM]);
+    let rx_descriptors = RX_DESCRIPTORS.init([DmaDescriptor::EMPTY; DMA_NUM]);
+    I2s::new(
+        i2s,
+        Standard::Philips,
+        data_format(config.bit_depth),
+        config.sample_rate.hz().Hz(),
+        dma_channel,
+        rx_descriptors,
+        tx_descriptors,
+    )
+}
+
+/// Bytes of the circular DMA buffers, [`DMA_NUM`] chunks of the configured size and bit depth
+fn dma_bytes() -> usize {
+    let config = AudioConfig::current();
+    DMA_NUM * config.chunk_size * config.bit_depth.frame_bytes()
+}
+
+/// The circular DMA buffer of the output
+pub fn take_tx_buffer() -> &'static mut [u8] {
+    &mut TX_BUFFER.init([0u8; DMA_BYTES])[..dma_bytes()]
+}
+
+/// The circular DMA buffer of the input
+pub fn take_rx_buffer() -> &'static mut [u8] {
+    &mut RX_BUFFER.init([0u8; DMA_BYTES])[..dma_bytes()]
+}
+
+/// [`AudioSink`] on the circular DMA transfer of the I2S
+///
+/// Packs the samples into the bit depth of the [`AudioConfig`]: 16 bits, or 32-bit slots with
+/// 24 bits right-justified or 32 bits.
+pub struct I2sSink<T> {
+    transfer: T,
+    bit_depth: BitDepth,
+    bytes: [u8; CHUNK_BYTES],
+}
+
+impl<T> I2sSink<T> {
+    pub fn new(transfer: T) -> Self {
+        Self {
+            transfer,
+            bit_depth: AudioConfig::current().bit_depth,
+            bytes: [0; CHUNK_BYTES],
+        }
+    }
+
+    /// Pack `samples` into the byte buffer, returns the number of bytes
+    fn pack(&mut self, samples: &[Sample]) -> usize {
+        let values = samples.iter().flatten();
+        match self.bit_depth {
+            BitDepth::Bits16 => {
+                for (bytes, x) in self.bytes.chunks_exact_mut(2).zip(values) {
+                    bytes.copy_from_slice(&((x >> 16) as i16).to_le_bytes());
+                }
+            }
+            BitDepth::Bits24 => {
+                for (bytes, x) in self.bytes.chunks_exact_mut(4).zip(values) {
+                    bytes.copy_from_slice(&(x >> 8).to_le_bytes());
+                }
+            }
+            BitDepth::Bits32 => {
+                for (bytes, x) in self.bytes.chunks_exact_mut(4).zip(values) {
+                    bytes.copy_from_slice(&x.to_le_bytes());
+                }
+            }
+        }
+        samples.len() * self.bit_depth.frame_bytes()
+    }
+}
+
+impl<'d, T, TXBUF> AudioSink for I2sSink<I2sWriteDmaTransferAsync<'d, T, TXBUF>>
+where
+    T: RegisterAccess,
+    TXBUF: ReadBuffer,
+{
+    /// Copy samples into the circular DMA buffer
+    ///
+    /// When the DMA has caught up with the writer it plays stale data, which is reported as an
+    /// [`Underrun`].
+    async fn write(&mut self, samples: &[Sample]) -> Result<usize, Underrun> {
+        let len = self.pack(samples);
+        match self.transfer.push(&self.bytes[..len]).await {
+            Ok(written_bytes) => Ok(written_bytes / self.bit_depth.frame_bytes()),
+            Err(Error::DmaError(DmaError::Late)) => Err(Underrun),
+            Err(e) => panic!("I2S transfer failed: {:?}", e),
+        }
+    }
+}
+
+/// [`AudioSource`] on the circular DMA transfer of the I2S input
+///
+/// Unpacks the bit depth of the [`AudioConfig`] into left-justified samples, the reverse of
+/// [`I2sSink`].
+pub struct I2sSource<T> {
+    transfer: T,
+    bit_depth: BitDepth,
+    bytes: [u8; CHUNK_BYTES],
+}
+
+impl<T> I2sSource<T> {
+    pub fn new(transfer: T) -> Self {
+        Self {
+            transfer,
+            bit_depth: AudioConfig::current().bit_depth,
+            bytes: [0; CHUNK_BYTES],
+        }
+    }
+
+    /// Unpack the first `len` bytes of the byte buffer into `samples`
+    fn unpack(&self, len: usize, samples: &mut [Sample]) {
+        let values = samples.iter_mut().flatten();
+        match self.bit_depth {
+            BitDepth::Bits16 => {
+                for (x, bytes) in values.zip(self.bytes[..len].chunks_exact(2)) {
+                    *x = (i16::from_le_bytes([bytes[0], bytes[1]]) as i32) << 16;
+                }
+            }
+            BitDepth::Bits24 => {
+                for (x, bytes) in values.zip(self.bytes[..len].chunks_exact(4)) {
+                    *x = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) << 8;
+                }
+            }
+            BitDepth::Bits32 => {
+                for (x, bytes) in values.zip(self.bytes[..len].chunks_exact(4)) {
+                    *x = i32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+                }
+            }
+        }
+    }
+}
+
+impl<'d, T, RXBUF> AudioSource for I2sSource<I2sReadDmaTransferAsync<'d, T, RXBUF>>
+where
+    T: RegisterAccess,
+    RXBUF: WriteBuffer,
+{
+    /// Copy samples out of the circular DMA buffer
+    ///
+    /// When the reader falls behind, the DMA overwrites samples that were not read, which is
+    /// reported as an [`Overrun`].
+    async fn read(&mut self, samples: &mut [Sample]) -> Result<usize, Overrun> {
+        let frame_bytes = self.bit_depth.frame_bytes();
+        let len = samples.len().min(CHUNK_SAMPLES) * frame_bytes;
+        match self.transfer.pop(&mut self.bytes[..len]).await {
+            Ok(read_bytes) => {
+                self.unpack(read_bytes, samples);
+                Ok(read_bytes / frame_bytes)
+            }
+            Err(Error::DmaError(DmaError::Late)) => Err(Overrun),
+            Err(e) => panic!("I2S transfer failed: {:?}", e),
+        }
+    }
+}
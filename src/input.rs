@@ -1,19 +1,22 @@
 use alloc::{boxed::Box, vec, vec::Vec};
-use embassy_time::{Duration, Ticker};
+use embassy_time::{Duration, Ticker, Timer};
 use esp_hal::{
     analog::adc::{
         Adc, AdcCalScheme, AdcChannel, AdcConfig, AdcPin, Attenuation, CalibrationAccess,
     },
-    gpio::AnalogPin,
+    gpio::{AnalogPin, Input, InputPin},
     peripheral::Peripheral,
     peripherals::ADC1,
 };
 
 use crate::{
     filters::traits::{Filter, Filterable},
-    midi::send_control,
+    midi::{control::MAX_14BIT, send_control, send_control_value, ControlMode},
 };
 
+/// Maximum value of a 12-bit ADC measurement
+const ADC_MAX: u16 = 0xFFF;
+
 /// Simple first order
 #[derive(Clone)]
 pub struct AdcFilter {
@@ -143,10 +146,13 @@ where
     }
 }
 
+/// Analog inputs and the MIDI controls they send
+pub type AnalogInputs<'d> = Vec<(Box<dyn AdcPoll<ADC1> + 'd>, ControlMode)>;
+
 pub struct AnalogInputBuilder<'d> {
     adc_config: AdcConfig<ADC1>,
     input_config: AnalogInputConfig,
-    inputs: Vec<(Box<dyn AdcPoll<ADC1> + 'd>, u8)>,
+    inputs: AnalogInputs<'d>,
 }
 
 /// Builder type for analog inputs
@@ -162,24 +168,33 @@ impl<'d> AnalogInputBuilder<'d> {
         }
     }
 
-    /// Add a new analog input
+    /// Add a new analog input that sends 7-bit control changes
     ///
     /// `pin` - GPIO pin
     /// `control` - MIDI control code
-    pub fn add(mut self, pin: impl AdcChannel + AnalogPin + 'd, control: u8) -> Self {
+    pub fn add(self, pin: impl AdcChannel + AnalogPin + 'd, control: u8) -> Self {
+        self.add_with_mode(pin, ControlMode::Cc(control))
+    }
+
+    /// Add a new analog input
+    ///
+    /// `pin` - GPIO pin
+    /// `mode` - MIDI control code and resolution, e.g. 14-bit control change or NRPN
+    pub fn add_with_mode(
+        mut self,
+        pin: impl AdcChannel + AnalogPin + 'd,
+        mode: ControlMode,
+    ) -> Self {
         type AdcCal = esp_hal::analog::adc::AdcCalBasic<ADC1>;
         let input = AnalogInput::<_, _, AdcCal>::new(&mut self.adc_config, pin, self.input_config);
-        self.inputs.push((Box::new(input), control));
+        self.inputs.push((Box::new(input), mode));
         self
     }
 
     /// Build the adc instance and all inputs
     ///
     /// `adc1` - the ADC1 peripheral
-    pub fn build(
-        self,
-        adc1: impl Peripheral<P = ADC1> + 'd,
-    ) -> (Adc<'d, ADC1>, Vec<(Box<dyn AdcPoll<ADC1> + 'd>, u8)>) {
+    pub fn build(self, adc1: impl Peripheral<P = ADC1> + 'd) -> (Adc<'d, ADC1>, AnalogInputs<'d>) {
         let adc = Adc::new(adc1, self.adc_config);
         (adc, self.inputs)
     }
@@ -194,17 +209,37 @@ impl<'d> AnalogInputBuilder<'d> {
 /// ...
 pub async fn produce_midi_on_analog_input_change<'d>(
     adc: &mut Adc<'d, ADC1>,
-    inputs: &mut [(Box<dyn AdcPoll<ADC1> + 'd>, u8)],
+    inputs: &mut [(Box<dyn AdcPoll<ADC1> + 'd>, ControlMode)],
     poll_interval: Duration,
 ) {
     let mut tick = Ticker::every(poll_interval);
     loop {
-        for (input, control) in inputs.iter_mut() {
+        for (input, mode) in inputs.iter_mut() {
             tick.next().await;
             if let Some(v) = input.poll(adc) {
-                let value = (v / 32) as u8;
-                send_control(*control, value).await;
+                // scale the 12-bit measurement to the 14-bit controller range
+                let value = (v.min(ADC_MAX) as u32 * MAX_14BIT as u32 / ADC_MAX as u32) as u16;
+                send_control_value(*mode, value).await;
             }
         }
     }
 }
+
+const DEBOUNCE: Duration = Duration::from_millis(20);
+
+/// Sends control change `control` with value 127 when a button is pressed and 0 when it is
+/// released.
+///
+/// The button is expected to pull the input low while pressed, e.g. the BOOT button.
+pub async fn produce_midi_on_button_press<P: InputPin>(button: &mut Input<'_, P>, control: u8) {
+    loop {
+        button.wait_for_falling_edge().await;
+        Timer::after(DEBOUNCE).await;
+        if button.is_low() {
+            send_control(control, 127).await;
+            button.wait_for_high().await;
+            Timer::after(DEBOUNCE).await;
+            send_control(control, 0).await;
+        }
+    }
+}
@@ -1,14 +1,43 @@
 #![no_std]
-#![no_main]
 #![feature(const_fn_floating_point_arithmetic)]
 
 extern crate alloc;
 
+pub mod audio;
+pub mod config;
+#[cfg(feature = "hw")]
+pub mod cores;
+pub mod diagnostics;
 pub mod discrete_functions;
+pub mod effects;
 pub mod envelope;
 pub mod filters;
 pub mod i2s;
+pub mod line_in;
+pub mod link;
+pub mod master;
 pub mod oscillators;
+pub mod params;
+pub mod preset;
+pub mod stereo;
+pub mod sync;
+pub mod usb_audio;
 pub mod voice;
 pub mod midi;
+pub mod mpe;
+#[cfg(feature = "hw")]
 pub mod input;
+
+// Without the `hw` feature only the hardware-independent logic builds, e.g. for the unit tests
+// on the host. There is no console then, `println!` only checks its arguments.
+#[cfg(feature = "hw")]
+pub(crate) use esp_println::println;
+
+#[cfg(not(feature = "hw"))]
+macro_rules! println {
+    ($($arg:tt)*) => {{
+        let _ = format_args!($($arg)*);
+    }};
+}
+#[cfg(not(feature = "hw"))]
+pub(crate) use println;
@@ -0,0 +1,132 @@
+use micromath::F32Ext;
+use midi_msg::{ChannelVoiceMsg, MidiMsg};
+
+use crate::{
+    config,
+    envelope::{ADSREnvelope, Envelope, EnvelopeFollower},
+    filters::{traits::Filter, BiquadLowPassFilter},
+    stereo::{self, DualMono, Stereo},
+    voice::BLOCK_SIZE,
+};
+
+/// Processing of an external audio input, e.g. a line-level signal from an I2S ADC
+///
+/// The input runs through a low-pass filter and a VCA, afterwards it can go through the effects
+/// like the synth. The VCA stays open, or an ADSR envelope gates it with the notes of any
+/// channel, which turns the synth into a keyed gate for the input.
+///
+/// An envelope follower tracks the level of the input. It opens the filter for loud signals,
+/// like an envelope filter, and [`LineIn::level`] makes it available as a modulation source,
+/// e.g. as a sidechain for the synth.
+pub struct LineIn {
+    lp: DualMono<BiquadLowPassFilter>,
+    pub env: ADSREnvelope,
+    pub follower: EnvelopeFollower,
+    /// linear input gain
+    gain: f32,
+    /// cutoff without modulation in Hz
+    cutoff: f32,
+    /// octaves the follower moves the cutoff at full level
+    follow_depth: f32,
+    /// whether the envelope gates the input, otherwise the VCA stays open
+    gated: bool,
+    /// notes that hold the gate open
+    held: u32,
+}
+
+impl LineIn {
+    pub fn new() -> Self {
+        let mut line_in = Self {
+            lp: DualMono::new(BiquadLowPassFilter::new),
+            env: ADSREnvelope::new(0.005, 0.1, 1., 0.1),
+            follower: EnvelopeFollower::new(0.005, 0.1),
+            gain: 1.,
+            cutoff: 0.,
+            follow_depth: 0.,
+            gated: false,
+            held: 0,
+        };
+        // wide open, the biquad keeps the cutoff below Nyquist
+        line_in.set_cutoff(config::sample_rate() / 2.);
+        line_in
+    }
+
+    /// Set the linear gain of the input
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain;
+    }
+
+    /// Set the cutoff of the low-pass filter in Hz, without the follower
+    pub fn set_cutoff(&mut self, cutoff: f32) {
+        self.cutoff = cutoff;
+    }
+
+    pub fn set_q(&mut self, q: f32) {
+        self.lp.for_each(|lp| lp.set_q(q));
+    }
+
+    /// Set how many octaves the follower opens the filter at full level, 0 turns it off
+    pub fn set_follow_depth(&mut self, octaves: f32) {
+        self.follow_depth = octaves;
+    }
+
+    /// Gate the input with the envelope, or leave the VCA open
+    pub fn set_gated(&mut self, gated: bool) {
+        self.gated = gated;
+    }
+
+    /// Level of the input from the envelope follower, 1 = full scale
+    pub fn level(&self) -> f32 {
+        self.follower.level()
+    }
+
+    /// Open and close the gate with the notes of any channel
+    pub fn handle_midi(&mut self, msg: &MidiMsg) {
+        if let MidiMsg::ChannelVoice { msg, .. } = msg {
+            match *msg {
+                ChannelVoiceMsg::NoteOn { note, velocity } if velocity > 0 => {
+                    if self.held == 0 {
+                        self.env.note_on(note, velocity);
+                    }
+                    self.held += 1;
+                }
+                ChannelVoiceMsg::NoteOn { note, velocity }
+                | ChannelVoiceMsg::NoteOff { note, velocity } => {
+                    self.held = self.held.saturating_sub(1);
+                    if self.held == 0 {
+                        self.env.note_off(note, velocity);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Process the input frames in `block` in place
+    ///
+    /// The block is processed in pieces of [`BLOCK_SIZE`] frames, the follower moves the cutoff
+    /// once per piece.
+    pub fn process_block(&mut self, block: &mut [Stereo]) {
+        let mut gains = [1.; BLOCK_SIZE];
+        for chunk in block.chunks_mut(BLOCK_SIZE) {
+            for frame in chunk.iter_mut() {
+                *frame = frame.map(|x| self.gain * x);
+                self.follower.filter(stereo::mono(*frame));
+            }
+
+            let octaves = self.follow_depth * self.follower.level();
+            let cutoff = self.cutoff * 2f32.powf(octaves);
+            self.lp.for_each(|lp| lp.set_cutoff(cutoff));
+            self.lp.process_block(chunk);
+
+            if self.gated {
+                let gains = &mut gains[..chunk.len()];
+                gains.fill(1.);
+                self.env.process_block(gains);
+                for (frame, gain) in chunk.iter_mut().zip(gains.iter()) {
+                    *frame = frame.map(|x| gain * x);
+                }
+            }
+        }
+    }
+}
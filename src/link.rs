@@ -0,0 +1,184 @@
+use embassy_time::{Duration, Timer};
+use static_cell::StaticCell;
+
+use crate::{
+    midi::{
+        learn::{LearnBytes, LearnState},
+        schedule::{EventScheduler, Timing},
+        TimedMidi,
+    },
+    params::Patch,
+    println,
+    sync::{Consumer, Producer, Queue, Snapshot, SnapshotReader, SnapshotWriter},
+    voice::Instrument,
+};
+
+/// Capacity of the event queue, plus one
+pub const EVENT_QUEUE: usize = 64;
+
+static LINK: StaticCell<Link> = StaticCell::new();
+
+/// Channels between the control task and the audio task
+///
+/// The audio task owns the instrument. The control task collects the MIDI events, handles the
+/// presets and the flash, and talks to the audio task only through lock-free queues and
+/// snapshots, so a burst of events never blocks rendering and both tasks can run on different
+/// cores. [`Link::split`] returns the [`ControlLink`] and [`AudioLink`] ends.
+pub struct Link {
+    events: Queue<TimedMidi, EVENT_QUEUE>,
+    /// patches to load, from a Program Change
+    loads: Snapshot<Patch>,
+    /// current patch of the instrument
+    patch: Snapshot<Patch>,
+    /// MIDI learn bindings of the instrument, published when they change
+    learn: Snapshot<LearnBytes>,
+    /// MIDI learn state of the instrument, published when it changes
+    learn_state: Snapshot<LearnState>,
+    /// MIDI timing of the last report period
+    timing: Snapshot<Timing>,
+}
+
+impl Link {
+    pub fn new() -> Self {
+        Self {
+            events: Queue::new(),
+            loads: Snapshot::new(Patch::DEFAULT),
+            patch: Snapshot::new(Patch::DEFAULT),
+            learn: Snapshot::new(LearnBytes::EMPTY),
+            learn_state: Snapshot::new(LearnState::Idle),
+            timing: Snapshot::new(Timing::default()),
+        }
+    }
+
+    /// Create the link in static memory, for tasks that run on different cores
+    ///
+    /// Panics when it is called a second time.
+    pub fn take() -> (ControlLink<'static>, AudioLink<'static>) {
+        LINK.init(Link::new()).split()
+    }
+
+    pub fn split(&mut self) -> (ControlLink<'_>, AudioLink<'_>) {
+        let (event_tx, event_rx) = self.events.split();
+        let (load_tx, load_rx) = self.loads.split();
+        let (patch_tx, patch_rx) = self.patch.split();
+        let (learn_tx, learn_rx) = self.learn.split();
+        let (learn_state_tx, learn_state_rx) = self.learn_state.split();
+        let (timing_tx, timing_rx) = self.timing.split();
+        (
+            ControlLink {
+                events: event_tx,
+                loads: load_tx,
+                patch: patch_rx,
+                learn: learn_rx,
+                learn_state: learn_state_rx,
+                timing: timing_rx,
+            },
+            AudioLink {
+                events: event_rx,
+                loads: load_rx,
+                patch: patch_tx,
+                learn: learn_tx,
+                learn_state: learn_state_tx,
+                last_learn_state: LearnState::Idle,
+                timing: timing_tx,
+            },
+        )
+    }
+}
+
+/// Control task end of a [`Link`]
+pub struct ControlLink<'a> {
+    events: Producer<'a, TimedMidi, EVENT_QUEUE>,
+    loads: SnapshotWriter<'a, Patch>,
+    patch: SnapshotReader<'a, Patch>,
+    learn: SnapshotReader<'a, LearnBytes>,
+    learn_state: SnapshotReader<'a, LearnState>,
+    timing: SnapshotReader<'a, Timing>,
+}
+
+impl ControlLink<'_> {
+    /// Pass `event` on to the audio task, waits while the queue is full
+    pub async fn send(&mut self, mut event: TimedMidi) {
+        while let Err(rejected) = self.events.push(event) {
+            event = rejected;
+            Timer::after(Duration::from_millis(1)).await;
+        }
+    }
+
+    /// Let the instrument load `patch` at the start of the next chunk
+    pub fn load(&mut self, patch: Patch) {
+        self.loads.publish(patch);
+    }
+
+    /// The patch of the instrument at the end of the last chunk
+    pub fn patch(&mut self) -> Patch {
+        self.patch.update();
+        *self.patch.get()
+    }
+
+    /// The MIDI learn bindings, if they changed since the last call
+    pub fn take_learn(&mut self) -> Option<&[u8]> {
+        match self.learn.update() {
+            true => Some(self.learn.get().as_slice()),
+            false => None,
+        }
+    }
+
+    /// Print what the audio task published since the last call to the console
+    ///
+    /// The audio task never prints itself, the console would hold up rendering.
+    pub fn log(&mut self) {
+        if self.learn_state.update() {
+            println!("{}", self.learn_state.get());
+        }
+        if self.timing.update() && self.timing.get().events > 0 {
+            println!("{}", self.timing.get());
+        }
+    }
+}
+
+/// Audio task end of a [`Link`]
+pub struct AudioLink<'a> {
+    events: Consumer<'a, TimedMidi, EVENT_QUEUE>,
+    loads: SnapshotReader<'a, Patch>,
+    patch: SnapshotWriter<'a, Patch>,
+    learn: SnapshotWriter<'a, LearnBytes>,
+    learn_state: SnapshotWriter<'a, LearnState>,
+    /// the learn state that was published last
+    last_learn_state: LearnState,
+    timing: SnapshotWriter<'a, Timing>,
+}
+
+impl AudioLink<'_> {
+    /// Queue the events of the control task and load a new patch, call it before each chunk
+    pub fn receive<I: Instrument>(&mut self, scheduler: &mut EventScheduler, instrument: &mut I) {
+        if self.loads.update() {
+            instrument.load_patch(self.loads.get());
+        }
+        // events that do not fit into the scheduler wait in the queue for the next chunk
+        while !scheduler.is_full() {
+            match self.events.pop() {
+                Some(event) => scheduler.push(event).unwrap(),
+                None => break,
+            }
+        }
+    }
+
+    /// Publish the state of `instrument` for the control task, call it after each chunk
+    pub fn publish<I: Instrument>(&mut self, instrument: &mut I) {
+        self.patch.publish(instrument.patch());
+        if instrument.learn().take_changed() {
+            self.learn.publish(instrument.learn().to_bytes());
+        }
+        let learn_state = instrument.learn().state();
+        if learn_state != self.last_learn_state {
+            self.last_learn_state = learn_state;
+            self.learn_state.publish(learn_state);
+        }
+    }
+
+    /// Publish the MIDI timing of a report period, e.g. [`EventScheduler::take_timing`]
+    pub fn report(&mut self, timing: Timing) {
+        self.timing.publish(timing);
+    }
+}
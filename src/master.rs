@@ -0,0 +1,111 @@
+use micromath::F32Ext;
+
+use crate::{
+    config::AudioConfig,
+    effects::{db_to_gain, Compressor, Limiter},
+    filters::{traits::Filter, DcBlocker},
+    i2s::Sample,
+    oscillators::{traits::Generator, Noise},
+    params::{ParamId, Patch},
+    stereo::{DualMono, Stereo},
+};
+
+/// -3 dB point of the DC blocker
+const DC_CUTOFF: f32 = 10.;
+
+/// Levels of the master bus since the last call to [`MasterBus::take_metrics`]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Metrics {
+    /// highest absolute level after the master gain, 1 = full scale
+    pub peak: f32,
+    /// largest gain reduction of compressor and limiter together in dB, 0 or negative
+    pub reduction: f32,
+    /// samples that were out of range when quantizing
+    pub clips: u32,
+}
+
+/// The last stage before the output
+///
+/// Applies the master gain, removes DC, compresses (optional) and limits the signal, then
+/// quantizes it to the configured bit depth with TPDF dither. The samples are left-justified in
+/// 32 bits, see [`Sample`]. The limiter keeps the signal below full scale, so
+/// clipping is only reported when something bypasses it.
+pub struct MasterBus {
+    /// linear master gain
+    gain: f32,
+    dc: DualMono<DcBlocker>,
+    pub compressor: Compressor,
+    pub limiter: Limiter,
+    dither: Noise,
+    /// full scale at the configured bit depth, in LSB
+    full_scale: f32,
+    /// shift from the bit depth to the left-justified sample
+    shift: u32,
+    metrics: Metrics,
+}
+
+impl MasterBus {
+    pub fn new() -> Self {
+        let bits = AudioConfig::current().bit_depth.bits();
+        Self {
+            gain: 1.,
+            dc: DualMono::new(|| DcBlocker::new(DC_CUTOFF)),
+            compressor: Compressor::new(),
+            limiter: Limiter::new(),
+            dither: Noise::new(0xD1_7E8),
+            full_scale: ((1u64 << (bits - 1)) - 1) as f32,
+            shift: 32 - bits,
+            metrics: Metrics::default(),
+        }
+    }
+
+    /// Apply the master parameters of `patch`
+    pub fn update(&mut self, patch: &Patch) {
+        self.gain = db_to_gain(patch.get(ParamId::MasterGain));
+        self.compressor
+            .set_threshold(patch.get(ParamId::CompThreshold));
+        self.compressor.set_ratio(patch.get(ParamId::CompRatio));
+        self.compressor.set_attack(patch.get(ParamId::CompAttack));
+        self.compressor.set_release(patch.get(ParamId::CompRelease));
+        self.compressor.set_makeup(patch.get(ParamId::CompMakeup));
+    }
+
+    /// Return the metrics collected since the last call and start over
+    pub fn take_metrics(&mut self) -> Metrics {
+        core::mem::take(&mut self.metrics)
+    }
+
+    /// Scale `x` to the bit depth with triangular dither of ±1 LSB
+    fn quantize(&mut self, x: f32) -> i32 {
+        let dither = 0.5 * (self.dither.generate() + self.dither.generate());
+        let y = x * self.full_scale + dither;
+        if !(-self.full_scale - 1. ..=self.full_scale).contains(&y) {
+            self.metrics.clips += 1;
+        }
+        // the cast saturates, the clamp keeps the shift from overflowing
+        let y = (y.round() as i32).clamp(-(self.full_scale as i32) - 1, self.full_scale as i32);
+        y << self.shift
+    }
+}
+
+impl Filter for MasterBus {
+    type In = Stereo;
+    type Out = Sample;
+
+    fn filter(&mut self, x: Stereo) -> Sample {
+        let x = self.dc.filter(x.map(|x| x * self.gain));
+        let [l, r] = x;
+        self.metrics.peak = self.metrics.peak.max(l).max(-l).max(r).max(-r);
+
+        let x = match self.compressor.is_active() {
+            true => self.compressor.filter(x),
+            false => x,
+        };
+        let [l, r] = self.limiter.filter(x);
+
+        let reduction = self.compressor.reduction() + self.limiter.reduction();
+        self.metrics.reduction = self.metrics.reduction.min(reduction);
+
+        [self.quantize(l), self.quantize(r)]
+    }
+}
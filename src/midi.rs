@@ -1,10 +1,38 @@
 use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel};
+use embassy_time::Instant;
 
+pub mod clock;
+pub mod control;
+pub mod learn;
+pub mod schedule;
 pub mod send;
 pub mod sequencer;
+pub mod sysex;
+#[cfg(feature = "hw")]
 pub mod usb;
 
 use midi_msg::MidiMsg;
 pub use send::*;
 
-pub static MIDI_EVENTS: Channel<CriticalSectionRawMutex, MidiMsg, 4> = Channel::new();
+/// A MIDI message with the time it was produced or received
+#[derive(Debug, Clone)]
+pub struct TimedMidi {
+    pub msg: MidiMsg,
+    pub time: Instant,
+}
+
+impl TimedMidi {
+    /// Stamp `msg` with the current time
+    pub fn now(msg: MidiMsg) -> Self {
+        Self {
+            msg,
+            time: Instant::now(),
+        }
+    }
+}
+
+/// Incoming events for the audio task, which drains the channel once per chunk
+///
+/// It holds the events of a few chunks, senders wait when it is full. Their time stamps are taken
+/// before, so waiting does not delay the event.
+pub static MIDI_EVENTS: Channel<CriticalSectionRawMutex, TimedMidi, 32> = Channel::new();
@@ -0,0 +1,96 @@
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use embassy_time::{Duration, Instant};
+use midi_msg::{MidiMsg, SystemRealTimeMsg};
+
+use super::TimedMidi;
+
+/// MIDI clock pulses per quarter note
+pub const CLOCKS_PER_BEAT: u8 = 24;
+
+/// Duration of a quarter note in microseconds, 120 BPM until a tempo source sets it
+static BEAT_MICROS: AtomicU32 = AtomicU32::new(500_000);
+
+/// Duration of a quarter note, as set by the sequencer or the MIDI clock
+pub fn beat_duration() -> Duration {
+    Duration::from_micros(BEAT_MICROS.load(Ordering::Relaxed) as u64)
+}
+
+pub fn set_beat_duration(duration: Duration) {
+    BEAT_MICROS.store(duration.as_micros() as u32, Ordering::Relaxed);
+}
+
+/// Note lengths for tempo-synced times, in quarter notes
+///
+/// Index 0 turns tempo sync off, so a division parameter can select free-running times as well.
+pub const NOTE_DIVISIONS: [(&str, f32); 14] = [
+    ("free", 0.),
+    ("1/32", 0.125),
+    ("1/16T", 1. / 6.),
+    ("1/16", 0.25),
+    ("1/8T", 1. / 3.),
+    ("1/16D", 0.375),
+    ("1/8", 0.5),
+    ("1/4T", 2. / 3.),
+    ("1/8D", 0.75),
+    ("1/4", 1.),
+    ("1/2T", 4. / 3.),
+    ("1/4D", 1.5),
+    ("1/2", 2.),
+    ("1/1", 4.),
+];
+
+/// Duration in seconds of the note division selected by a division parameter
+///
+/// Returns `None` for "free", i.e. when tempo sync is off.
+pub fn synced_time(division: f32) -> Option<f32> {
+    let beats = NOTE_DIVISIONS.get(division as usize)?.1;
+    (beats > 0.).then(|| beats * beat_duration().as_micros() as f32 * 1e-6)
+}
+
+/// Derives the tempo from incoming MIDI clock messages
+///
+/// The duration of a beat is measured over the last 24 pulses, which averages out the jitter of
+/// single pulses.
+pub struct MidiClock {
+    /// time of the first pulse of the current beat
+    beat_start: Option<Instant>,
+    pulses: u8,
+}
+
+impl MidiClock {
+    pub fn new() -> Self {
+        Self {
+            beat_start: None,
+            pulses: 0,
+        }
+    }
+
+    /// Measure the tempo with the time stamps of the clock pulses
+    pub fn handle_midi(&mut self, event: &TimedMidi) {
+        let MidiMsg::SystemRealTime { msg } = &event.msg else {
+            return;
+        };
+        match msg {
+            SystemRealTimeMsg::TimingClock => {
+                let now = event.time;
+                match self.beat_start {
+                    Some(start) => {
+                        self.pulses += 1;
+                        if self.pulses == CLOCKS_PER_BEAT {
+                            set_beat_duration(now - start);
+                            self.beat_start = Some(now);
+                            self.pulses = 0;
+                        }
+                    }
+                    None => self.beat_start = Some(now),
+                }
+            }
+            SystemRealTimeMsg::Start | SystemRealTimeMsg::Stop => {
+                self.beat_start = None;
+                self.pulses = 0;
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use super::learn::{LearnAction, MidiLearn};
+use crate::params::{param_for_nrpn, params_for_cc, ParamId};
+use micromath::F32Ext;
+
+/// Control change that selects the MSB of a non-registered parameter number
+pub const NRPN_MSB: u8 = 99;
+pub const NRPN_LSB: u8 = 98;
+/// Control change that selects the MSB of a registered parameter number
+pub const RPN_MSB: u8 = 101;
+pub const RPN_LSB: u8 = 100;
+pub const DATA_ENTRY_MSB: u8 = 6;
+pub const DATA_ENTRY_LSB: u8 = 38;
+pub const DATA_INCREMENT: u8 = 96;
+pub const DATA_DECREMENT: u8 = 97;
+const BANK_SELECT_MSB: u8 = 0;
+
+/// Maximum of a 14-bit value
+pub const MAX_14BIT: u16 = 0x3FFF;
+/// RPN number that deselects the current parameter
+pub const RPN_NULL: u16 = MAX_14BIT;
+
+/// A control value reassembled from one or more control changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlEvent {
+    /// Plain 7-bit control change
+    Cc { control: u8, value: u8 },
+    /// 14-bit control change, MSB on `control` (1-31) and LSB on `control + 32`
+    Cc14 { control: u8, value: u16 },
+    /// Non-registered parameter number with 14-bit data
+    Nrpn { param: u16, value: u16 },
+    /// Registered parameter number with 14-bit data
+    Rpn { param: u16, value: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+    None,
+    Nrpn,
+    Rpn,
+}
+
+/// Reassembles 14-bit control changes, NRPNs and RPNs of one MIDI channel
+///
+/// An MSB is applied right away, with the missing LSB bits filled in so that 7-bit controllers
+/// still cover the full range. A following LSB refines the value, for the controls that the
+/// registry binds to parameters. Data entry, increment and decrement change the selected (N)RPN,
+/// the latter by one 7-bit step, and are ignored while none is selected.
+pub struct ControlDecoder {
+    msb: [u8; 32],
+    selection: Selection,
+    param_msb: u8,
+    param_lsb: u8,
+    data: u16,
+}
+
+impl ControlDecoder {
+    pub fn new() -> Self {
+        Self {
+            msb: [0; 32],
+            selection: Selection::None,
+            param_msb: 0x7F,
+            param_lsb: 0x7F,
+            data: 0,
+        }
+    }
+
+    /// Feed a control change, returns the resulting control value if there is one
+    pub fn decode(&mut self, control: u8, value: u8) -> Option<ControlEvent> {
+        match control {
+            NRPN_MSB | NRPN_LSB | RPN_MSB | RPN_LSB => {
+                match control {
+                    NRPN_MSB | RPN_MSB => self.param_msb = value,
+                    _ => self.param_lsb = value,
+                }
+                self.selection = match control {
+                    NRPN_MSB | NRPN_LSB => Selection::Nrpn,
+                    _ if self.param() == RPN_NULL => Selection::None,
+                    _ => Selection::Rpn,
+                };
+                // the data of the previous parameter must not leak into the new one
+                self.data = 0;
+                None
+            }
+            // data entry only applies while a parameter is selected
+            DATA_ENTRY_MSB | DATA_ENTRY_LSB | DATA_INCREMENT | DATA_DECREMENT
+                if self.selection == Selection::None =>
+            {
+                None
+            }
+            DATA_ENTRY_MSB => {
+                self.data = from_msb(value);
+                self.data_event()
+            }
+            DATA_ENTRY_LSB => {
+                self.data = (self.data & !0x7F) | value as u16;
+                self.data_event()
+            }
+            DATA_INCREMENT => {
+                self.data = (self.data + 0x80).min(MAX_14BIT);
+                self.data_event()
+            }
+            DATA_DECREMENT => {
+                self.data = self.data.saturating_sub(0x80);
+                self.data_event()
+            }
+            BANK_SELECT_MSB => Some(ControlEvent::Cc { control, value }),
+            1..=31 => {
+                self.msb[control as usize] = value;
+                Some(ControlEvent::Cc14 {
+                    control,
+                    value: from_msb(value),
+                })
+            }
+            33..=63 if has_14bit_lsb(control - 32) => {
+                let control = control - 32;
+                let msb = self.msb[control as usize] as u16;
+                Some(ControlEvent::Cc14 {
+                    control,
+                    value: msb << 7 | value as u16,
+                })
+            }
+            _ => Some(ControlEvent::Cc { control, value }),
+        }
+    }
+
+    fn param(&self) -> u16 {
+        (self.param_msb as u16) << 7 | self.param_lsb as u16
+    }
+
+    fn data_event(&self) -> Option<ControlEvent> {
+        let (param, value) = (self.param(), self.data);
+        match self.selection {
+            Selection::None => None,
+            Selection::Nrpn => Some(ControlEvent::Nrpn { param, value }),
+            Selection::Rpn => Some(ControlEvent::Rpn { param, value }),
+        }
+    }
+}
+
+/// Whether the LSB of control change `msb`, on `msb + 32`, refines a parameter of the registry
+///
+/// Other controls in 33-63 are plain 7-bit control changes.
+fn has_14bit_lsb(msb: u8) -> bool {
+    params_for_cc(msb).next().is_some()
+}
+
+/// Expand a 7-bit MSB to 14 bit, such that 0 maps to 0 and 127 maps to [`MAX_14BIT`]
+fn from_msb(msb: u8) -> u16 {
+    (msb as u16) << 7 | msb as u16
+}
+
+/// Split a 14-bit value into MSB and LSB
+pub fn split_14bit(value: u16) -> (u8, u8) {
+    let value = value.min(MAX_14BIT);
+    ((value >> 7) as u8, (value & 0x7F) as u8)
+}
+
+/// Parameters addressed by a control value
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParamTargets {
+    /// A single parameter, e.g. learned or addressed by NRPN
+    Single(ParamId),
+    /// All parameters the registry binds to this control change
+    Control(u8),
+}
+
+impl ParamTargets {
+    pub fn iter(self) -> impl Iterator<Item = ParamId> {
+        let (single, control) = match self {
+            ParamTargets::Single(id) => (Some(id), None),
+            ParamTargets::Control(control) => (None, Some(control)),
+        };
+        single
+            .into_iter()
+            .chain(control.into_iter().flat_map(params_for_cc))
+    }
+}
+
+/// Result of [`ParamControls::handle_cc`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ControlTarget {
+    /// Set the targeted parameters to the normalized value in [0, 1]
+    Params(ParamTargets, f32),
+    /// A registered parameter that is not a sound parameter, left to the caller
+    Rpn { param: u16, value: u16 },
+}
+
+/// Routes the control changes of one channel to parameters
+///
+/// Combines the [`ControlDecoder`] for high-resolution values with the [`MidiLearn`] bindings and
+/// the parameter registry.
+pub struct ParamControls {
+    decoder: ControlDecoder,
+    pub learn: MidiLearn,
+}
+
+impl ParamControls {
+    pub fn new() -> Self {
+        Self {
+            decoder: ControlDecoder::new(),
+            learn: MidiLearn::new(),
+        }
+    }
+
+    pub fn handle_cc(&mut self, control: u8, value: u8) -> Option<ControlTarget> {
+        match self.decoder.decode(control, value)? {
+            ControlEvent::Cc { control, value } => self.resolve(control, value as f32 / 127.),
+            ControlEvent::Cc14 { control, value } => {
+                self.resolve(control, value as f32 / MAX_14BIT as f32)
+            }
+            ControlEvent::Nrpn { param, value } => {
+                let id = param_for_nrpn(param)?;
+                self.learn.touch(id);
+                let x = value as f32 / MAX_14BIT as f32;
+                Some(ControlTarget::Params(ParamTargets::Single(id), x))
+            }
+            ControlEvent::Rpn { param, value } => Some(ControlTarget::Rpn { param, value }),
+        }
+    }
+
+    fn resolve(&mut self, control: u8, x: f32) -> Option<ControlTarget> {
+        let value = (x * 127.).round() as u8;
+        let targets = match self.learn.handle_cc(control, value) {
+            LearnAction::Consumed => return None,
+            LearnAction::Apply(id) => ParamTargets::Single(id),
+            LearnAction::Default => ParamTargets::Control(control),
+        };
+        Some(ControlTarget::Params(targets, x))
+    }
+}
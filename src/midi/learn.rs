@@ -0,0 +1,239 @@
+use core::fmt;
+
+use crate::params::{params_for_cc, ParamId};
+
+/// Control change that toggles learn mode, e.g. sent by a button
+pub const LEARN_CC: u8 = 103;
+/// Control change that clears learned bindings
+///
+/// In learn mode, after a source was moved, only the binding of that source is cleared.
+/// Otherwise all learned bindings are cleared.
+pub const CLEAR_CC: u8 = 104;
+/// Maximum number of learned bindings
+pub const MAX_BINDINGS: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearnState {
+    Idle,
+    /// Waiting for a knob to be moved or a control change to arrive
+    WaitingForSource,
+    /// Waiting for a parameter to be touched, which then gets bound to `control`
+    WaitingForTarget {
+        control: u8,
+    },
+}
+
+impl fmt::Display for LearnState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LearnState::Idle => write!(f, "learn: idle"),
+            LearnState::WaitingForSource => write!(f, "learn: move a control"),
+            LearnState::WaitingForTarget { control } => {
+                write!(f, "learn: CC {}, touch a parameter", control)
+            }
+        }
+    }
+}
+
+/// What the caller should do with a control change after [`MidiLearn::handle_cc`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LearnAction {
+    /// The control change was consumed by learn mode
+    Consumed,
+    /// Apply the control change to the learned parameter
+    Apply(ParamId),
+    /// Apply the control change to the parameters bound by the registry
+    Default,
+}
+
+/// Bindings serialized by [`MidiLearn::to_bytes`], in a fixed-size buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LearnBytes {
+    bytes: [u8; 2 * MAX_BINDINGS],
+    len: usize,
+}
+
+impl LearnBytes {
+    pub const EMPTY: Self = Self {
+        bytes: [0; 2 * MAX_BINDINGS],
+        len: 0,
+    };
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+/// Bindings of MIDI control changes to parameters that can be learned at runtime
+///
+/// A learned binding takes precedence over the binding in the parameter registry. Analog inputs
+/// send control changes as well, so knobs can be rebound the same way as external controllers.
+///
+/// Learning works in two steps: after entering learn mode (see [`LEARN_CC`]), the next moved
+/// knob or incoming control change is the source. The next touched parameter becomes its target.
+/// A parameter is touched by sending a control change that is bound to it, or through
+/// [`MidiLearn::touch`].
+pub struct MidiLearn {
+    bindings: [Option<ParamId>; 128],
+    state: LearnState,
+    learn_pressed: bool,
+    clear_pressed: bool,
+    changed: bool,
+}
+
+impl MidiLearn {
+    pub fn new() -> Self {
+        Self {
+            bindings: [None; 128],
+            state: LearnState::Idle,
+            learn_pressed: false,
+            clear_pressed: false,
+            changed: false,
+        }
+    }
+
+    pub fn state(&self) -> LearnState {
+        self.state
+    }
+
+    pub fn start(&mut self) {
+        self.state = LearnState::WaitingForSource;
+    }
+
+    pub fn cancel(&mut self) {
+        self.state = LearnState::Idle;
+    }
+
+    /// The parameter learned for control change `control`
+    pub fn binding(&self, control: u8) -> Option<ParamId> {
+        self.bindings.get(control as usize).copied().flatten()
+    }
+
+    /// Bind `control` to `id`, unless [`MAX_BINDINGS`] other controls are bound already
+    pub fn bind(&mut self, control: u8, id: ParamId) {
+        let count = self.bindings.iter().flatten().count();
+        if self.binding(control).is_none() && count >= MAX_BINDINGS {
+            return;
+        }
+        if let Some(binding) = self.bindings.get_mut(control as usize) {
+            *binding = Some(id);
+            self.changed = true;
+        }
+    }
+
+    pub fn unbind(&mut self, control: u8) {
+        if let Some(binding) = self.bindings.get_mut(control as usize) {
+            self.changed |= binding.take().is_some();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.bindings = [None; 128];
+        self.changed = true;
+    }
+
+    /// Touch parameter `id`, which binds it to the source when learn mode is waiting for a target
+    pub fn touch(&mut self, id: ParamId) {
+        if let LearnState::WaitingForTarget { control } = self.state {
+            self.bind(control, id);
+            self.state = LearnState::Idle;
+        }
+    }
+
+    /// Returns true once after the bindings were changed, e.g. to persist them
+    pub fn take_changed(&mut self) -> bool {
+        core::mem::take(&mut self.changed)
+    }
+
+    /// Process an incoming control change and decide how it should be applied
+    pub fn handle_cc(&mut self, control: u8, value: u8) -> LearnAction {
+        match control {
+            LEARN_CC => {
+                let pressed = value >= 64;
+                if pressed && !self.learn_pressed {
+                    match self.state {
+                        LearnState::Idle => self.start(),
+                        _ => self.cancel(),
+                    }
+                }
+                self.learn_pressed = pressed;
+                LearnAction::Consumed
+            }
+            CLEAR_CC => {
+                let pressed = value >= 64;
+                if pressed && !self.clear_pressed {
+                    match self.state {
+                        LearnState::WaitingForTarget { control } => {
+                            self.unbind(control);
+                            self.state = LearnState::Idle;
+                        }
+                        _ => self.clear(),
+                    }
+                }
+                self.clear_pressed = pressed;
+                LearnAction::Consumed
+            }
+            _ => match self.state {
+                LearnState::Idle => self.resolve(control),
+                LearnState::WaitingForSource => {
+                    self.state = LearnState::WaitingForTarget { control };
+                    LearnAction::Consumed
+                }
+                LearnState::WaitingForTarget { control: source } if source == control => {
+                    LearnAction::Consumed
+                }
+                LearnState::WaitingForTarget { .. } => {
+                    let action = self.resolve(control);
+                    let touched = match action {
+                        LearnAction::Apply(id) => Some(id),
+                        _ => params_for_cc(control).next(),
+                    };
+                    if let Some(id) = touched {
+                        self.touch(id);
+                    }
+                    action
+                }
+            },
+        }
+    }
+
+    fn resolve(&self, control: u8) -> LearnAction {
+        match self.binding(control) {
+            Some(id) => LearnAction::Apply(id),
+            None => LearnAction::Default,
+        }
+    }
+
+    /// Serialize the bindings as pairs of control number and parameter index
+    ///
+    /// Does not allocate, so the audio task can publish the bindings.
+    pub fn to_bytes(&self) -> LearnBytes {
+        let mut bytes = LearnBytes::EMPTY;
+        let pairs = self
+            .bindings
+            .iter()
+            .enumerate()
+            .filter_map(|(control, id)| id.map(|id| [control as u8, id as u8]));
+        for (pair, chunk) in pairs.zip(bytes.bytes.chunks_exact_mut(2)) {
+            chunk.copy_from_slice(&pair);
+            bytes.len += 2;
+        }
+        bytes
+    }
+
+    /// Replace the bindings with the ones serialized by [`MidiLearn::to_bytes`]
+    ///
+    /// Pairs referring to unknown parameters are skipped.
+    pub fn load_bytes(&mut self, bytes: &[u8]) {
+        self.bindings = [None; 128];
+        for pair in bytes.chunks_exact(2).take(MAX_BINDINGS) {
+            if let (Some(binding), Some(id)) = (
+                self.bindings.get_mut(pair[0] as usize),
+                ParamId::from_index(pair[1] as usize),
+            ) {
+                *binding = Some(id);
+            }
+        }
+        self.changed = false;
+    }
+}
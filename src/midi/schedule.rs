@@ -0,0 +1,154 @@
+use alloc::collections::VecDeque;
+use core::fmt;
+use embassy_time::Instant;
+use midi_msg::MidiMsg;
+
+use super::TimedMidi;
+use crate::{config::AudioConfig, link::EVENT_QUEUE, stereo::Stereo, voice::Instrument};
+
+/// The estimate of the audio clock moves by 1/2^CLOCK_SMOOTHING of its error per block
+const CLOCK_SMOOTHING: u32 = 4;
+
+/// Timing of the events, see [`EventScheduler::take_timing`]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Timing {
+    /// events that were rendered
+    pub events: u32,
+    /// events that were rendered after their frame
+    pub late: u32,
+    /// largest distance of an event from its frame, in frames
+    pub max_jitter: u32,
+    /// largest distance of a block start from the estimated audio clock, in frames
+    ///
+    /// This is the jitter the events would have without time stamps.
+    pub max_clock_jitter: u32,
+}
+
+impl fmt::Display for Timing {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "midi: {} events, {} late, jitter {} frames, without time stamps {} frames",
+            self.events, self.late, self.max_jitter, self.max_clock_jitter
+        )
+    }
+}
+
+/// Places time-stamped MIDI events on the frame they belong to
+///
+/// The renderer only runs once per chunk, so handling the events right away would move them to
+/// chunk boundaries. Instead, the time stamp of every event is mapped to the audio clock, and
+/// [`EventScheduler::render`] splits the block at the frames of the events.
+///
+/// The audio clock is the position of the rendered frames. Each block compares it with the timer
+/// at the start of the block, the smoothed offset between both maps time stamps to frames.
+pub struct EventScheduler {
+    /// events that were not rendered yet, sorted by time
+    ///
+    /// Its capacity is reserved up front, so the audio task never allocates.
+    pending: VecDeque<TimedMidi>,
+    /// frame at the start of the next block
+    position: i64,
+    /// offset from timer frames to the audio position, scaled by 2^CLOCK_SMOOTHING
+    clock: Option<i64>,
+    /// time from an event to the frame it is rendered on, in frames
+    ///
+    /// One chunk: the events that arrive while a chunk is rendered and played go into the next
+    /// chunk, at the same distance from its start.
+    latency: i64,
+    sample_rate: u64,
+    timing: Timing,
+}
+
+impl EventScheduler {
+    pub fn new() -> Self {
+        let config = AudioConfig::current();
+        Self {
+            pending: VecDeque::with_capacity(EVENT_QUEUE),
+            position: 0,
+            clock: None,
+            latency: config.chunk_size as i64,
+            sample_rate: config.sample_rate.hz() as u64,
+            timing: Timing::default(),
+        }
+    }
+
+    /// Frames since the start of the timer
+    fn timer_frames(&self, time: Instant) -> i64 {
+        (time.as_micros() * self.sample_rate / 1_000_000) as i64
+    }
+
+    /// Whether [`EventScheduler::push`] rejects events until some are rendered
+    pub fn is_full(&self) -> bool {
+        self.pending.len() >= EVENT_QUEUE
+    }
+
+    /// Queue `event` for rendering, returns it when the scheduler is full
+    pub fn push(&mut self, event: TimedMidi) -> Result<(), TimedMidi> {
+        if self.is_full() {
+            return Err(event);
+        }
+        let i = self.pending.partition_point(|e| e.time <= event.time);
+        self.pending.insert(i, event);
+        Ok(())
+    }
+
+    /// Render `block` with `instrument`, starting at `now`
+    ///
+    /// The block is rendered in pieces, and `handle` applies every event that belongs in the
+    /// block to the instrument between them. Events for later frames stay queued.
+    pub fn render<I: Instrument>(
+        &mut self,
+        now: Instant,
+        instrument: &mut I,
+        block: &mut [Stereo],
+        mut handle: impl FnMut(&mut I, &MidiMsg),
+    ) {
+        let offset = self.update_clock(now);
+        let end = self.position + block.len() as i64;
+        let mut start = 0;
+        while let Some(event) = self.pending.front() {
+            let frame = self.timer_frames(event.time) + offset + self.latency - self.position;
+            if frame >= block.len() as i64 {
+                break;
+            }
+            let Some(event) = self.pending.pop_front() else {
+                break;
+            };
+            // late events go to the start of the block, and events never overtake each other
+            let at = frame.max(start as i64) as usize;
+            let jitter = (at as i64 - frame) as u32;
+            if jitter > 0 {
+                self.timing.late += 1;
+                self.timing.max_jitter = self.timing.max_jitter.max(jitter);
+            }
+            self.timing.events += 1;
+
+            instrument.generate_block(&mut block[start..at]);
+            start = at;
+            handle(instrument, &event.msg);
+        }
+        instrument.generate_block(&mut block[start..]);
+        self.position = end;
+    }
+
+    /// Measure the audio clock at the start of a block and return the smoothed offset
+    fn update_clock(&mut self, now: Instant) -> i64 {
+        let measured = self.position - self.timer_frames(now);
+        let clock = match self.clock {
+            Some(clock) => clock + measured - (clock >> CLOCK_SMOOTHING),
+            None => measured << CLOCK_SMOOTHING,
+        };
+        self.clock = Some(clock);
+
+        let offset = clock >> CLOCK_SMOOTHING;
+        let deviation = (measured - offset).max(offset - measured) as u32;
+        self.timing.max_clock_jitter = self.timing.max_clock_jitter.max(deviation);
+        offset
+    }
+
+    /// Return the timing collected since the last call and start over
+    pub fn take_timing(&mut self) -> Timing {
+        core::mem::take(&mut self.timing)
+    }
+}
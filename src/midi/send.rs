@@ -1,6 +1,10 @@
+use embassy_time::Instant;
 use midi_msg::{Channel::Ch1, ControlChange, MidiMsg};
 
-use super::MIDI_EVENTS;
+use super::{
+    control::{split_14bit, DATA_ENTRY_LSB, DATA_ENTRY_MSB, NRPN_LSB, NRPN_MSB},
+    TimedMidi, MIDI_EVENTS,
+};
 
 pub async fn send_control(control: u8, value: u8) {
     let msg = MidiMsg::ChannelVoice {
@@ -9,21 +13,63 @@ pub async fn send_control(control: u8, value: u8) {
             control: ControlChange::CC { control, value },
         },
     };
-    MIDI_EVENTS.send(msg).await;
+    MIDI_EVENTS.send(TimedMidi::now(msg)).await;
 }
 
 pub async fn send_note_on(note: u8, velocity: u8) {
+    send_note_on_at(note, velocity, Instant::now()).await;
+}
+
+/// Send a NoteOn that should sound at `time`, e.g. the time of a sequencer step
+pub async fn send_note_on_at(note: u8, velocity: u8, time: Instant) {
     let msg = MidiMsg::ChannelVoice {
         channel: Ch1,
         msg: midi_msg::ChannelVoiceMsg::NoteOn { note, velocity },
     };
-    MIDI_EVENTS.send(msg).await;
+    MIDI_EVENTS.send(TimedMidi { msg, time }).await;
 }
 
 pub async fn send_note_off(note: u8, velocity: u8) {
+    send_note_off_at(note, velocity, Instant::now()).await;
+}
+
+/// Send a NoteOff that should sound at `time`
+pub async fn send_note_off_at(note: u8, velocity: u8, time: Instant) {
     let msg = MidiMsg::ChannelVoice {
         channel: Ch1,
         msg: midi_msg::ChannelVoiceMsg::NoteOff { note, velocity },
     };
-    MIDI_EVENTS.send(msg).await;
+    MIDI_EVENTS.send(TimedMidi { msg, time }).await;
+}
+
+/// How a controller value is transmitted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlMode {
+    /// 7-bit control change
+    Cc(u8),
+    /// 14-bit control change pair, MSB on the given control (1-31) and LSB on control + 32
+    Cc14(u8),
+    /// Non-registered parameter number with 14-bit data entry
+    Nrpn(u16),
+}
+
+/// Send a 14-bit controller `value` in range [0, 16383]
+///
+/// In [`ControlMode::Cc`] mode only the 7 most significant bits are sent.
+pub async fn send_control_value(mode: ControlMode, value: u16) {
+    let (msb, lsb) = split_14bit(value);
+    match mode {
+        ControlMode::Cc(control) => send_control(control, msb).await,
+        ControlMode::Cc14(control) => {
+            send_control(control, msb).await;
+            send_control(control + 32, lsb).await;
+        }
+        ControlMode::Nrpn(param) => {
+            let (param_msb, param_lsb) = split_14bit(param);
+            send_control(NRPN_MSB, param_msb).await;
+            send_control(NRPN_LSB, param_lsb).await;
+            send_control(DATA_ENTRY_MSB, msb).await;
+            send_control(DATA_ENTRY_LSB, lsb).await;
+        }
+    }
 }
@@ -1,26 +1,37 @@
+#[cfg(feature = "hw")]
 use alloc::vec::Vec;
-use embassy_time::{Duration, Ticker, Timer};
+use embassy_time::{Duration, Instant, Timer};
 
-use super::{send_note_off, send_note_on};
+use super::{clock::set_beat_duration, send_note_off_at, send_note_on_at};
 
+/// Sequencer steps per quarter note, the sequencer runs in eighth notes
+pub const STEPS_PER_BEAT: u32 = 2;
+
+#[cfg(feature = "hw")]
 #[embassy_executor::task]
 pub async fn sequencer(melody: Vec<u8>, beat_duration: Duration, note_duration: Duration) {
     produce_midi_for_note_sequence(&melody, beat_duration, note_duration).await;
 }
 
 /// Produce NoteOn and NoteOff events for each note in the sequence
+///
+/// The sequencer sets the tempo for tempo-synced effects, see [`super::clock`].
 pub async fn produce_midi_for_note_sequence(
     melody: &[u8],
     beat_duration: Duration,
     note_duration: Duration,
 ) {
-    let mut beat = Ticker::every(beat_duration);
+    set_beat_duration(beat_duration * STEPS_PER_BEAT);
+    // the events carry the time of the step rather than the time the task woke up, so the notes
+    // stay on the grid even when the executor is busy
+    let mut step = Instant::now();
     for note in melody.iter().cycle() {
-        beat.next().await;
-        let note_off = Timer::after(note_duration);
-        send_note_on(*note, 127).await;
+        step += beat_duration;
+        Timer::at(step).await;
+        send_note_on_at(*note, 127, step).await;
 
-        note_off.await;
-        send_note_off(*note, 127).await;
+        let note_off = step + note_duration;
+        Timer::at(note_off).await;
+        send_note_off_at(*note, 127, note_off).await;
     }
 }
@@ -0,0 +1,168 @@
+use alloc::vec::Vec;
+
+use super::learn::{CLEAR_CC, LEARN_CC};
+use crate::diagnostics::Diagnostics;
+
+const SYSEX_START: u8 = 0xF0;
+const SYSEX_END: u8 = 0xF7;
+
+/// Manufacturer ID for non-commercial use
+pub const MANUFACTURER_ID: u8 = 0x7D;
+
+/// `F0 7D 01 F7` asks for the [`Diagnostics`]
+pub const DIAGNOSTICS_REQUEST: u8 = 0x01;
+/// `F0 7D 02 <load> <peak load> <chunks> <late> <underruns> <overruns> F7` answers it
+///
+/// The loads are in 0.1 %, as 14-bit values in two bytes. The counters are 32-bit values in five
+/// bytes. Both are sent most significant bits first, 7 bits per byte.
+pub const DIAGNOSTICS_REPLY: u8 = 0x02;
+/// `F0 7D 03 F7` toggles learn mode, like a press of [`LEARN_CC`]
+pub const LEARN_REQUEST: u8 = 0x03;
+/// `F0 7D 04 F7` clears learned bindings, like a press of [`CLEAR_CC`]
+pub const CLEAR_REQUEST: u8 = 0x04;
+
+/// Longest SysEx message that is assembled, longer ones are dropped
+const MAX_SYSEX: usize = 32;
+
+/// Whether `sysex`, from `F0` to `F7`, asks for the diagnostics
+pub fn is_diagnostics_request(sysex: &[u8]) -> bool {
+    sysex == [SYSEX_START, MANUFACTURER_ID, DIAGNOSTICS_REQUEST, SYSEX_END]
+}
+
+/// The learn control that `sysex`, from `F0` to `F7`, presses, if it is a learn request
+pub fn learn_control(sysex: &[u8]) -> Option<u8> {
+    match sysex {
+        [SYSEX_START, MANUFACTURER_ID, LEARN_REQUEST, SYSEX_END] => Some(LEARN_CC),
+        [SYSEX_START, MANUFACTURER_ID, CLEAR_REQUEST, SYSEX_END] => Some(CLEAR_CC),
+        _ => None,
+    }
+}
+
+/// The SysEx reply with `diagnostics`, from `F0` to `F7`
+pub fn diagnostics_reply(diagnostics: &Diagnostics) -> Vec<u8> {
+    let mut sysex = Vec::with_capacity(28);
+    sysex.extend_from_slice(&[SYSEX_START, MANUFACTURER_ID, DIAGNOSTICS_REPLY]);
+    for load in [diagnostics.load, diagnostics.peak_load] {
+        let permille = (1000. * load).clamp(0., 0x3FFF as f32) as u16;
+        sysex.extend_from_slice(&[(permille >> 7) as u8, (permille & 0x7F) as u8]);
+    }
+    let counters = [
+        diagnostics.chunks,
+        diagnostics.late,
+        diagnostics.underruns,
+        diagnostics.overruns,
+    ];
+    for counter in counters {
+        sysex.extend((0..5).rev().map(|i| ((counter >> (7 * i)) & 0x7F) as u8));
+    }
+    sysex.push(SYSEX_END);
+    sysex
+}
+
+/// Collects a SysEx message from the 4-byte event packets of USB MIDI
+pub struct SysexReader {
+    bytes: Vec<u8>,
+    /// the message is longer than [`MAX_SYSEX`] and is skipped until its end
+    overflow: bool,
+}
+
+impl SysexReader {
+    pub fn new() -> Self {
+        Self {
+            bytes: Vec::with_capacity(MAX_SYSEX),
+            overflow: false,
+        }
+    }
+
+    /// Whether `packet` is part of a SysEx message
+    pub fn is_sysex(packet: &[u8]) -> bool {
+        matches!(packet[0] & 0x0F, 0x4..=0x7)
+    }
+
+    /// Add a SysEx packet, returns the message from `F0` to `F7` when it is complete
+    pub fn push(&mut self, packet: &[u8]) -> Option<&[u8]> {
+        // code index 4 continues the message, 5, 6 and 7 end it after 1, 2 or 3 bytes
+        let (len, end) = match packet[0] & 0x0F {
+            0x4 => (3, false),
+            0x5 => (1, true),
+            0x6 => (2, true),
+            0x7 => (3, true),
+            _ => return None,
+        };
+        let data = &packet[1..=len];
+        if data[0] == SYSEX_START {
+            self.bytes.clear();
+            self.overflow = false;
+        }
+        match self.bytes.len() + len > MAX_SYSEX {
+            true => self.overflow = true,
+            false => self.bytes.extend_from_slice(data),
+        }
+        match end {
+            true if !self.overflow && self.bytes.first() == Some(&SYSEX_START) => {
+                Some(self.bytes.as_slice())
+            }
+            true => {
+                self.bytes.clear();
+                None
+            }
+            false => None,
+        }
+    }
+}
+
+/// Split `sysex`, from `F0` to `F7`, into USB MIDI event packets for cable 0
+pub fn usb_packets(sysex: &[u8]) -> impl Iterator<Item = [u8; 4]> + '_ {
+    let count = sysex.len().div_ceil(3);
+    sysex.chunks(3).enumerate().map(move |(i, chunk)| {
+        let code = match (i + 1 == count, chunk.len()) {
+            (false, _) => 0x4,
+            (true, 1) => 0x5,
+            (true, 2) => 0x6,
+            (true, _) => 0x7,
+        };
+        let mut packet = [code, 0, 0, 0];
+        packet[1..=chunk.len()].copy_from_slice(chunk);
+        packet
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        midi::learn::{LearnAction, LearnState, MidiLearn},
+        params::ParamId,
+    };
+
+    /// Send `sysex` through a reader and press the learn control it asks for
+    fn request(learn: &mut MidiLearn, sysex: &[u8]) {
+        let mut reader = SysexReader::new();
+        let control = usb_packets(sysex)
+            .filter_map(|packet| reader.push(&packet).and_then(learn_control))
+            .last()
+            .expect("a learn request");
+        assert_eq!(learn.handle_cc(control, 127), LearnAction::Consumed);
+        assert_eq!(learn.handle_cc(control, 0), LearnAction::Consumed);
+    }
+
+    #[test]
+    fn learn_requests_press_the_learn_controls() {
+        let mut learn = MidiLearn::new();
+        request(&mut learn, &[0xF0, 0x7D, 0x03, 0xF7]);
+        assert_eq!(learn.state(), LearnState::WaitingForSource);
+        request(&mut learn, &[0xF0, 0x7D, 0x03, 0xF7]);
+        assert_eq!(learn.state(), LearnState::Idle);
+
+        learn.bind(20, ParamId::LpCutoff);
+        request(&mut learn, &[0xF0, 0x7D, 0x04, 0xF7]);
+        assert_eq!(learn.binding(20), None);
+    }
+
+    #[test]
+    fn other_sysex_is_not_a_learn_request() {
+        assert_eq!(learn_control(&[0xF0, 0x7D, 0x01, 0xF7]), None);
+        assert_eq!(learn_control(&[0xF0, 0x7E, 0x03, 0xF7]), None);
+        assert_eq!(learn_control(&[0xF0, 0x7D, 0x03, 0x00, 0xF7]), None);
+    }
+}
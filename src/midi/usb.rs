@@ -1,4 +1,5 @@
-use embassy_futures::join::join;
+use alloc::vec::Vec;
+use embassy_futures::join::join3;
 use embassy_usb::{class::midi::MidiClass, driver::EndpointError, Builder};
 use esp_backtrace as _;
 use esp_hal::{
@@ -11,7 +12,18 @@ use esp_hal::{
 use esp_println::println;
 use midi_msg::MidiMsg;
 
-use crate::midi::MIDI_EVENTS;
+use crate::{
+    config::AudioConfig,
+    diagnostics::AUDIO_STATS,
+    midi::{
+        send::send_control,
+        sysex::{
+            diagnostics_reply, is_diagnostics_request, learn_control, usb_packets, SysexReader,
+        },
+        TimedMidi, MIDI_EVENTS,
+    },
+    usb_audio::{UsbAudioClass, UsbAudioStream},
+};
 
 struct Disconnected {}
 
@@ -42,15 +54,19 @@ fn create_usb_config<'d>() -> embassy_usb::Config<'d> {
     config
 }
 
+/// Run the USB device, a composite of MIDI and audio
+///
+/// MIDI events go to [`MIDI_EVENTS`]. The audio function streams the frames of `audio`, i.e. what
+/// the DAC plays, to the host, see [`UsbAudioClass`].
 #[embassy_executor::task]
-pub async fn handle_usb(usb: Usb<'static>) {
+pub async fn handle_usb(usb: Usb<'static>, mut audio: UsbAudioStream<'static>) {
     println!("starting usb handler on {:?}", get_core());
     let mut ep_out_buffer = [0u8; 1024];
     let config = Config::default();
     let driver = Driver::new(usb, &mut ep_out_buffer, config);
 
     let usb_config = create_usb_config();
-    let mut config_descriptor = [0; 256];
+    let mut config_descriptor = [0; 512];
     let mut bos_descriptor = [0; 256];
     let mut control_buf = [0; 64];
 
@@ -64,6 +80,8 @@ pub async fn handle_usb(usb: Usb<'static>) {
     );
 
     let mut class = MidiClass::new(&mut builder, 1, 1, 64);
+    let sample_rate = AudioConfig::current().sample_rate.hz();
+    let mut audio_class = UsbAudioClass::new(&mut builder, sample_rate);
     let mut usb = builder.build();
 
     let usb_fut = usb.run();
@@ -77,20 +95,39 @@ pub async fn handle_usb(usb: Usb<'static>) {
             println!("Disconnected");
         }
     };
-    join(usb_fut, midi_fut).await;
+    let audio_fut = audio_class.run(&mut audio);
+    join3(usb_fut, midi_fut, audio_fut).await;
 }
 
 async fn midi_print<'d>(class: &mut MidiClass<'d, Driver<'d>>) -> Result<(), Disconnected> {
     let mut buf = [0; 64];
+    let mut sysex = SysexReader::new();
     loop {
         println!("Waiting for data");
         let n = class.read_packet(&mut buf).await?;
 
-        if let Ok((msg, _)) = MidiMsg::from_midi(&buf[1..n]) {
-            println!("MIDI: {:?}", msg);
-            MIDI_EVENTS.send(msg).await;
-        } else {
-            println!("MIDI: error parsing event: {:x?}", &buf[1..n]);
+        // a USB packet holds one or more 4-byte MIDI event packets
+        for packet in buf[..n].chunks_exact(4) {
+            if SysexReader::is_sysex(packet) {
+                let Some(request) = sysex.push(packet) else {
+                    continue;
+                };
+                // a SysEx request reads the audio diagnostics or presses a learn control
+                if is_diagnostics_request(request) {
+                    let reply: Vec<u8> = usb_packets(&diagnostics_reply(&AUDIO_STATS.read()))
+                        .flatten()
+                        .collect();
+                    class.write_packet(&reply).await?;
+                } else if let Some(control) = learn_control(request) {
+                    send_control(control, 127).await;
+                    send_control(control, 0).await;
+                }
+            } else if let Ok((msg, _)) = MidiMsg::from_midi(&packet[1..]) {
+                println!("MIDI: {:?}", msg);
+                MIDI_EVENTS.send(TimedMidi::now(msg)).await;
+            } else {
+                println!("MIDI: error parsing event: {:x?}", &packet[1..]);
+            }
         }
     }
 }
@@ -0,0 +1,447 @@
+use alloc::vec::Vec;
+use midi_msg::{ChannelVoiceMsg, ControlChange, MidiMsg};
+
+use crate::{
+    midi::{
+        control::{ControlDecoder, ControlEvent, ControlTarget, ParamControls},
+        learn::MidiLearn,
+    },
+    params::{ParamId, Patch},
+    stereo::Stereo,
+    voice::{Instrument, Voice, BLOCK_SIZE},
+};
+
+/// RPN of the MPE configuration message, the data MSB is the number of member channels
+pub const RPN_MPE_CONFIGURATION: u16 = 6;
+/// RPN of the pitch bend range, the data MSB is in semitones and the LSB in cents
+pub const RPN_PITCH_BEND_RANGE: u16 = 0;
+/// Control change of the third dimension of control ("timbre")
+pub const TIMBRE_CC: u8 = 74;
+
+const LOWER_MASTER: u8 = 0;
+const UPPER_MASTER: u8 = 15;
+const DEFAULT_MEMBER_BEND_RANGE: f32 = 48.;
+const DEFAULT_MASTER_BEND_RANGE: f32 = 2.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Zone {
+    /// Number of member channels, 0 disables the zone
+    pub members: u8,
+    /// Pitch bend range of the member channels in semitones
+    pub member_bend_range: f32,
+    /// Pitch bend range of the master channel in semitones
+    pub master_bend_range: f32,
+}
+
+impl Zone {
+    const DISABLED: Zone = Zone {
+        members: 0,
+        member_bend_range: DEFAULT_MEMBER_BEND_RANGE,
+        master_bend_range: DEFAULT_MASTER_BEND_RANGE,
+    };
+
+    fn new(members: u8) -> Self {
+        Self {
+            members,
+            ..Self::DISABLED
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ZoneId {
+    Lower,
+    Upper,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    Master(ZoneId),
+    Member(ZoneId),
+    /// Channel 1 when no zone is configured: plays notes and controls the sound
+    Single,
+    Unused,
+}
+
+/// Expression state of a MIDI channel
+struct ChannelState {
+    decoder: ControlDecoder,
+    /// pitch bend, range: [-1, 1]
+    bend: f32,
+    /// range: [0, 1]
+    pressure: f32,
+    /// range: [0, 1]
+    timbre: f32,
+}
+
+impl ChannelState {
+    fn new() -> Self {
+        Self {
+            decoder: ControlDecoder::new(),
+            bend: 0.,
+            pressure: 1.,
+            timbre: 0.5,
+        }
+    }
+}
+
+struct Slot {
+    channel: u8,
+    note: Option<u8>,
+    age: u32,
+}
+
+/// Polyphonic instrument with MIDI Polyphonic Expression
+///
+/// Zones are configured with the MPE configuration message (RPN 6) on the master channel:
+/// channel 1 for the lower zone and channel 16 for the upper zone. Every note on a member channel
+/// gets its own [`Voice`]. Pitch bend, channel pressure and CC 74 of the member channel control
+/// pitch, pressure and timbre of that voice. Control changes on a master channel set the sound
+/// parameters of all voices, the master pitch bend applies to all voices of the zone.
+///
+/// As long as no zone is configured, channel 1 plays polyphonically with channel-wide expression.
+pub struct MpeSynth {
+    voices: Vec<Voice>,
+    slots: Vec<Slot>,
+    lower: Zone,
+    upper: Zone,
+    channels: Vec<ChannelState>,
+    controls: ParamControls,
+    patch: Patch,
+    age: u32,
+}
+
+impl MpeSynth {
+    /// Create a synth with `polyphony` voices
+    pub fn new(polyphony: usize) -> Self {
+        Self {
+            voices: (0..polyphony).map(|_| Voice::new()).collect(),
+            slots: (0..polyphony)
+                .map(|_| Slot {
+                    channel: 0,
+                    note: None,
+                    age: 0,
+                })
+                .collect(),
+            lower: Zone::DISABLED,
+            upper: Zone::DISABLED,
+            channels: (0..16).map(|_| ChannelState::new()).collect(),
+            controls: ParamControls::new(),
+            patch: Patch::DEFAULT,
+            age: 0,
+        }
+    }
+
+    pub fn lower_zone(&self) -> Zone {
+        self.lower
+    }
+
+    pub fn upper_zone(&self) -> Zone {
+        self.upper
+    }
+
+    /// Configure the lower zone with `members` member channels, starting at channel 2
+    pub fn set_lower_zone(&mut self, members: u8) {
+        self.lower = Zone::new(members.min(15));
+        // the zones must not overlap, the other zone shrinks
+        self.upper.members = self.upper.members.min(14 - self.lower.members.min(14));
+    }
+
+    /// Configure the upper zone with `members` member channels, starting at channel 15 downwards
+    pub fn set_upper_zone(&mut self, members: u8) {
+        self.upper = Zone::new(members.min(15));
+        self.lower.members = self.lower.members.min(14 - self.upper.members.min(14));
+    }
+
+    /// Set the pitch bend range of the member channels of both zones in semitones
+    pub fn set_member_bend_range(&mut self, semitones: f32) {
+        self.lower.member_bend_range = semitones;
+        self.upper.member_bend_range = semitones;
+    }
+
+    fn role(&self, channel: u8) -> Role {
+        let lower = self.lower.members;
+        let upper = self.upper.members;
+        if lower == 0 && upper == 0 {
+            return if channel == LOWER_MASTER {
+                Role::Single
+            } else {
+                Role::Unused
+            };
+        }
+        match channel {
+            LOWER_MASTER if lower > 0 => Role::Master(ZoneId::Lower),
+            UPPER_MASTER if upper > 0 => Role::Master(ZoneId::Upper),
+            c if lower > 0 && c > LOWER_MASTER && c <= lower => Role::Member(ZoneId::Lower),
+            c if upper > 0 && c < UPPER_MASTER && c >= UPPER_MASTER - upper => {
+                Role::Member(ZoneId::Upper)
+            }
+            _ => Role::Unused,
+        }
+    }
+
+    fn zone(&self, id: ZoneId) -> &Zone {
+        match id {
+            ZoneId::Lower => &self.lower,
+            ZoneId::Upper => &self.upper,
+        }
+    }
+
+    fn zone_mut(&mut self, id: ZoneId) -> &mut Zone {
+        match id {
+            ZoneId::Lower => &mut self.lower,
+            ZoneId::Upper => &mut self.upper,
+        }
+    }
+
+    /// Pitch offset in semitones of a note on `channel`, including the master pitch bend
+    fn bend(&self, channel: u8) -> f32 {
+        match self.role(channel) {
+            Role::Member(id) => {
+                let zone = self.zone(id);
+                let master = match id {
+                    ZoneId::Lower => LOWER_MASTER,
+                    ZoneId::Upper => UPPER_MASTER,
+                };
+                self.channels[channel as usize].bend * zone.member_bend_range
+                    + self.channels[master as usize].bend * zone.master_bend_range
+            }
+            Role::Master(id) => {
+                self.channels[channel as usize].bend * self.zone(id).master_bend_range
+            }
+            Role::Single | Role::Unused => {
+                self.channels[channel as usize].bend * self.lower.master_bend_range
+            }
+        }
+    }
+
+    /// Apply the expression of `channel` to all voices playing on it
+    ///
+    /// Voices of member channels additionally follow the master channel of their zone.
+    fn update_expression(&mut self, channel: u8) {
+        let role = self.role(channel);
+        for i in 0..self.voices.len() {
+            let slot_channel = self.slots[i].channel;
+            let affected = slot_channel == channel
+                || matches!(
+                    (role, self.role(slot_channel)),
+                    (Role::Master(a), Role::Member(b)) if a == b
+                );
+            if affected {
+                let bend = self.bend(slot_channel);
+                let state = &self.channels[slot_channel as usize];
+                let voice = &mut self.voices[i];
+                voice.set_pitch_bend(bend);
+                voice.set_pressure(state.pressure);
+                voice.set_timbre(state.timbre);
+            }
+        }
+    }
+
+    fn note_on(&mut self, channel: u8, note: u8, velocity: u8) {
+        // prefer voices that are not playing, then the one that was started first
+        let Some(i) = (0..self.voices.len()).min_by_key(|&i| {
+            let slot = &self.slots[i];
+            (slot.note.is_some(), slot.age)
+        }) else {
+            return;
+        };
+        self.age = self.age.wrapping_add(1);
+        self.slots[i] = Slot {
+            channel,
+            note: Some(note),
+            age: self.age,
+        };
+        let bend = self.bend(channel);
+        let state = &self.channels[channel as usize];
+        let voice = &mut self.voices[i];
+        voice.set_pitch_bend(bend);
+        voice.set_pressure(state.pressure);
+        voice.set_timbre(state.timbre);
+        // a stolen voice must not return to its previous note on release
+        voice.release_all();
+        voice.handle_note_on(note, velocity);
+    }
+
+    fn note_off(&mut self, channel: u8, note: u8, velocity: u8) {
+        for (slot, voice) in self.slots.iter_mut().zip(self.voices.iter_mut()) {
+            if slot.channel == channel && slot.note == Some(note) {
+                slot.note = None;
+                voice.handle_note_off(note, velocity);
+            }
+        }
+    }
+
+    fn set_param(&mut self, id: ParamId, value: f32) {
+        self.patch.set(id, value);
+        for voice in self.voices.iter_mut() {
+            voice.set_param(id, value);
+        }
+    }
+
+    /// Control changes on channel 1 and 16, which configure the zones even while they are disabled
+    fn handle_master_cc(&mut self, channel: u8, control: u8, value: u8) {
+        let role = self.role(channel);
+        if matches!(role, Role::Master(_) | Role::Single) {
+            if let Some(ControlTarget::Params(targets, x)) = self.controls.handle_cc(control, value)
+            {
+                for id in targets.iter() {
+                    self.set_param(id, id.spec().from_normalized(x));
+                }
+            }
+        }
+
+        let event = self.channels[channel as usize]
+            .decoder
+            .decode(control, value);
+        let Some(ControlEvent::Rpn { param, value }) = event else {
+            return;
+        };
+        match (param, role) {
+            (RPN_MPE_CONFIGURATION, _) if channel == LOWER_MASTER => {
+                self.set_lower_zone((value >> 7) as u8);
+            }
+            (RPN_MPE_CONFIGURATION, _) => self.set_upper_zone((value >> 7) as u8),
+            (RPN_PITCH_BEND_RANGE, Role::Master(id)) => {
+                self.zone_mut(id).master_bend_range = bend_range(value);
+            }
+            (RPN_PITCH_BEND_RANGE, Role::Single) => {
+                self.lower.master_bend_range = bend_range(value);
+            }
+            _ => {}
+        }
+    }
+
+    fn handle_member_cc(&mut self, channel: u8, zone: ZoneId, control: u8, value: u8) {
+        if control == TIMBRE_CC {
+            self.channels[channel as usize].timbre = value as f32 / 127.;
+            self.update_expression(channel);
+            return;
+        }
+        let event = self.channels[channel as usize]
+            .decoder
+            .decode(control, value);
+        if let Some(ControlEvent::Rpn {
+            param: RPN_PITCH_BEND_RANGE,
+            value,
+        }) = event
+        {
+            // the per-note bend range applies to all member channels of the zone
+            self.zone_mut(zone).member_bend_range = bend_range(value);
+        }
+    }
+}
+
+/// Decode the data of the pitch bend range RPN to semitones
+fn bend_range(value: u16) -> f32 {
+    (value >> 7) as f32 + (value & 0x7F) as f32 / 100.
+}
+
+impl Instrument for MpeSynth {
+    fn generate(&mut self) -> Stereo {
+        let mut sum = [0.; 2];
+        for [l, r] in self.voices.iter_mut().map(|v| v.generate()) {
+            sum[0] += l;
+            sum[1] += r;
+        }
+        let n = self.voices.len().max(1) as f32;
+        sum.map(|x| x / n)
+    }
+
+    fn generate_block(&mut self, block: &mut [Stereo]) {
+        let n = self.voices.len().max(1) as f32;
+        let mut voice_block = [[0.; 2]; BLOCK_SIZE];
+        for chunk in block.chunks_mut(BLOCK_SIZE) {
+            let voice_block = &mut voice_block[..chunk.len()];
+            chunk.fill([0.; 2]);
+            for voice in self.voices.iter_mut() {
+                voice.generate_block(voice_block);
+                for (sum, [l, r]) in chunk.iter_mut().zip(voice_block.iter()) {
+                    sum[0] += l;
+                    sum[1] += r;
+                }
+            }
+            for frame in chunk.iter_mut() {
+                *frame = frame.map(|x| x / n);
+            }
+        }
+    }
+
+    fn handle_midi(&mut self, msg: MidiMsg) {
+        let MidiMsg::ChannelVoice { channel, msg } = msg else {
+            return;
+        };
+        let channel = channel as u8;
+        let role = self.role(channel);
+        match (msg, role) {
+            (
+                ChannelVoiceMsg::ControlChange {
+                    control: ControlChange::CC { control, value },
+                },
+                Role::Member(zone),
+            ) => self.handle_member_cc(channel, zone, control, value),
+            (
+                ChannelVoiceMsg::ControlChange {
+                    control: ControlChange::CC { control, value },
+                },
+                _,
+            ) => {
+                if control == TIMBRE_CC && role == Role::Single {
+                    self.channels[channel as usize].timbre = value as f32 / 127.;
+                    self.update_expression(channel);
+                } else if channel == LOWER_MASTER || channel == UPPER_MASTER {
+                    self.handle_master_cc(channel, control, value);
+                }
+            }
+            (_, Role::Unused) => {}
+            (ChannelVoiceMsg::NoteOn { note, velocity: 0 }, _) => self.note_off(channel, note, 0),
+            (ChannelVoiceMsg::NoteOn { note, velocity }, _) => {
+                self.note_on(channel, note, velocity);
+            }
+            (ChannelVoiceMsg::NoteOff { note, velocity }, _) => {
+                self.note_off(channel, note, velocity);
+            }
+            (ChannelVoiceMsg::PitchBend { bend }, _) => {
+                self.channels[channel as usize].bend = (bend as f32 - 8192.) / 8192.;
+                self.update_expression(channel);
+            }
+            (ChannelVoiceMsg::ChannelPressure { pressure }, _) => {
+                self.channels[channel as usize].pressure = pressure as f32 / 127.;
+                self.update_expression(channel);
+            }
+            _ => {}
+        }
+    }
+
+    fn patch(&self) -> Patch {
+        self.patch
+    }
+
+    fn load_patch(&mut self, patch: &Patch) {
+        for id in ParamId::all() {
+            self.set_param(id, patch.get(id));
+        }
+    }
+
+    fn learn(&mut self) -> &mut MidiLearn {
+        &mut self.controls.learn
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn master_channels_bend_by_the_range_of_their_zone() {
+        let mut synth = MpeSynth::new(1);
+        synth.set_lower_zone(7);
+        synth.set_upper_zone(7);
+        synth.lower.master_bend_range = 2.;
+        synth.upper.master_bend_range = 12.;
+        synth.channels[LOWER_MASTER as usize].bend = 1.;
+        synth.channels[UPPER_MASTER as usize].bend = 1.;
+        assert_eq!(synth.bend(LOWER_MASTER), 2.);
+        assert_eq!(synth.bend(UPPER_MASTER), 12.);
+    }
+}
@@ -1,5 +1,8 @@
+pub mod modulation;
 mod oscillators;
 pub mod phaser;
 pub mod scales;
 pub mod traits;
+mod unison;
 pub use oscillators::*;
+pub use unison::*;
@@ -0,0 +1,77 @@
+use super::traits::{Generator, Shaped};
+
+/// Slave oscillator for hard sync
+///
+/// Whenever the master oscillator wraps, the slave restarts its cycle. The jump in the output
+/// that this causes is smoothed with a polyBLEP residual, which removes most of the aliasing.
+///
+/// The master has to be generated first in every time step, so its wrap is known before the slave
+/// produces its sample. The wrap happens between the current and the next sample, so both halves
+/// of the residual can be applied without delaying the output.
+pub struct HardSync<O> {
+    pub osc: O,
+    /// correction for the next sample
+    residual: f32,
+}
+
+impl<O: Shaped + Generator<Out = f32>> HardSync<O> {
+    pub fn new(osc: O) -> Self {
+        Self { osc, residual: 0. }
+    }
+
+    /// Produce the next sample, `master_wrap` is [`super::phaser::PhaseGenerator::wrapped`] of
+    /// the master
+    pub fn generate_synced(&mut self, master_wrap: Option<f32>) -> f32 {
+        let mut out = self.osc.generate() + self.residual;
+        self.residual = 0.;
+
+        if let Some(d) = master_wrap {
+            let phase_gen = self.osc.get_phase_generator();
+            let free = phase_gen.phase();
+            phase_gen.sync(d);
+            let synced = phase_gen.phase();
+
+            // height of the step and its polyBLEP residual before and after the discontinuity
+            let h = self.osc.shape(synced) - self.osc.shape(free);
+            out += h * d * d / 2.;
+            self.residual = -h * (1. - d) * (1. - d) / 2.;
+        }
+        out
+    }
+}
+
+/// An oscillator of the voice, as source or target of ring modulation and FM
+///
+/// With the same oscillator as source and target, FM feeds the oscillator back into itself and
+/// ring modulation squares it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OscId {
+    /// the unison, as one oscillator
+    Osc1,
+    Osc2,
+    Sub,
+}
+
+impl OscId {
+    /// Decode the value of a routing parameter, e.g. [`crate::params::ParamId::FmSource`]
+    pub fn from_param(value: f32) -> Self {
+        match value as u8 {
+            0 => OscId::Osc1,
+            1 => OscId::Osc2,
+            _ => OscId::Sub,
+        }
+    }
+}
+
+/// Frequency factor for linear through-zero FM, see [`super::phaser::PhaseGenerator::set_fm`]
+///
+/// `modulator` is the output of the modulating oscillator in [-1, 1]. With a depth above 1 the
+/// carrier frequency goes through zero and the phase runs backwards.
+pub fn fm_factor(modulator: f32, depth: f32) -> f32 {
+    1. + depth * modulator
+}
+
+/// Ring modulation of `a` and `b`, crossfaded with `a` by `mix` in [0, 1]
+pub fn ring_mod(a: f32, b: f32, mix: f32) -> f32 {
+    a + mix * (a * b - a)
+}
@@ -1,6 +1,7 @@
 use super::{
     phaser::{PhaseGenerator, Phased},
-    traits::{Generator, Oscillator},
+    scales::{freq, REFERENCE_FREQ},
+    traits::{generate_shaped_block, Generator, Oscillator, Shaped},
 };
 use crate::discrete_functions::sin;
 use core::f32::consts::{PI, TAU};
@@ -39,6 +40,10 @@ impl Generator for SineOscillator {
         let phi = self.phase_gen.generate();
         sin(phi)
     }
+
+    fn generate_block(&mut self, block: &mut [f32]) {
+        generate_shaped_block(self, block);
+    }
 }
 
 impl Phased for SineOscillator {
@@ -47,6 +52,12 @@ impl Phased for SineOscillator {
     }
 }
 
+impl Shaped for SineOscillator {
+    fn shape(&self, phi: f32) -> f32 {
+        sin(phi)
+    }
+}
+
 // Sawtooth =============================
 //
 //  1 ┤        /        /
@@ -72,7 +83,11 @@ impl Generator for SawToothOscillator {
 
     fn generate(&mut self) -> Self::Out {
         let phi = self.phase_gen.generate();
-        phi / PI - 1.0
+        saw(phi)
+    }
+
+    fn generate_block(&mut self, block: &mut [f32]) {
+        generate_shaped_block(self, block);
     }
 }
 
@@ -81,6 +96,12 @@ impl Phased for SawToothOscillator {
         &mut self.phase_gen
     }
 }
+
+impl Shaped for SawToothOscillator {
+    fn shape(&self, phi: f32) -> f32 {
+        saw(phi)
+    }
+}
 // PWM ==================================
 //
 //  1 ┤───┐     ┌───┐     ┌
@@ -119,6 +140,10 @@ impl Generator for PWMOscillator {
             -1.0
         }
     }
+
+    fn generate_block(&mut self, block: &mut [f32]) {
+        generate_shaped_block(self, block);
+    }
 }
 
 impl Phased for PWMOscillator {
@@ -127,6 +152,293 @@ impl Phased for PWMOscillator {
     }
 }
 
+impl Shaped for PWMOscillator {
+    fn shape(&self, phi: f32) -> f32 {
+        if phi < TAU * self.duty_cycle {
+            1.0
+        } else {
+            -1.0
+        }
+    }
+}
+
+// Triangle =============================
+//
+//  1 ┤   *               *
+//    ┤ *   *           *   *
+//    ┼*─────*─────2π──*─────*─────4π─>
+//    ┤        *   *           *   *
+// ─1 ┤          *               *
+
+pub struct TriangleOscillator {
+    phase_gen: PhaseGenerator,
+}
+
+impl TriangleOscillator {
+    pub fn new(f_ref: f32) -> Self {
+        Self {
+            phase_gen: PhaseGenerator::new(f_ref),
+        }
+    }
+}
+
+impl Generator for TriangleOscillator {
+    type Out = f32;
+
+    fn generate(&mut self) -> f32 {
+        let phi = self.phase_gen.generate();
+        triangle(phi)
+    }
+
+    fn generate_block(&mut self, block: &mut [f32]) {
+        generate_shaped_block(self, block);
+    }
+}
+
+impl Phased for TriangleOscillator {
+    fn get_phase_generator(&mut self) -> &mut PhaseGenerator {
+        &mut self.phase_gen
+    }
+}
+
+impl Shaped for TriangleOscillator {
+    fn shape(&self, phi: f32) -> f32 {
+        triangle(phi)
+    }
+}
+
+// Waveforms ============================
+
+/// Triangle in phase with the sine
+fn triangle(phi: f32) -> f32 {
+    let x = phi / TAU;
+    if x < 0.25 {
+        4. * x
+    } else if x < 0.75 {
+        2. - 4. * x
+    } else {
+        4. * x - 4.
+    }
+}
+
+fn saw(phi: f32) -> f32 {
+    phi / PI - 1.0
+}
+
+fn square(phi: f32) -> f32 {
+    if phi < PI {
+        1.0
+    } else {
+        -1.0
+    }
+}
+
+/// Basic waveforms that can be selected at runtime
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Waveform {
+    Sine,
+    Triangle,
+    Saw,
+    Square,
+    /// Blend of the other waveforms, see [`MorphOscillator`]
+    Morph,
+}
+
+impl Waveform {
+    /// Decode the value of a waveform parameter, e.g. [`crate::params::ParamId::Waveform`]
+    pub fn from_param(value: f32) -> Self {
+        match value as u8 {
+            0 => Waveform::Sine,
+            1 => Waveform::Triangle,
+            2 => Waveform::Saw,
+            3 => Waveform::Square,
+            _ => Waveform::Morph,
+        }
+    }
+}
+
+/// Produce `waveform` at phase `phi`, `morph` is only used by [`Waveform::Morph`]
+fn waveform(waveform: Waveform, phi: f32, morph: f32) -> f32 {
+    match waveform {
+        Waveform::Sine => sin(phi),
+        Waveform::Triangle => triangle(phi),
+        Waveform::Saw => saw(phi),
+        Waveform::Square => square(phi),
+        Waveform::Morph => morphed(phi, morph),
+    }
+}
+
+/// Blend sine (0) to triangle (1) to saw (2) to square (3)
+fn morphed(phi: f32, morph: f32) -> f32 {
+    let morph = morph.clamp(0., 3.);
+    let shapes = [
+        Waveform::Sine,
+        Waveform::Triangle,
+        Waveform::Saw,
+        Waveform::Square,
+    ];
+    let i = (morph as usize).min(2);
+    let t = morph - i as f32;
+    let a = waveform(shapes[i], phi, 0.);
+    let b = waveform(shapes[i + 1], phi, 0.);
+    a + t * (b - a)
+}
+
+// Morph ================================
+
+pub struct MorphOscillator {
+    phase_gen: PhaseGenerator,
+
+    /// Blend between the waveforms: 0 = sine, 1 = triangle, 2 = saw, 3 = square
+    ///
+    /// range: [0, 3]
+    pub morph: f32,
+}
+
+impl MorphOscillator {
+    pub fn new(f_ref: f32, morph: f32) -> Self {
+        Self {
+            phase_gen: PhaseGenerator::new(f_ref),
+            morph,
+        }
+    }
+}
+
+impl Generator for MorphOscillator {
+    type Out = f32;
+
+    fn generate(&mut self) -> f32 {
+        let phi = self.phase_gen.generate();
+        morphed(phi, self.morph)
+    }
+
+    fn generate_block(&mut self, block: &mut [f32]) {
+        generate_shaped_block(self, block);
+    }
+}
+
+impl Phased for MorphOscillator {
+    fn get_phase_generator(&mut self) -> &mut PhaseGenerator {
+        &mut self.phase_gen
+    }
+}
+
+impl Shaped for MorphOscillator {
+    fn shape(&self, phi: f32) -> f32 {
+        morphed(phi, self.morph)
+    }
+}
+
+// Switchable waveform ==================
+
+/// Oscillator whose waveform can be changed while it is running
+///
+/// Changing the waveform keeps the phase, so there is no need to replace the oscillator.
+pub struct WaveformOscillator {
+    phase_gen: PhaseGenerator,
+    pub waveform: Waveform,
+    /// see [`MorphOscillator::morph`]
+    pub morph: f32,
+}
+
+impl WaveformOscillator {
+    pub fn new(f_ref: f32, waveform: Waveform) -> Self {
+        Self {
+            phase_gen: PhaseGenerator::new(f_ref),
+            waveform,
+            morph: 0.,
+        }
+    }
+}
+
+impl Generator for WaveformOscillator {
+    type Out = f32;
+
+    fn generate(&mut self) -> f32 {
+        let phi = self.phase_gen.generate();
+        waveform(self.waveform, phi, self.morph)
+    }
+
+    fn generate_block(&mut self, block: &mut [f32]) {
+        generate_shaped_block(self, block);
+    }
+}
+
+impl Phased for WaveformOscillator {
+    fn get_phase_generator(&mut self) -> &mut PhaseGenerator {
+        &mut self.phase_gen
+    }
+}
+
+impl Shaped for WaveformOscillator {
+    fn shape(&self, phi: f32) -> f32 {
+        waveform(self.waveform, phi, self.morph)
+    }
+}
+
+// Sub-oscillator =======================
+
+/// Oscillator that plays one or two octaves below the frequency it is set to
+pub struct SubOscillator<O> {
+    pub osc: O,
+    /// range: [1, 2]
+    octaves: u8,
+    frequency: f32,
+}
+
+impl<O: Oscillator> SubOscillator<O> {
+    pub fn new(osc: O, octaves: u8) -> Self {
+        let mut sub = Self {
+            osc,
+            octaves: 1,
+            frequency: REFERENCE_FREQ,
+        };
+        sub.set_octaves(octaves);
+        sub
+    }
+
+    pub fn set_octaves(&mut self, octaves: u8) {
+        self.octaves = octaves.clamp(1, 2);
+        self.set_frequency(self.frequency);
+    }
+}
+
+impl<O: Oscillator> Generator for SubOscillator<O> {
+    type Out = O::Out;
+
+    fn generate(&mut self) -> O::Out {
+        self.osc.generate()
+    }
+
+    fn generate_block(&mut self, block: &mut [O::Out]) {
+        self.osc.generate_block(block);
+    }
+}
+
+impl<O: Oscillator> Oscillator for SubOscillator<O> {
+    fn tune(&mut self, tuning_factor: f32) {
+        self.osc.tune(tuning_factor);
+    }
+
+    fn set_frequency(&mut self, frequency: f32) {
+        self.frequency = frequency;
+        self.osc
+            .set_frequency(frequency / (1 << self.octaves) as f32);
+    }
+
+    fn set_note(&mut self, note: u8) {
+        self.set_frequency(freq(note));
+    }
+
+    fn reset(&mut self) {
+        self.osc.reset();
+    }
+
+    fn set_phase(&mut self, phase: f32) {
+        self.osc.set_phase(phase);
+    }
+}
+
 // ======================================
 
 pub struct Noise {
@@ -157,4 +469,5 @@ impl Oscillator for Noise {
     fn set_frequency(&mut self, _frequency: f32) {}
     fn set_note(&mut self, _note: u8) {}
     fn reset(&mut self) {}
+    fn set_phase(&mut self, _phase: f32) {}
 }
@@ -1,14 +1,44 @@
 use core::f32::consts::TAU;
+use micromath::F32Ext;
 
-use crate::i2s::SAMPLE_RATE;
+use crate::config;
 
 use super::scales::REFERENCE_FREQ;
 
-pub const DT: f32 = 1. / SAMPLE_RATE as f32;
 /// calculate the phase increment for the set frequency, reference frequency
-/// and tune of the oscillator
-fn phase_increment(f_set: f32, f_ref: f32, tune: f32) -> f32 {
-    TAU * f_set * DT * (f_ref / REFERENCE_FREQ) * tune
+/// and tune of the oscillator, `dt` is the time between two samples
+fn phase_increment(f_set: f32, f_ref: f32, tune: f32, dt: f32) -> f32 {
+    TAU * f_set * dt * (f_ref / REFERENCE_FREQ) * tune
+}
+
+/// Largest wrap time below 1 sample
+const MAX_WRAP: f32 = 1. - f32::EPSILON;
+
+/// Bring `phi` into [0, 2 * pi)
+fn wrap(phi: f32) -> f32 {
+    let phi = phi.rem_euclid(TAU);
+    // rounding can land a tiny negative phase on 2 * pi
+    match phi < TAU {
+        true => phi,
+        false => 0.,
+    }
+}
+
+/// Wrap `phi` after a step of `step`, returns the phase and the time since the wrap in samples
+///
+/// Under deep through-zero FM a step can span more than a cycle, so the phase may wrap several
+/// times at once. The time is the one since the last of these wraps.
+fn wrap_step(phi: f32, step: f32) -> (f32, Option<f32>) {
+    if (0. ..=TAU).contains(&phi) {
+        return (phi, None);
+    }
+    let phi = wrap(phi);
+    let since = match step > 0. {
+        true => phi / step,
+        // through-zero FM runs the phase backwards
+        false => (phi - TAU) / step,
+    };
+    (phi, Some(since.clamp(0., MAX_WRAP)))
 }
 
 /// The phase generator is the heart of every oscillator. It's purpose is to produce the current
@@ -16,6 +46,7 @@ fn phase_increment(f_set: f32, f_ref: f32, tune: f32) -> f32 {
 pub struct PhaseGenerator {
     phi: f32,  // current phase
     dphi: f32, // phase increment
+    dt: f32,   // time between two samples, from the configured sample rate
 
     /// nominal frequency, range = (0, inf)
     f_set: f32,
@@ -23,6 +54,10 @@ pub struct PhaseGenerator {
     f_ref: f32,
     /// tuning factor, range = (0, inf), default = 1.0
     tune: f32,
+    /// frequency factor for linear FM, negative values run the phase backwards, default = 1.0
+    fm: f32,
+    /// time in samples from the last wrap to the next phase value, range = [0, 1)
+    wrap: Option<f32>,
 }
 
 impl PhaseGenerator {
@@ -31,37 +66,88 @@ impl PhaseGenerator {
     /// `f_ref` reference frequency (default = `scales::REFERENCE_FREQ`)
     pub fn new(f_ref: f32) -> Self {
         let f_set = REFERENCE_FREQ;
+        let dt = config::dt();
         PhaseGenerator {
             phi: 0.0,
-            dphi: phase_increment(f_set, f_ref, 1.0),
+            dphi: phase_increment(f_set, f_ref, 1.0, dt),
+            dt,
             f_set,
             f_ref,
             tune: 1.0,
+            fm: 1.0,
+            wrap: None,
         }
     }
 
     pub fn tune(&mut self, tune: f32) {
         self.tune = tune;
-        self.dphi = phase_increment(self.f_set, self.f_ref, self.tune);
+        self.dphi = phase_increment(self.f_set, self.f_ref, self.tune, self.dt);
     }
 
     pub fn set_frequency(&mut self, frequency: f32) {
         self.f_set = frequency;
-        self.dphi = phase_increment(self.f_set, self.f_ref, self.tune);
+        self.dphi = phase_increment(self.f_set, self.f_ref, self.tune, self.dt);
     }
 
     pub fn reset(&mut self) {
         self.phi = 0.;
     }
 
+    /// Set the current phase, range = [0, 2 * pi)
+    pub fn set_phase(&mut self, phi: f32) {
+        self.phi = phi;
+    }
+
+    /// The phase that the next call to `generate` will return
+    pub fn phase(&self) -> f32 {
+        self.phi
+    }
+
+    /// Set the frequency factor for linear through-zero FM, e.g. `1 + depth * modulator`
+    ///
+    /// The factor applies until it is changed again, 1.0 turns FM off.
+    pub fn set_fm(&mut self, fm: f32) {
+        self.fm = fm;
+    }
+
+    /// Restart the cycle, as if the phase wrapped `offset` samples before the next phase value
+    ///
+    /// This is the slave side of hard sync, `offset` comes from [`PhaseGenerator::wrapped`] of
+    /// the master.
+    pub fn sync(&mut self, offset: f32) {
+        let step = self.dphi * self.fm;
+        self.phi = wrap(offset * step);
+    }
+
+    /// Whether the phase wrapped during the last call to `generate`
+    ///
+    /// Returns the time in samples from the wrap to the next phase value, range = [0, 1). It is
+    /// used to place the discontinuity of hard sync between two samples.
+    pub fn wrapped(&self) -> Option<f32> {
+        self.wrap
+    }
+
     pub fn generate(&mut self) -> f32 {
         let a = self.phi;
-        self.phi += self.dphi;
-        if self.phi > TAU {
-            self.phi -= TAU;
-        }
+        let step = self.dphi * self.fm;
+        (self.phi, self.wrap) = wrap_step(self.phi + step, step);
         a
     }
+
+    /// Fill `block` with the next phase values, the same as calling `generate` for each of them
+    ///
+    /// [`PhaseGenerator::wrapped`] reports the wrap of the last value afterwards.
+    pub fn generate_block(&mut self, block: &mut [f32]) {
+        let step = self.dphi * self.fm;
+        let mut phi = self.phi;
+        let mut wrap = self.wrap;
+        for x in block.iter_mut() {
+            *x = phi;
+            (phi, wrap) = wrap_step(phi + step, step);
+        }
+        self.phi = phi;
+        self.wrap = wrap;
+    }
 }
 
 /// Helper trait that facilitates implementation of other traits
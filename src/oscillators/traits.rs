@@ -11,6 +11,32 @@ pub trait Generator {
     ///
     /// Should only be called once per time step
     fn generate(&mut self) -> Self::Out;
+
+    /// Fill `block` with the next outputs
+    ///
+    /// The default calls `generate` for every element, generators override it when a block can
+    /// be produced faster.
+    fn generate_block(&mut self, block: &mut [Self::Out]) {
+        for x in block.iter_mut() {
+            *x = self.generate();
+        }
+    }
+}
+
+/// Oscillator whose output only depends on its phase
+pub trait Shaped: Phased {
+    /// The output at phase `phi`, range = [0, 2 * pi)
+    fn shape(&self, phi: f32) -> f32;
+}
+
+/// Block implementation for [`Shaped`] oscillators
+///
+/// Generates all phases first and shapes them afterwards, so each loop stays small.
+pub fn generate_shaped_block<O: Shaped>(osc: &mut O, block: &mut [f32]) {
+    osc.get_phase_generator().generate_block(block);
+    for x in block.iter_mut() {
+        *x = osc.shape(*x);
+    }
 }
 
 pub trait Oscillator: Generator {
@@ -31,6 +57,9 @@ pub trait Oscillator: Generator {
 
     /// Reset the internal state (e.g. phase) to initial values
     fn reset(&mut self);
+
+    /// Set the phase, range = [0, 2 * pi)
+    fn set_phase(&mut self, phase: f32);
 }
 
 // blanket implementation for phased generators, i.e. generators whose output depends on an
@@ -51,6 +80,10 @@ impl<T: Phased + Generator> Oscillator for T {
     fn reset(&mut self) {
         self.get_phase_generator().reset();
     }
+
+    fn set_phase(&mut self, phase: f32) {
+        self.get_phase_generator().set_phase(phase);
+    }
 }
 
 // // Arrays ==============================================================
@@ -0,0 +1,296 @@
+use super::traits::{Generator, Oscillator};
+use crate::{
+    stereo::{pan_gains, Stereo},
+    voice::BLOCK_SIZE,
+};
+use alloc::vec::Vec;
+use core::f32::consts::TAU;
+use micromath::F32Ext;
+use rand_core::{RngCore, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+pub const MAX_UNISON_VOICES: usize = 16;
+
+/// Distribution of the detune over the unison voices
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DetuneCurve {
+    /// The voices are spaced evenly in pitch
+    Linear,
+    /// The inner voices stay closer to the centre, which sounds denser (supersaw)
+    Exponential,
+}
+
+impl DetuneCurve {
+    /// Map a voice position in [-1, 1] to its share of the detune
+    fn map(self, x: f32) -> f32 {
+        match self {
+            DetuneCurve::Linear => x,
+            DetuneCurve::Exponential if x < 0. => 1. - 2f32.powf(-x),
+            DetuneCurve::Exponential => 2f32.powf(x) - 1.,
+        }
+    }
+}
+
+/// Phases of the voices after [`Oscillator::reset`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PhaseMode {
+    /// All voices start at phase 0, which gives every note the same attack
+    Fixed,
+    /// Every voice starts at a random phase
+    Random,
+}
+
+/// Stack of detuned oscillators that are spread across the stereo field
+///
+/// The voices are placed at positions from -1 (left, lowest detune) to 1 (right, highest detune).
+/// The centre voice is the one in the middle, or the two in the middle for an even number of
+/// voices. Its level relative to the side voices is set by the mix. With one or two voices there
+/// are no side voices and the mix has no effect.
+///
+/// The output is `[left, right]`. The voices are panned with the constant-power pan law, see
+/// [`pan_gains`].
+pub struct Unison<O> {
+    oscs: Vec<O>,
+    /// `[left, right]` gain of every voice
+    gains: Vec<Stereo>,
+    /// number of active voices, range = [1, MAX_UNISON_VOICES]
+    voices: usize,
+    /// detune of the outermost voices in cents, range = [0, inf)
+    detune: f32,
+    curve: DetuneCurve,
+    phase_mode: PhaseMode,
+    /// level of the side voices, range = [0, 1], 0 = centre voice only, 0.5 = all equal
+    mix: f32,
+    /// range = [0, 1], 0 = mono
+    width: f32,
+    /// tuning factor of the whole stack
+    tune: f32,
+    rng: XorShiftRng,
+}
+
+impl<O: Oscillator<Out = f32>> Unison<O> {
+    /// Create a unison with `voices` voices, `make` is called once for each possible voice
+    pub fn new(voices: usize, mut make: impl FnMut() -> O, seed: u32) -> Self {
+        let mut unison = Self {
+            oscs: (0..MAX_UNISON_VOICES).map(|_| make()).collect(),
+            gains: Vec::with_capacity(MAX_UNISON_VOICES),
+            voices: 1,
+            detune: 0.,
+            curve: DetuneCurve::Exponential,
+            phase_mode: PhaseMode::Random,
+            mix: 0.5,
+            width: 1.,
+            tune: 1.,
+            rng: XorShiftRng::from_seed(bytemuck::cast([seed, seed, seed, seed])),
+        };
+        unison.set_voices(voices);
+        unison.reset();
+        unison
+    }
+
+    /// All oscillators, including the inactive ones, e.g. to change their waveform
+    pub fn oscillators_mut(&mut self) -> impl Iterator<Item = &mut O> {
+        self.oscs.iter_mut()
+    }
+
+    /// The voice in the middle of the stack, e.g. as master for hard sync
+    pub fn centre_mut(&mut self) -> &mut O {
+        &mut self.oscs[self.voices / 2]
+    }
+
+    pub fn set_voices(&mut self, voices: usize) {
+        let voices = voices.clamp(1, MAX_UNISON_VOICES);
+        // voices that become active continue with their own phase, so there is no click
+        self.voices = voices;
+        self.update_tuning();
+        self.update_gains();
+    }
+
+    /// Set the detune of the outermost voices in cents
+    pub fn set_detune(&mut self, cents: f32) {
+        self.detune = cents;
+        self.update_tuning();
+    }
+
+    pub fn set_detune_curve(&mut self, curve: DetuneCurve) {
+        self.curve = curve;
+        self.update_tuning();
+    }
+
+    pub fn set_phase_mode(&mut self, mode: PhaseMode) {
+        self.phase_mode = mode;
+    }
+
+    /// Set the level of the side voices relative to the centre voice, range = [0, 1]
+    pub fn set_mix(&mut self, mix: f32) {
+        self.mix = mix.clamp(0., 1.);
+        self.update_gains();
+    }
+
+    /// Set the stereo width, range = [0, 1]
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0., 1.);
+        self.update_gains();
+    }
+
+    /// Position of voice `i` in [-1, 1]
+    fn position(&self, i: usize) -> f32 {
+        if self.voices == 1 {
+            0.
+        } else {
+            2. * i as f32 / (self.voices - 1) as f32 - 1.
+        }
+    }
+
+    fn is_centre(&self, i: usize) -> bool {
+        let n = self.voices;
+        i == n / 2 || (n % 2 == 0 && i == n / 2 - 1)
+    }
+
+    fn update_tuning(&mut self) {
+        for i in 0..self.voices {
+            let cents = self.detune * self.curve.map(self.position(i));
+            let factor = self.tune * 2f32.powf(cents / 1200.);
+            self.oscs[i].tune(factor);
+        }
+    }
+
+    fn update_gains(&mut self) {
+        // without side voices there is nothing to mix the centre with
+        let sides = self.voices > 2;
+        let level = |centre: bool| match (centre, sides) {
+            (true, true) => 1. - self.mix,
+            (true, false) => 1.,
+            (false, _) => self.mix,
+        };
+        // the voices are uncorrelated, so normalize the power of the sum
+        let power = (0..self.voices)
+            .map(|i| level(self.is_centre(i)).powi(2))
+            .sum::<f32>();
+        let norm = if power > 0. { 1. / power.sqrt() } else { 0. };
+
+        self.gains.clear();
+        for i in 0..self.voices {
+            let gain = level(self.is_centre(i)) * norm;
+            let [left, right] = pan_gains(self.position(i) * self.width);
+            self.gains.push([gain * left, gain * right]);
+        }
+    }
+}
+
+impl<O: Oscillator<Out = f32>> Generator for Unison<O> {
+    type Out = Stereo;
+
+    fn generate(&mut self) -> Stereo {
+        let mut out = [0.; 2];
+        for (osc, gain) in self.oscs.iter_mut().zip(self.gains.iter()) {
+            let x = osc.generate();
+            out[0] += x * gain[0];
+            out[1] += x * gain[1];
+        }
+        out
+    }
+
+    fn generate_block(&mut self, block: &mut [Stereo]) {
+        // one voice after the other, so the block loop of each oscillator runs
+        let mut voice = [0.; BLOCK_SIZE];
+        for chunk in block.chunks_mut(BLOCK_SIZE) {
+            let voice = &mut voice[..chunk.len()];
+            chunk.fill([0.; 2]);
+            for (osc, gain) in self.oscs.iter_mut().zip(self.gains.iter()) {
+                osc.generate_block(voice);
+                for (out, x) in chunk.iter_mut().zip(voice.iter()) {
+                    out[0] += x * gain[0];
+                    out[1] += x * gain[1];
+                }
+            }
+        }
+    }
+}
+
+impl<O: Oscillator<Out = f32>> Oscillator for Unison<O> {
+    fn tune(&mut self, tuning_factor: f32) {
+        self.tune = tuning_factor;
+        self.update_tuning();
+    }
+
+    fn set_frequency(&mut self, frequency: f32) {
+        self.oscs
+            .iter_mut()
+            .for_each(|osc| osc.set_frequency(frequency));
+    }
+
+    fn set_note(&mut self, note: u8) {
+        self.oscs.iter_mut().for_each(|osc| osc.set_note(note));
+    }
+
+    /// Restart all voices at their start phase, see [`PhaseMode`]
+    fn reset(&mut self) {
+        for osc in self.oscs.iter_mut() {
+            match self.phase_mode {
+                PhaseMode::Fixed => osc.reset(),
+                PhaseMode::Random => {
+                    let x = (self.rng.next_u32() >> 8) as f32 / (1 << 24) as f32;
+                    osc.set_phase(TAU * x);
+                }
+            }
+        }
+    }
+
+    fn set_phase(&mut self, phase: f32) {
+        self.oscs.iter_mut().for_each(|osc| osc.set_phase(phase));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::oscillators::oscillators::SineOscillator;
+
+    fn unison(voices: usize, mix: f32) -> Unison<SineOscillator> {
+        let mut unison = Unison::new(voices, || SineOscillator::new(440.), 1);
+        unison.set_mix(mix);
+        unison
+    }
+
+    fn power(unison: &Unison<SineOscillator>) -> f32 {
+        unison.gains.iter().flatten().map(|gain| gain * gain).sum()
+    }
+
+    #[test]
+    fn mix_does_not_silence_one_or_two_voices() {
+        for voices in [1, 2] {
+            for mix in [0., 0.5, 1.] {
+                let unison = unison(voices, mix);
+                assert!((power(&unison) - 1.).max(1. - power(&unison)) < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_match_the_samples() {
+        let make = || {
+            let mut unison = unison(5, 0.5);
+            unison.set_detune(20.);
+            unison.set_phase_mode(PhaseMode::Fixed);
+            unison.reset();
+            unison
+        };
+        let (mut by_sample, mut by_block) = (make(), make());
+        let mut block = [[0.; 2]; 100];
+        by_block.generate_block(&mut block);
+        for frame in block {
+            let expected = by_sample.generate();
+            for (x, y) in frame.into_iter().zip(expected) {
+                assert!((x - y).max(y - x) < 1e-5);
+            }
+        }
+    }
+
+    #[test]
+    fn full_mix_leaves_only_the_side_voices() {
+        let unison = unison(5, 1.);
+        assert_eq!(unison.gains[2], [0., 0.]);
+        assert!((power(&unison) - 1.).max(1. - power(&unison)) < 1e-4);
+    }
+}
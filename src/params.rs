@@ -0,0 +1,1075 @@
+use core::fmt;
+use micromath::F32Ext;
+
+/// Identifies a sound parameter
+///
+/// The discriminant is the index into [`PARAMS`] and the position in a serialized [`Patch`], so
+/// new parameters must only be appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[repr(u8)]
+pub enum ParamId {
+    Detune,
+    LpCutoff,
+    LpQ,
+    HpCutoff,
+    HpQ,
+    AttackTime,
+    DecayTime,
+    SustainLevel,
+    ReleaseTime,
+    GlideTime,
+    Portamento,
+    GlideMode,
+    NotePriority,
+    UnisonVoices,
+    UnisonMix,
+    StereoWidth,
+    UnisonPhase,
+    Waveform,
+    Morph,
+    SubWaveform,
+    SubOctave,
+    SubLevel,
+    Osc2Waveform,
+    Osc2Pitch,
+    Osc2Level,
+    Sync,
+    RingMix,
+    FmAmount,
+    FilterType,
+    Resonance,
+    Drive,
+    DelayTime,
+    DelayDivision,
+    DelayFeedback,
+    DelayMix,
+    DelayPingPong,
+    DelayHighCut,
+    DelayLowCut,
+    ReverbSize,
+    ReverbDamping,
+    ReverbPreDelay,
+    ReverbMix,
+    ModType,
+    ModRate,
+    ModDivision,
+    ModDepth,
+    ModFeedback,
+    ModMix,
+    DistShape,
+    DistDrive,
+    DistTone,
+    Oversampling,
+    CrushBits,
+    CrushRate,
+    Pan,
+    OutputWidth,
+    MasterGain,
+    CompThreshold,
+    CompRatio,
+    CompAttack,
+    CompRelease,
+    CompMakeup,
+    FmSource,
+    FmTarget,
+    RingSource,
+    RingTarget,
+}
+
+/// Number of parameters
+///
+/// Every parameter takes 2 bytes of a stored [`Patch`], which must fit into one preset record,
+/// i.e. up to 119 parameters. `preset::bank` checks this at compile time.
+pub const PARAM_COUNT: usize = 66;
+
+impl ParamId {
+    pub fn spec(self) -> &'static ParamSpec {
+        &PARAMS[self as usize]
+    }
+
+    pub fn from_index(index: usize) -> Option<Self> {
+        PARAMS.get(index).map(|spec| spec.id)
+    }
+
+    /// Iterate over all parameters in registry order
+    pub fn all() -> impl Iterator<Item = ParamId> {
+        PARAMS.iter().map(|spec| spec.id)
+    }
+}
+
+/// Maps a normalized control value in [0, 1] to the parameter range [min, max]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Curve {
+    /// Equal steps in value
+    Linear,
+    /// Equal steps in ratio, e.g. for frequencies and times. Requires `min > 0`.
+    Log,
+    /// Slow change at the bottom of the range and fast change at the top
+    Exp,
+    /// The given number of equally spaced values, including min and max
+    Stepped(u8),
+}
+
+const EXP_CURVATURE: f32 = 4.;
+
+impl Curve {
+    fn map(self, x: f32, min: f32, max: f32) -> f32 {
+        let x = x.clamp(0., 1.);
+        match self {
+            Curve::Linear => min + (max - min) * x,
+            Curve::Log => min * (max / min).powf(x),
+            Curve::Exp => {
+                let y = ((EXP_CURVATURE * x).exp() - 1.) / (EXP_CURVATURE.exp() - 1.);
+                min + (max - min) * y
+            }
+            Curve::Stepped(n) => {
+                let steps = n.max(2) as f32 - 1.;
+                min + (max - min) * (x * steps).round() / steps
+            }
+        }
+    }
+
+    fn unmap(self, value: f32, min: f32, max: f32) -> f32 {
+        let x = match self {
+            Curve::Linear | Curve::Stepped(_) => (value - min) / (max - min),
+            Curve::Log => (value / min).ln() / (max / min).ln(),
+            Curve::Exp => {
+                let y = (value - min) / (max - min);
+                (y * (EXP_CURVATURE.exp() - 1.) + 1.).ln() / EXP_CURVATURE
+            }
+        };
+        x.clamp(0., 1.)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Unit {
+    None,
+    Hertz,
+    Seconds,
+    Cents,
+    Semitones,
+    /// Values in [0, 1] displayed as percentage
+    Percent,
+    Decibels,
+}
+
+/// Description of a sound parameter
+#[derive(Debug)]
+pub struct ParamSpec {
+    pub id: ParamId,
+    pub name: &'static str,
+    pub unit: Unit,
+    pub min: f32,
+    pub max: f32,
+    pub curve: Curve,
+    pub default: f32,
+    /// MIDI control change bound to this parameter
+    pub cc: Option<u8>,
+    /// NRPN number bound to this parameter
+    pub nrpn: Option<u16>,
+}
+
+impl ParamSpec {
+    /// Map a normalized value in [0, 1] to the parameter range
+    pub fn from_normalized(&self, x: f32) -> f32 {
+        self.clamp(self.curve.map(x, self.min, self.max))
+    }
+
+    /// Map a value in the parameter range to [0, 1]
+    pub fn to_normalized(&self, value: f32) -> f32 {
+        self.curve.unmap(value, self.min, self.max)
+    }
+
+    /// Map a 7-bit MIDI value to the parameter range
+    pub fn from_midi(&self, value: u8) -> f32 {
+        self.from_normalized(value as f32 / 127.)
+    }
+
+    pub fn clamp(&self, value: f32) -> f32 {
+        value.clamp(self.min, self.max)
+    }
+
+    /// Format `value` with its unit for displays
+    pub fn display(&self, value: f32) -> ParamDisplay {
+        ParamDisplay {
+            unit: self.unit,
+            value,
+        }
+    }
+}
+
+pub struct ParamDisplay {
+    unit: Unit,
+    value: f32,
+}
+
+impl fmt::Display for ParamDisplay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let v = self.value;
+        match self.unit {
+            Unit::None => write!(f, "{:.2}", v),
+            Unit::Hertz if v >= 1000. => write!(f, "{:.2} kHz", v / 1000.),
+            Unit::Hertz => write!(f, "{:.0} Hz", v),
+            Unit::Seconds if v >= 1. => write!(f, "{:.2} s", v),
+            Unit::Seconds if v >= 0.01 => write!(f, "{:.0} ms", v * 1000.),
+            Unit::Seconds => write!(f, "{:.1} ms", v * 1000.),
+            Unit::Cents => write!(f, "{:.1} ct", v),
+            Unit::Semitones => write!(f, "{:.2} st", v),
+            Unit::Percent => write!(f, "{:.0} %", v * 100.),
+            Unit::Decibels => write!(f, "{:.1} dB", v),
+        }
+    }
+}
+
+/// The parameter registry
+///
+/// One entry per [`ParamId`], in the same order. CC handling, preset serialization and displays
+/// are all driven by this table.
+pub const PARAMS: [ParamSpec; PARAM_COUNT] = [
+    ParamSpec {
+        id: ParamId::Detune,
+        name: "Detune",
+        unit: Unit::Cents,
+        min: 0.,
+        max: 825.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(14),
+        nrpn: Some(0),
+    },
+    ParamSpec {
+        id: ParamId::LpCutoff,
+        name: "LP Cutoff",
+        unit: Unit::Hertz,
+        min: 128.,
+        max: 16384.,
+        curve: Curve::Log,
+        default: 440.,
+        cc: Some(15),
+        nrpn: Some(1),
+    },
+    ParamSpec {
+        id: ParamId::LpQ,
+        name: "LP Reso",
+        unit: Unit::None,
+        min: 0.5,
+        max: 12.,
+        curve: Curve::Log,
+        default: 0.72,
+        cc: Some(16),
+        nrpn: Some(2),
+    },
+    ParamSpec {
+        id: ParamId::HpCutoff,
+        name: "HP Cutoff",
+        unit: Unit::Hertz,
+        min: 16.,
+        max: 16384.,
+        curve: Curve::Log,
+        default: 440.,
+        cc: Some(17),
+        nrpn: Some(3),
+    },
+    ParamSpec {
+        id: ParamId::HpQ,
+        name: "HP Reso",
+        unit: Unit::None,
+        min: 0.5,
+        max: 12.,
+        curve: Curve::Log,
+        default: 0.72,
+        cc: Some(18),
+        nrpn: Some(4),
+    },
+    ParamSpec {
+        id: ParamId::AttackTime,
+        name: "Attack",
+        unit: Unit::Seconds,
+        min: 1e-4,
+        max: 1.,
+        curve: Curve::Log,
+        default: 0.01,
+        cc: Some(19),
+        nrpn: Some(5),
+    },
+    ParamSpec {
+        id: ParamId::DecayTime,
+        name: "Decay",
+        unit: Unit::Seconds,
+        min: 1e-4,
+        max: 1.,
+        curve: Curve::Log,
+        default: 0.01,
+        cc: Some(20),
+        nrpn: Some(6),
+    },
+    ParamSpec {
+        id: ParamId::SustainLevel,
+        name: "Sustain",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.6,
+        cc: Some(21),
+        nrpn: Some(7),
+    },
+    ParamSpec {
+        id: ParamId::ReleaseTime,
+        name: "Release",
+        unit: Unit::Seconds,
+        min: 1e-4,
+        max: 1.,
+        curve: Curve::Log,
+        default: 0.2,
+        cc: Some(22),
+        nrpn: Some(8),
+    },
+    ParamSpec {
+        id: ParamId::GlideTime,
+        name: "Glide",
+        unit: Unit::Seconds,
+        min: 1e-3,
+        max: 5.,
+        curve: Curve::Log,
+        default: 0.1,
+        cc: Some(5),
+        nrpn: Some(9),
+    },
+    ParamSpec {
+        id: ParamId::Portamento,
+        name: "Portamento",
+        unit: Unit::None,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Stepped(2),
+        default: 0.,
+        cc: Some(65),
+        nrpn: Some(10),
+    },
+    ParamSpec {
+        id: ParamId::GlideMode,
+        name: "Glide Mode",
+        unit: Unit::None,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Stepped(2),
+        default: 0.,
+        cc: None,
+        nrpn: Some(11),
+    },
+    ParamSpec {
+        id: ParamId::NotePriority,
+        name: "Priority",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 0.,
+        cc: None,
+        nrpn: Some(12),
+    },
+    ParamSpec {
+        id: ParamId::UnisonVoices,
+        name: "Voices",
+        unit: Unit::None,
+        min: 1.,
+        max: 16.,
+        curve: Curve::Stepped(16),
+        default: 4.,
+        cc: Some(24),
+        nrpn: Some(13),
+    },
+    ParamSpec {
+        id: ParamId::UnisonMix,
+        name: "Unison Mix",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.5,
+        cc: Some(25),
+        nrpn: Some(14),
+    },
+    ParamSpec {
+        id: ParamId::StereoWidth,
+        name: "Width",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.8,
+        cc: Some(26),
+        nrpn: Some(15),
+    },
+    ParamSpec {
+        id: ParamId::UnisonPhase,
+        name: "Random Phase",
+        unit: Unit::None,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Stepped(2),
+        default: 1.,
+        cc: None,
+        nrpn: Some(16),
+    },
+    ParamSpec {
+        id: ParamId::Waveform,
+        name: "Waveform",
+        unit: Unit::None,
+        min: 0.,
+        max: 4.,
+        curve: Curve::Stepped(5),
+        default: 2.,
+        cc: Some(27),
+        nrpn: Some(17),
+    },
+    ParamSpec {
+        id: ParamId::Morph,
+        name: "Morph",
+        unit: Unit::None,
+        min: 0.,
+        max: 3.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(28),
+        nrpn: Some(18),
+    },
+    ParamSpec {
+        id: ParamId::SubWaveform,
+        name: "Sub Waveform",
+        unit: Unit::None,
+        min: 0.,
+        max: 4.,
+        curve: Curve::Stepped(5),
+        default: 3.,
+        cc: Some(29),
+        nrpn: Some(19),
+    },
+    ParamSpec {
+        id: ParamId::SubOctave,
+        name: "Sub Octave",
+        unit: Unit::None,
+        min: 1.,
+        max: 2.,
+        curve: Curve::Stepped(2),
+        default: 1.,
+        cc: None,
+        nrpn: Some(20),
+    },
+    ParamSpec {
+        id: ParamId::SubLevel,
+        name: "Sub Level",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.5,
+        cc: Some(30),
+        nrpn: Some(21),
+    },
+    ParamSpec {
+        id: ParamId::Osc2Waveform,
+        name: "Osc 2 Waveform",
+        unit: Unit::None,
+        min: 0.,
+        max: 4.,
+        curve: Curve::Stepped(5),
+        default: 2.,
+        cc: Some(85),
+        nrpn: Some(22),
+    },
+    ParamSpec {
+        id: ParamId::Osc2Pitch,
+        name: "Osc 2 Pitch",
+        unit: Unit::Semitones,
+        min: -24.,
+        max: 24.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(86),
+        nrpn: Some(23),
+    },
+    ParamSpec {
+        id: ParamId::Osc2Level,
+        name: "Osc 2 Level",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(87),
+        nrpn: Some(24),
+    },
+    ParamSpec {
+        id: ParamId::Sync,
+        name: "Sync",
+        unit: Unit::None,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Stepped(2),
+        default: 0.,
+        cc: Some(88),
+        nrpn: Some(25),
+    },
+    ParamSpec {
+        id: ParamId::RingMix,
+        name: "Ring Mod",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(89),
+        nrpn: Some(26),
+    },
+    ParamSpec {
+        id: ParamId::FmAmount,
+        name: "FM",
+        unit: Unit::None,
+        min: 0.,
+        max: 4.,
+        curve: Curve::Exp,
+        default: 0.,
+        cc: Some(90),
+        nrpn: Some(27),
+    },
+    ParamSpec {
+        id: ParamId::FilterType,
+        name: "Filter Type",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 0.,
+        cc: None,
+        nrpn: Some(28),
+    },
+    ParamSpec {
+        id: ParamId::Resonance,
+        name: "Ladder Reso",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.1,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(71),
+        nrpn: Some(29),
+    },
+    ParamSpec {
+        id: ParamId::Drive,
+        name: "Drive",
+        unit: Unit::None,
+        min: 1.,
+        max: 16.,
+        curve: Curve::Log,
+        default: 1.,
+        cc: Some(75),
+        nrpn: Some(30),
+    },
+    ParamSpec {
+        id: ParamId::DelayTime,
+        name: "Delay Time",
+        unit: Unit::Seconds,
+        min: 1e-3,
+        max: 2.,
+        curve: Curve::Log,
+        default: 0.3,
+        cc: Some(12),
+        nrpn: Some(31),
+    },
+    ParamSpec {
+        id: ParamId::DelayDivision,
+        name: "Delay Sync",
+        unit: Unit::None,
+        min: 0.,
+        max: 13.,
+        curve: Curve::Stepped(14),
+        default: 0.,
+        cc: Some(106),
+        nrpn: Some(32),
+    },
+    ParamSpec {
+        id: ParamId::DelayFeedback,
+        name: "Delay Feedback",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 0.98,
+        curve: Curve::Linear,
+        default: 0.35,
+        cc: Some(13),
+        nrpn: Some(33),
+    },
+    ParamSpec {
+        id: ParamId::DelayMix,
+        name: "Delay Mix",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(105),
+        nrpn: Some(34),
+    },
+    ParamSpec {
+        id: ParamId::DelayPingPong,
+        name: "Ping Pong",
+        unit: Unit::None,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Stepped(2),
+        default: 0.,
+        cc: Some(107),
+        nrpn: Some(35),
+    },
+    ParamSpec {
+        id: ParamId::DelayHighCut,
+        name: "Delay High Cut",
+        unit: Unit::Hertz,
+        min: 500.,
+        max: 16384.,
+        curve: Curve::Log,
+        default: 8000.,
+        cc: None,
+        nrpn: Some(36),
+    },
+    ParamSpec {
+        id: ParamId::DelayLowCut,
+        name: "Delay Low Cut",
+        unit: Unit::Hertz,
+        min: 16.,
+        max: 2000.,
+        curve: Curve::Log,
+        default: 60.,
+        cc: None,
+        nrpn: Some(37),
+    },
+    ParamSpec {
+        id: ParamId::ReverbSize,
+        name: "Room Size",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.5,
+        cc: Some(93),
+        nrpn: Some(38),
+    },
+    ParamSpec {
+        id: ParamId::ReverbDamping,
+        name: "Reverb Damping",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.5,
+        cc: None,
+        nrpn: Some(39),
+    },
+    ParamSpec {
+        id: ParamId::ReverbPreDelay,
+        name: "Pre-Delay",
+        unit: Unit::Seconds,
+        min: 0.,
+        max: 0.05,
+        curve: Curve::Linear,
+        default: 0.01,
+        cc: None,
+        nrpn: Some(40),
+    },
+    ParamSpec {
+        id: ParamId::ReverbMix,
+        name: "Reverb Mix",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(91),
+        nrpn: Some(41),
+    },
+    ParamSpec {
+        id: ParamId::ModType,
+        name: "Mod FX",
+        unit: Unit::None,
+        min: 0.,
+        max: 3.,
+        curve: Curve::Stepped(4),
+        default: 0.,
+        cc: Some(111),
+        nrpn: Some(42),
+    },
+    ParamSpec {
+        id: ParamId::ModRate,
+        name: "Mod Rate",
+        unit: Unit::Hertz,
+        min: 0.02,
+        max: 10.,
+        curve: Curve::Log,
+        default: 0.5,
+        cc: Some(108),
+        nrpn: Some(43),
+    },
+    ParamSpec {
+        id: ParamId::ModDivision,
+        name: "Mod Sync",
+        unit: Unit::None,
+        min: 0.,
+        max: 13.,
+        curve: Curve::Stepped(14),
+        default: 0.,
+        cc: None,
+        nrpn: Some(44),
+    },
+    ParamSpec {
+        id: ParamId::ModDepth,
+        name: "Mod Depth",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.5,
+        cc: Some(109),
+        nrpn: Some(45),
+    },
+    ParamSpec {
+        id: ParamId::ModFeedback,
+        name: "Mod Feedback",
+        unit: Unit::Percent,
+        min: -0.95,
+        max: 0.95,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(110),
+        nrpn: Some(46),
+    },
+    ParamSpec {
+        id: ParamId::ModMix,
+        name: "Mod Mix",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.5,
+        cc: Some(92),
+        nrpn: Some(47),
+    },
+    ParamSpec {
+        id: ParamId::DistShape,
+        name: "Distortion",
+        unit: Unit::None,
+        min: 0.,
+        max: 5.,
+        curve: Curve::Stepped(6),
+        default: 0.,
+        cc: Some(112),
+        nrpn: Some(48),
+    },
+    ParamSpec {
+        id: ParamId::DistDrive,
+        name: "Dist Drive",
+        unit: Unit::None,
+        min: 1.,
+        max: 32.,
+        curve: Curve::Log,
+        default: 2.,
+        cc: Some(113),
+        nrpn: Some(49),
+    },
+    ParamSpec {
+        id: ParamId::DistTone,
+        name: "Dist Tone",
+        unit: Unit::Hertz,
+        min: 500.,
+        max: 16000.,
+        curve: Curve::Log,
+        default: 16000.,
+        cc: Some(114),
+        nrpn: Some(50),
+    },
+    ParamSpec {
+        id: ParamId::Oversampling,
+        name: "Oversampling",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 1.,
+        cc: None,
+        nrpn: Some(51),
+    },
+    ParamSpec {
+        id: ParamId::CrushBits,
+        name: "Bit Depth",
+        unit: Unit::None,
+        min: 1.,
+        max: 16.,
+        curve: Curve::Stepped(16),
+        default: 16.,
+        cc: Some(115),
+        nrpn: Some(52),
+    },
+    ParamSpec {
+        id: ParamId::CrushRate,
+        name: "Crush Rate",
+        unit: Unit::Hertz,
+        min: 500.,
+        // the highest sample rate, the default leaves the rate alone at every sample rate
+        max: 96000.,
+        curve: Curve::Log,
+        default: 96000.,
+        cc: Some(116),
+        nrpn: Some(53),
+    },
+    ParamSpec {
+        id: ParamId::Pan,
+        name: "Pan",
+        unit: Unit::None,
+        min: -1.,
+        max: 1.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(10),
+        nrpn: Some(54),
+    },
+    ParamSpec {
+        id: ParamId::OutputWidth,
+        name: "Output Width",
+        unit: Unit::Percent,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Linear,
+        default: 1.,
+        cc: None,
+        nrpn: Some(55),
+    },
+    ParamSpec {
+        id: ParamId::MasterGain,
+        name: "Master Gain",
+        unit: Unit::Decibels,
+        min: -24.,
+        max: 12.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: Some(7),
+        nrpn: Some(56),
+    },
+    ParamSpec {
+        id: ParamId::CompThreshold,
+        name: "Comp Threshold",
+        unit: Unit::Decibels,
+        min: -40.,
+        max: 0.,
+        curve: Curve::Linear,
+        default: -12.,
+        cc: None,
+        nrpn: Some(57),
+    },
+    ParamSpec {
+        id: ParamId::CompRatio,
+        name: "Comp Ratio",
+        unit: Unit::None,
+        min: 1.,
+        max: 20.,
+        curve: Curve::Log,
+        default: 1.,
+        cc: None,
+        nrpn: Some(58),
+    },
+    ParamSpec {
+        id: ParamId::CompAttack,
+        name: "Comp Attack",
+        unit: Unit::Seconds,
+        min: 1e-4,
+        max: 0.1,
+        curve: Curve::Log,
+        default: 0.005,
+        cc: None,
+        nrpn: Some(59),
+    },
+    ParamSpec {
+        id: ParamId::CompRelease,
+        name: "Comp Release",
+        unit: Unit::Seconds,
+        min: 0.01,
+        max: 1.,
+        curve: Curve::Log,
+        default: 0.1,
+        cc: None,
+        nrpn: Some(60),
+    },
+    ParamSpec {
+        id: ParamId::CompMakeup,
+        name: "Comp Makeup",
+        unit: Unit::Decibels,
+        min: 0.,
+        max: 24.,
+        curve: Curve::Linear,
+        default: 0.,
+        cc: None,
+        nrpn: Some(61),
+    },
+    // oscillator routing: 0 = osc 1 (the unison), 1 = osc 2, 2 = sub
+    ParamSpec {
+        id: ParamId::FmSource,
+        name: "FM Source",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 1.,
+        cc: None,
+        nrpn: Some(62),
+    },
+    ParamSpec {
+        id: ParamId::FmTarget,
+        name: "FM Target",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 0.,
+        cc: None,
+        nrpn: Some(63),
+    },
+    ParamSpec {
+        id: ParamId::RingSource,
+        name: "Ring Source",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 0.,
+        cc: None,
+        nrpn: Some(64),
+    },
+    ParamSpec {
+        id: ParamId::RingTarget,
+        name: "Ring Target",
+        unit: Unit::None,
+        min: 0.,
+        max: 2.,
+        curve: Curve::Stepped(3),
+        default: 1.,
+        cc: None,
+        nrpn: Some(65),
+    },
+];
+
+/// Control changes that move several parameters at once
+pub const CC_MACROS: [(u8, &[ParamId]); 1] = [(
+    23,
+    &[
+        ParamId::AttackTime,
+        ParamId::DecayTime,
+        ParamId::ReleaseTime,
+    ],
+)];
+
+// The registry must be in `ParamId` order
+const _: () = {
+    let mut i = 0;
+    while i < PARAM_COUNT {
+        assert!(PARAMS[i].id as usize == i);
+        i += 1;
+    }
+};
+
+/// All parameters bound to control change `control`
+pub fn params_for_cc(control: u8) -> impl Iterator<Item = ParamId> {
+    let direct = PARAMS
+        .iter()
+        .filter(move |spec| spec.cc == Some(control))
+        .map(|spec| spec.id);
+    let macros = CC_MACROS
+        .iter()
+        .filter(move |(cc, _)| *cc == control)
+        .flat_map(|(_, ids)| ids.iter().copied());
+    direct.chain(macros)
+}
+
+/// The parameter bound to NRPN number `nrpn`
+pub fn param_for_nrpn(nrpn: u16) -> Option<ParamId> {
+    PARAMS
+        .iter()
+        .find(|spec| spec.nrpn == Some(nrpn))
+        .map(|spec| spec.id)
+}
+
+/// Values of all parameters, e.g. the content of a preset slot
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Patch {
+    values: [f32; PARAM_COUNT],
+}
+
+impl Patch {
+    /// All parameters at their default value
+    pub const DEFAULT: Patch = {
+        let mut values = [0.; PARAM_COUNT];
+        let mut i = 0;
+        while i < PARAM_COUNT {
+            values[i] = PARAMS[i].default;
+            i += 1;
+        }
+        Patch { values }
+    };
+
+    /// Number of bytes in the serialized representation
+    pub const SIZE: usize = 1 + 2 * PARAM_COUNT;
+
+    pub fn get(&self, id: ParamId) -> f32 {
+        self.values[id as usize]
+    }
+
+    pub fn set(&mut self, id: ParamId, value: f32) {
+        self.values[id as usize] = id.spec().clamp(value);
+    }
+
+    /// Builder-style [`Patch::set`] for use in constants
+    ///
+    /// The value is not clamped.
+    pub const fn with(mut self, id: ParamId, value: f32) -> Self {
+        self.values[id as usize] = value;
+        self
+    }
+
+    /// Serialize as parameter count followed by the normalized values as little-endian `u16`s
+    pub fn to_bytes(&self) -> [u8; Self::SIZE] {
+        let mut bytes = [0; Self::SIZE];
+        bytes[0] = PARAM_COUNT as u8;
+        for (chunk, spec) in bytes[1..].chunks_exact_mut(2).zip(PARAMS.iter()) {
+            let x = spec.to_normalized(self.get(spec.id));
+            let q = (x * u16::MAX as f32).round() as u16;
+            chunk.copy_from_slice(&q.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Deserialize from the representation produced by [`Patch::to_bytes`]
+    ///
+    /// Parameters missing in `bytes`, e.g. because they were added after the patch was stored,
+    /// keep their default value. Returns `None` if `bytes` is malformed.
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (&count, values) = bytes.split_first()?;
+        if values.len() < 2 * count as usize {
+            return None;
+        }
+        let mut patch = Self::DEFAULT;
+        for (chunk, spec) in values
+            .chunks_exact(2)
+            .take(count as usize)
+            .zip(PARAMS.iter())
+        {
+            let q = u16::from_le_bytes([chunk[0], chunk[1]]);
+            patch.set(spec.id, spec.from_normalized(q as f32 / u16::MAX as f32));
+        }
+        Some(patch)
+    }
+}
+
+impl Default for Patch {
+    fn default() -> Self {
+        Self::DEFAULT
+    }
+}
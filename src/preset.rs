@@ -0,0 +1,6 @@
+pub mod bank;
+pub mod factory;
+pub mod log;
+pub mod storage;
+
+pub use bank::*;
@@ -0,0 +1,154 @@
+use midi_msg::{Channel, ChannelVoiceMsg, ControlChange, MidiMsg};
+
+use super::{
+    factory::FACTORY_PRESETS,
+    log::{RecordId, RecordLog, MAX_PAYLOAD},
+    storage::{Flash, FlashError},
+};
+use crate::{params::Patch, println, voice::Instrument};
+
+/// Number of program slots
+pub const PRESET_SLOTS: u8 = 128;
+
+/// Control change that stores the current parameters into the selected program slot
+///
+/// The preset is stored when the value crosses from below 64 to 64 or above, so it can be
+/// triggered by a momentary button.
+pub const STORE_CC: u8 = 102;
+
+// A patch must fit into a single record, or `store` fails for every slot
+const _: () = assert!(Patch::SIZE <= MAX_PAYLOAD);
+
+const KIND_PATCH: u8 = 1;
+const KIND_LEARN: u8 = 2;
+
+/// Bank of presets stored in flash
+///
+/// Program slots without a stored preset fall back to the [`FACTORY_PRESETS`].
+pub struct PresetBank<F: Flash> {
+    log: RecordLog<F>,
+    program: u8,
+    store_pressed: bool,
+}
+
+impl<F: Flash> PresetBank<F> {
+    pub fn mount(flash: F) -> Result<Self, FlashError> {
+        Ok(Self {
+            log: RecordLog::mount(flash)?,
+            program: 0,
+            store_pressed: false,
+        })
+    }
+
+    /// Load the preset in `slot`
+    ///
+    /// Returns `None` if the slot is empty or its record can't be read.
+    pub fn load(&mut self, slot: u8) -> Option<Patch> {
+        let mut payload = [0; MAX_PAYLOAD];
+        match self.log.read(Self::record_id(slot), &mut payload) {
+            Ok(Some(len)) => Patch::from_bytes(&payload[..len]),
+            Ok(None) => FACTORY_PRESETS.get(slot as usize).map(|(_, patch)| *patch),
+            Err(e) => {
+                println!("preset {}: read error {:?}", slot, e);
+                None
+            }
+        }
+    }
+
+    /// Store `patch` in `slot`
+    pub fn store(&mut self, slot: u8, patch: &Patch) -> Result<(), FlashError> {
+        if slot >= PRESET_SLOTS {
+            return Err(FlashError::OutOfBounds);
+        }
+        self.log.write(Self::record_id(slot), &patch.to_bytes())
+    }
+
+    /// Name of the preset in `slot`, if it is a factory preset that hasn't been overwritten
+    pub fn factory_name(&self, slot: u8) -> Option<&'static str> {
+        if self.log.contains(Self::record_id(slot)) {
+            None
+        } else {
+            FACTORY_PRESETS.get(slot as usize).map(|(name, _)| *name)
+        }
+    }
+
+    /// Restore the MIDI learn bindings stored in flash
+    pub fn restore_learn<I: Instrument>(&mut self, instrument: &mut I) {
+        let mut payload = [0; MAX_PAYLOAD];
+        match self.log.read(Self::learn_id(), &mut payload) {
+            Ok(Some(len)) => instrument.learn().load_bytes(&payload[..len]),
+            Ok(None) => {}
+            Err(e) => println!("learn bindings: read error {:?}", e),
+        }
+    }
+
+    /// Currently selected program slot
+    pub fn program(&self) -> u8 {
+        self.program
+    }
+
+    /// Persist the MIDI learn bindings, as returned by [`crate::midi::learn::MidiLearn::to_bytes`]
+    pub fn store_learn(&mut self, bytes: &[u8]) {
+        match self.log.write(Self::learn_id(), bytes) {
+            Ok(()) => println!("learn: stored {} bindings", bytes.len() / 2),
+            Err(e) => println!("learn bindings: write error {:?}", e),
+        }
+    }
+
+    /// React to Program Change and [`STORE_CC`] events
+    ///
+    /// `patch` is the current sound, which [`STORE_CC`] stores. Returns the preset to load after
+    /// a Program Change.
+    pub fn handle_midi(&mut self, msg: &MidiMsg, patch: &Patch) -> Option<Patch> {
+        let MidiMsg::ChannelVoice {
+            channel: Channel::Ch1,
+            msg,
+        } = msg
+        else {
+            return None;
+        };
+        match *msg {
+            ChannelVoiceMsg::ProgramChange { program } => {
+                self.program = program;
+                let patch = self.load(program);
+                match patch {
+                    Some(_) => println!("load preset {}", program),
+                    None => println!("preset {} is empty", program),
+                }
+                patch
+            }
+            ChannelVoiceMsg::ControlChange {
+                control:
+                    ControlChange::CC {
+                        control: STORE_CC,
+                        value,
+                    },
+            } => {
+                let pressed = value >= 64;
+                if pressed && !self.store_pressed {
+                    match self.store(self.program, patch) {
+                        Ok(()) => println!("stored preset {}", self.program),
+                        Err(e) => println!("preset {}: write error {:?}", self.program, e),
+                    }
+                }
+                self.store_pressed = pressed;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn record_id(slot: u8) -> RecordId {
+        RecordId {
+            kind: KIND_PATCH,
+            key: slot,
+        }
+    }
+
+    fn learn_id() -> RecordId {
+        RecordId {
+            kind: KIND_LEARN,
+            key: 0,
+        }
+    }
+}
@@ -0,0 +1,57 @@
+use crate::params::{ParamId::*, Patch};
+
+/// Presets compiled into the firmware
+///
+/// They occupy the first program slots until they are overwritten by a stored preset.
+pub const FACTORY_PRESETS: [(&str, Patch); 5] = [
+    ("Init", Patch::DEFAULT),
+    (
+        "Super Saw",
+        Patch::DEFAULT
+            .with(Detune, 10.)
+            .with(LpCutoff, 4000.)
+            .with(HpCutoff, 80.)
+            .with(AttackTime, 0.02)
+            .with(DecayTime, 0.3)
+            .with(SustainLevel, 0.8)
+            .with(ReleaseTime, 0.4),
+    ),
+    (
+        "Pluck",
+        Patch::DEFAULT
+            .with(Detune, 3.5)
+            .with(LpCutoff, 2500.)
+            .with(LpQ, 2.)
+            .with(HpCutoff, 120.)
+            .with(AttackTime, 0.001)
+            .with(DecayTime, 0.15)
+            .with(SustainLevel, 0.05)
+            .with(ReleaseTime, 0.1),
+    ),
+    (
+        "Pad",
+        Patch::DEFAULT
+            .with(Detune, 17.)
+            .with(LpCutoff, 1200.)
+            .with(HpCutoff, 60.)
+            .with(AttackTime, 0.8)
+            .with(DecayTime, 1.)
+            .with(SustainLevel, 0.9)
+            .with(ReleaseTime, 1.),
+    ),
+    (
+        "Bass",
+        Patch::DEFAULT
+            .with(Detune, 1.7)
+            .with(LpCutoff, 600.)
+            .with(LpQ, 3.)
+            .with(HpCutoff, 30.)
+            .with(AttackTime, 0.005)
+            .with(DecayTime, 0.2)
+            .with(SustainLevel, 0.7)
+            .with(ReleaseTime, 0.05)
+            .with(GlideTime, 0.06)
+            .with(Portamento, 1.)
+            .with(NotePriority, 1.),
+    ),
+];
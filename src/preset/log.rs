@@ -0,0 +1,420 @@
+use alloc::collections::BTreeMap;
+
+use super::storage::{crc32, Flash, FlashError};
+use crate::println;
+
+/// Size of a record in flash, including header and checksum
+///
+/// A record holds a whole [`crate::params::Patch`]. Changing the size invalidates the records
+/// already in flash, the log is formatted on the next mount.
+pub const RECORD_SIZE: usize = 256;
+const HEADER_SIZE: usize = 12;
+const CRC_SIZE: usize = 4;
+/// Maximum number of payload bytes per record
+pub const MAX_PAYLOAD: usize = RECORD_SIZE - HEADER_SIZE - CRC_SIZE;
+const MAGIC: u16 = 0x5350;
+
+/// Identifies what a record contains: the record kind and a key within that kind (e.g. a slot)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecordId {
+    pub kind: u8,
+    pub key: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Location {
+    offset: u32,
+    seq: u32,
+}
+
+/// Append-only, wear-leveled log of checksummed records
+///
+/// Records are written one after another into the sectors of the flash, wrapping around at the
+/// end. A newer record (higher sequence number) supersedes all older records with the same
+/// [`RecordId`]. The sector following the one currently written to is always kept erased. Before
+/// the log moves on to the next sector, the records in the oldest sector that are still current
+/// are copied forward and the sector is erased. This way every sector gets erased at the same
+/// rate. When the oldest sector holds only current records, they fill the new sector and the log
+/// moves on again. It is full when all sectors but the spare hold only current records.
+///
+/// Torn writes and corrupted records are detected by the CRC and ignored, so after a power loss
+/// the previous version of a record is still available.
+pub struct RecordLog<F: Flash> {
+    flash: F,
+    index: BTreeMap<RecordId, Location>,
+    head_sector: u32,
+    head_slot: u32,
+    next_seq: u32,
+}
+
+impl<F: Flash> RecordLog<F> {
+    const RECORDS_PER_SECTOR: u32 = F::SECTOR_SIZE / RECORD_SIZE as u32;
+
+    /// Scan the flash and rebuild the index of current records
+    ///
+    /// A flash that contains no valid record at all is formatted.
+    pub fn mount(flash: F) -> Result<Self, FlashError> {
+        let mut log = Self {
+            flash,
+            index: BTreeMap::new(),
+            head_sector: 0,
+            head_slot: 0,
+            next_seq: 0,
+        };
+        if log.flash.sectors() < 2 {
+            return Err(FlashError::OutOfBounds);
+        }
+
+        let mut newest: Option<Location> = None;
+        for offset in log.record_offsets() {
+            let mut record = [0; RECORD_SIZE];
+            log.flash.read(offset, &mut record)?;
+            if let Some((id, seq, _)) = parse(&record) {
+                let location = Location { offset, seq };
+                if log.index.get(&id).map_or(true, |l| l.seq < seq) {
+                    log.index.insert(id, location);
+                }
+                if newest.map_or(true, |l| l.seq < seq) {
+                    newest = Some(location);
+                }
+            }
+        }
+
+        match newest {
+            None => {
+                println!("preset storage: no records found, formatting");
+                for sector in 0..log.flash.sectors() {
+                    if !log.is_sector_blank(sector)? {
+                        log.flash.erase(sector)?;
+                    }
+                }
+            }
+            Some(newest) => {
+                log.next_seq = newest.seq.wrapping_add(1);
+                log.head_sector = newest.offset / F::SECTOR_SIZE;
+                log.head_slot = (newest.offset % F::SECTOR_SIZE) / RECORD_SIZE as u32 + 1;
+                // skip garbage left behind by interrupted writes
+                while log.head_slot < Self::RECORDS_PER_SECTOR
+                    && !log.is_slot_blank(log.head_sector, log.head_slot)?
+                {
+                    log.head_slot += 1;
+                }
+                // an interrupted reclaim may have left the spare sector dirty
+                let spare = log.next_sector(log.head_sector);
+                if !log.is_sector_blank(spare)? {
+                    log.reclaim(spare)?;
+                }
+            }
+        }
+        Ok(log)
+    }
+
+    /// Read the current payload of record `id` into `payload`
+    ///
+    /// Returns the payload length, or `None` if there is no valid record with this id.
+    pub fn read(
+        &mut self,
+        id: RecordId,
+        payload: &mut [u8; MAX_PAYLOAD],
+    ) -> Result<Option<usize>, FlashError> {
+        let Some(location) = self.index.get(&id).copied() else {
+            return Ok(None);
+        };
+        let mut record = [0; RECORD_SIZE];
+        self.flash.read(location.offset, &mut record)?;
+        // the record was valid when mounting, but the flash might have changed since
+        Ok(parse(&record).map(|(_, _, data)| {
+            payload[..data.len()].copy_from_slice(data);
+            data.len()
+        }))
+    }
+
+    /// Append a new version of record `id`
+    pub fn write(&mut self, id: RecordId, payload: &[u8]) -> Result<(), FlashError> {
+        if payload.len() > MAX_PAYLOAD {
+            return Err(FlashError::OutOfBounds);
+        }
+        // a reclaimed sector full of current records fills the new head right away
+        let mut advances = 0;
+        while self.head_slot >= Self::RECORDS_PER_SECTOR {
+            if advances == self.flash.sectors() {
+                return Err(FlashError::Full);
+            }
+            self.advance()?;
+            advances += 1;
+        }
+        self.append(id, payload)
+    }
+
+    /// Whether there is a valid record with this id
+    pub fn contains(&self, id: RecordId) -> bool {
+        self.index.contains_key(&id)
+    }
+
+    /// Give back the underlying flash
+    pub fn release(self) -> F {
+        self.flash
+    }
+
+    fn append(&mut self, id: RecordId, payload: &[u8]) -> Result<(), FlashError> {
+        let mut record = [0xFF; RECORD_SIZE];
+        record[0..2].copy_from_slice(&MAGIC.to_le_bytes());
+        record[2] = id.kind;
+        record[3] = id.key;
+        record[4..8].copy_from_slice(&self.next_seq.to_le_bytes());
+        record[8..10].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+        record[HEADER_SIZE..HEADER_SIZE + payload.len()].copy_from_slice(payload);
+        let crc = crc32(&record[..RECORD_SIZE - CRC_SIZE]);
+        record[RECORD_SIZE - CRC_SIZE..].copy_from_slice(&crc.to_le_bytes());
+
+        let offset = self.head_sector * F::SECTOR_SIZE + self.head_slot * RECORD_SIZE as u32;
+        self.flash.write(offset, &record)?;
+        self.head_slot += 1;
+        self.index.insert(
+            id,
+            Location {
+                offset,
+                seq: self.next_seq,
+            },
+        );
+        self.next_seq = self.next_seq.wrapping_add(1);
+        Ok(())
+    }
+
+    /// Move the head into the spare sector and prepare a new spare sector
+    fn advance(&mut self) -> Result<(), FlashError> {
+        self.head_sector = self.next_sector(self.head_sector);
+        self.head_slot = 0;
+        let spare = self.next_sector(self.head_sector);
+        self.reclaim(spare)
+    }
+
+    /// Copy the current records of `sector` to the head and erase it
+    ///
+    /// The head sector always has room for these records: it was erased when the head moved into
+    /// it, and `sector` can hold no more records than that.
+    fn reclaim(&mut self, sector: u32) -> Result<(), FlashError> {
+        let start = sector * F::SECTOR_SIZE;
+        let end = start + F::SECTOR_SIZE;
+        let live: alloc::vec::Vec<RecordId> = self
+            .index
+            .iter()
+            .filter(|(_, l)| (start..end).contains(&l.offset))
+            .map(|(id, _)| *id)
+            .collect();
+        for id in live {
+            if self.head_slot >= Self::RECORDS_PER_SECTOR {
+                return Err(FlashError::OutOfBounds);
+            }
+            let mut payload = [0; MAX_PAYLOAD];
+            if let Some(len) = self.read(id, &mut payload)? {
+                self.append(id, &payload[..len])?;
+            } else {
+                self.index.remove(&id);
+            }
+        }
+        self.flash.erase(sector)
+    }
+
+    fn next_sector(&self, sector: u32) -> u32 {
+        (sector + 1) % self.flash.sectors()
+    }
+
+    fn record_offsets(&self) -> impl Iterator<Item = u32> {
+        let count = self.flash.sectors() * Self::RECORDS_PER_SECTOR;
+        (0..count).map(|i| {
+            let sector = i / Self::RECORDS_PER_SECTOR;
+            let slot = i % Self::RECORDS_PER_SECTOR;
+            sector * F::SECTOR_SIZE + slot * RECORD_SIZE as u32
+        })
+    }
+
+    fn is_slot_blank(&mut self, sector: u32, slot: u32) -> Result<bool, FlashError> {
+        let mut record = [0; RECORD_SIZE];
+        self.flash.read(
+            sector * F::SECTOR_SIZE + slot * RECORD_SIZE as u32,
+            &mut record,
+        )?;
+        Ok(record.iter().all(|&b| b == 0xFF))
+    }
+
+    fn is_sector_blank(&mut self, sector: u32) -> Result<bool, FlashError> {
+        for slot in 0..Self::RECORDS_PER_SECTOR {
+            if !self.is_slot_blank(sector, slot)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// Validate a raw record and split it into id, sequence number and payload
+fn parse(record: &[u8; RECORD_SIZE]) -> Option<(RecordId, u32, &[u8])> {
+    let magic = u16::from_le_bytes([record[0], record[1]]);
+    let len = u16::from_le_bytes([record[8], record[9]]) as usize;
+    if magic != MAGIC || len > MAX_PAYLOAD {
+        return None;
+    }
+    let crc = u32::from_le_bytes(record[RECORD_SIZE - CRC_SIZE..].try_into().unwrap());
+    if crc != crc32(&record[..RECORD_SIZE - CRC_SIZE]) {
+        return None;
+    }
+    let id = RecordId {
+        kind: record[2],
+        key: record[3],
+    };
+    let seq = u32::from_le_bytes(record[4..8].try_into().unwrap());
+    Some((id, seq, &record[HEADER_SIZE..HEADER_SIZE + len]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::preset::storage::RamFlash;
+
+    const SECTOR_SIZE: u32 = 4 * RECORD_SIZE as u32;
+    const A: RecordId = RecordId { kind: 1, key: 0 };
+    const B: RecordId = RecordId { kind: 1, key: 1 };
+
+    fn mount(sectors: u32) -> RecordLog<RamFlash<SECTOR_SIZE>> {
+        RecordLog::mount(RamFlash::new(sectors)).unwrap()
+    }
+
+    fn remount(log: RecordLog<RamFlash<SECTOR_SIZE>>) -> RecordLog<RamFlash<SECTOR_SIZE>> {
+        RecordLog::mount(log.release()).unwrap()
+    }
+
+    fn read(
+        log: &mut RecordLog<RamFlash<SECTOR_SIZE>>,
+        id: RecordId,
+    ) -> Option<alloc::vec::Vec<u8>> {
+        let mut payload = [0; MAX_PAYLOAD];
+        let len = log.read(id, &mut payload).unwrap()?;
+        Some(payload[..len].to_vec())
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut log = mount(4);
+        assert_eq!(read(&mut log, A), None);
+        log.write(A, &[1, 2, 3]).unwrap();
+        log.write(B, &[4]).unwrap();
+        assert_eq!(read(&mut log, A).unwrap(), [1, 2, 3]);
+
+        let mut log = remount(log);
+        assert_eq!(read(&mut log, A).unwrap(), [1, 2, 3]);
+        assert_eq!(read(&mut log, B).unwrap(), [4]);
+    }
+
+    #[test]
+    fn corrupted_record_falls_back_to_older_version() {
+        let mut log = mount(4);
+        log.write(A, &[1]).unwrap();
+        log.write(A, &[2]).unwrap();
+
+        // flip a bit in the CRC of the second record
+        let mut flash = log.release();
+        flash.bytes_mut()[2 * RECORD_SIZE - 1] ^= 0x01;
+        let mut log = RecordLog::mount(flash).unwrap();
+        assert_eq!(read(&mut log, A).unwrap(), [1]);
+    }
+
+    #[test]
+    fn torn_record_is_skipped_on_mount() {
+        let mut log = mount(4);
+        log.write(A, &[1]).unwrap();
+        log.write(A, &[2]).unwrap();
+
+        // the power went out while the second record was written, its tail is still erased
+        let mut flash = log.release();
+        flash.bytes_mut()[RECORD_SIZE + RECORD_SIZE / 2..2 * RECORD_SIZE].fill(0xFF);
+        let mut log = RecordLog::mount(flash).unwrap();
+        assert_eq!(read(&mut log, A).unwrap(), [1]);
+
+        // new records go behind the torn one
+        log.write(A, &[3]).unwrap();
+        let mut log = remount(log);
+        assert_eq!(read(&mut log, A).unwrap(), [3]);
+    }
+
+    #[test]
+    fn wraps_around_and_levels_wear() {
+        let mut log = mount(4);
+        log.write(B, &[0xBB]).unwrap();
+        for i in 0..200u8 {
+            log.write(A, &[i]).unwrap();
+        }
+        // B was copied forward whenever its sector was reclaimed
+        assert_eq!(read(&mut log, A).unwrap(), [199]);
+        assert_eq!(read(&mut log, B).unwrap(), [0xBB]);
+
+        let mut log = remount(log);
+        assert_eq!(read(&mut log, A).unwrap(), [199]);
+        assert_eq!(read(&mut log, B).unwrap(), [0xBB]);
+
+        let flash = log.release();
+        let counts = flash.erase_counts();
+        let (min, max) = (counts.iter().min().unwrap(), counts.iter().max().unwrap());
+        assert!(*min > 0, "erase counts {:?}", counts);
+        assert!(max - min <= 1, "erase counts {:?}", counts);
+    }
+
+    #[test]
+    fn copies_full_sectors_forward_with_many_ids() {
+        // 10 ids fill more than two sectors of 4 records, which then hold only current records
+        let ids = (0..10).map(|key| RecordId { kind: 2, key });
+        let mut log = mount(5);
+        for id in ids.clone() {
+            log.write(id, &[id.key]).unwrap();
+        }
+        // cycle through every sector many times
+        for i in 0..100u8 {
+            log.write(A, &[i]).unwrap();
+        }
+
+        let mut log = remount(log);
+        assert_eq!(read(&mut log, A).unwrap(), [99]);
+        for id in ids.clone() {
+            assert_eq!(read(&mut log, id).unwrap(), [id.key]);
+        }
+        log.write(A, &[100]).unwrap();
+        let mut log = remount(log);
+        assert_eq!(read(&mut log, A).unwrap(), [100]);
+
+        let counts = log.release().erase_counts().to_vec();
+        assert!(
+            counts.iter().all(|&count| count >= 10),
+            "erase counts {:?}",
+            counts
+        );
+    }
+
+    #[test]
+    fn full_log_rejects_new_ids_and_keeps_the_records() {
+        // all sectors but the spare hold current records
+        let ids = (0..12).map(|key| RecordId { kind: 2, key });
+        let mut log = mount(4);
+        for id in ids.clone() {
+            log.write(id, &[id.key]).unwrap();
+        }
+        assert_eq!(log.write(A, &[1]), Err(FlashError::Full));
+
+        let mut log = remount(log);
+        for id in ids {
+            assert_eq!(read(&mut log, id).unwrap(), [id.key]);
+        }
+        assert_eq!(read(&mut log, A), None);
+    }
+
+    #[test]
+    fn rejects_oversized_payload() {
+        let mut log = mount(4);
+        assert_eq!(
+            log.write(A, &[0; MAX_PAYLOAD + 1]),
+            Err(FlashError::OutOfBounds)
+        );
+        assert_eq!(read(&mut log, A), None);
+        log.write(A, &[0x5A; MAX_PAYLOAD]).unwrap();
+        assert_eq!(read(&mut log, A).unwrap(), [0x5A; MAX_PAYLOAD]);
+    }
+}
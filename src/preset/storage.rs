@@ -0,0 +1,209 @@
+use alloc::{vec, vec::Vec};
+use embedded_storage::nor_flash::NorFlash;
+
+/// Offset of the `presets` data partition, see `partitions.csv`
+pub const PRESET_PARTITION_OFFSET: u32 = 0x3F_0000;
+/// Size of the `presets` data partition, see `partitions.csv`
+pub const PRESET_PARTITION_SIZE: u32 = 0x1_0000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlashError {
+    /// The accessed region lies (partially) outside of the storage
+    OutOfBounds,
+    /// The accessed region is not aligned to the write or erase granularity
+    NotAligned,
+    /// The underlying device reported an error
+    Device,
+    /// Every record in the log is current, there is no room for a new one
+    Full,
+}
+
+/// Abstraction over NOR-flash-like storage
+///
+/// Erasing a sector sets all of its bits to 1, writing can only clear bits. Offsets are relative
+/// to the start of the storage.
+pub trait Flash {
+    /// Size of the smallest erasable unit in bytes
+    const SECTOR_SIZE: u32;
+
+    /// Total size of the storage in bytes
+    fn capacity(&self) -> u32;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError>;
+
+    /// Program `bytes` at `offset`
+    ///
+    /// The region must have been erased before, otherwise the result is the bitwise AND of the old
+    /// and the new content.
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError>;
+
+    /// Erase the sector with index `sector`
+    fn erase(&mut self, sector: u32) -> Result<(), FlashError>;
+
+    /// Number of erasable sectors
+    fn sectors(&self) -> u32 {
+        self.capacity() / Self::SECTOR_SIZE
+    }
+}
+
+/// A window into a [`NorFlash`] device, e.g. a partition of the on-board flash
+///
+/// ```ignore
+/// let flash = NorFlashPartition::new(
+///     esp_storage::FlashStorage::new(),
+///     PRESET_PARTITION_OFFSET,
+///     PRESET_PARTITION_SIZE,
+/// );
+/// ```
+pub struct NorFlashPartition<F: NorFlash> {
+    flash: F,
+    base: u32,
+    size: u32,
+}
+
+impl<F: NorFlash> NorFlashPartition<F> {
+    /// `base` and `size` must be multiples of the erase size of `flash`
+    pub fn new(flash: F, base: u32, size: u32) -> Self {
+        Self { flash, base, size }
+    }
+
+    fn check(&self, offset: u32, len: usize) -> Result<u32, FlashError> {
+        if offset as usize + len > self.size as usize {
+            Err(FlashError::OutOfBounds)
+        } else {
+            Ok(self.base + offset)
+        }
+    }
+}
+
+impl<F: NorFlash> Flash for NorFlashPartition<F> {
+    const SECTOR_SIZE: u32 = F::ERASE_SIZE as u32;
+
+    fn capacity(&self) -> u32 {
+        self.size
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let address = self.check(offset, bytes.len())?;
+        self.flash
+            .read(address, bytes)
+            .map_err(|_| FlashError::Device)
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let address = self.check(offset, bytes.len())?;
+        if address as usize % F::WRITE_SIZE != 0 || bytes.len() % F::WRITE_SIZE != 0 {
+            return Err(FlashError::NotAligned);
+        }
+        self.flash
+            .write(address, bytes)
+            .map_err(|_| FlashError::Device)
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), FlashError> {
+        let offset = sector * Self::SECTOR_SIZE;
+        let address = self.check(offset, Self::SECTOR_SIZE as usize)?;
+        self.flash
+            .erase(address, address + Self::SECTOR_SIZE)
+            .map_err(|_| FlashError::Device)
+    }
+}
+
+/// In-memory flash emulation with NOR semantics
+///
+/// Used to exercise the preset storage on the host. The raw content can be manipulated through
+/// [`RamFlash::bytes_mut`] to emulate corrupted or partially written records.
+pub struct RamFlash<const SECTOR_SIZE: u32> {
+    data: Vec<u8>,
+    erase_counts: Vec<u32>,
+}
+
+impl<const SECTOR_SIZE: u32> RamFlash<SECTOR_SIZE> {
+    /// Create an erased flash with `sectors` sectors
+    pub fn new(sectors: u32) -> Self {
+        Self {
+            data: vec![0xFF; (sectors * SECTOR_SIZE) as usize],
+            erase_counts: vec![0; sectors as usize],
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.data
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.data
+    }
+
+    /// Number of erase cycles per sector, useful to verify wear leveling
+    pub fn erase_counts(&self) -> &[u32] {
+        &self.erase_counts
+    }
+
+    fn range(&self, offset: u32, len: usize) -> Result<core::ops::Range<usize>, FlashError> {
+        let start = offset as usize;
+        if start + len > self.data.len() {
+            Err(FlashError::OutOfBounds)
+        } else {
+            Ok(start..start + len)
+        }
+    }
+}
+
+impl<const SECTOR_SIZE: u32> Flash for RamFlash<SECTOR_SIZE> {
+    const SECTOR_SIZE: u32 = SECTOR_SIZE;
+
+    fn capacity(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), FlashError> {
+        let range = self.range(offset, bytes.len())?;
+        bytes.copy_from_slice(&self.data[range]);
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), FlashError> {
+        let range = self.range(offset, bytes.len())?;
+        for (cell, byte) in self.data[range].iter_mut().zip(bytes) {
+            *cell &= byte;
+        }
+        Ok(())
+    }
+
+    fn erase(&mut self, sector: u32) -> Result<(), FlashError> {
+        let range = self.range(sector * SECTOR_SIZE, SECTOR_SIZE as usize)?;
+        self.data[range].fill(0xFF);
+        self.erase_counts[sector as usize] += 1;
+        Ok(())
+    }
+}
+
+const fn generate_crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+const CRC_TABLE: [u32; 256] = generate_crc_table();
+
+/// CRC-32 (IEEE 802.3) checksum
+pub fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &b| {
+        CRC_TABLE[((crc ^ b as u32) & 0xFF) as usize] ^ (crc >> 8)
+    })
+}
@@ -0,0 +1,135 @@
+use core::f32::consts::{FRAC_1_SQRT_2, FRAC_PI_4, SQRT_2};
+
+use crate::{
+    discrete_functions::{cos, sin},
+    filters::traits::Filter,
+    voice::BLOCK_SIZE,
+};
+
+/// A stereo sample, `[left, right]`
+///
+/// [`crate::filters::traits::Filterable`] covers `[f32; N]`, so stereo signals run through the
+/// same `Generator` and `Filter` traits as mono ones.
+pub type Stereo = [f32; 2];
+
+/// Constant-power pan law, `pan` in [-1, 1] from left to right
+///
+/// Returns the `[left, right]` gains. The power `left² + right²` is 1 at every position, so a
+/// source keeps its loudness while it moves. Both gains are 1/√2 in the centre.
+pub fn pan_gains(pan: f32) -> Stereo {
+    let phi = (pan.clamp(-1., 1.) + 1.) * FRAC_PI_4;
+    [cos(phi), sin(phi)]
+}
+
+/// Place the mono signal `x` at position `pan` in [-1, 1]
+pub fn pan(x: f32, pan: f32) -> Stereo {
+    pan_gains(pan).map(|gain| gain * x)
+}
+
+/// Move the stereo signal `x` towards one side, `pan` in [-1, 1]
+///
+/// The constant-power gains are scaled to unity in the centre, so a centred balance leaves the
+/// signal as it is.
+pub fn balance(x: Stereo, pan: f32) -> Stereo {
+    let [l, r] = pan_gains(pan);
+    [SQRT_2 * l * x[0], SQRT_2 * r * x[1]]
+}
+
+/// Mix down to mono, the inverse of [`pan`] to the centre
+pub fn mono([l, r]: Stereo) -> f32 {
+    FRAC_1_SQRT_2 * (l + r)
+}
+
+/// Mid/side stereo width
+///
+/// 0 is mono, 1 leaves the signal as it is and 2 doubles the side signal.
+pub struct Width {
+    /// range = [0, 2]
+    width: f32,
+}
+
+impl Width {
+    pub fn new(width: f32) -> Self {
+        let mut w = Self { width: 1. };
+        w.set_width(width);
+        w
+    }
+
+    pub fn set_width(&mut self, width: f32) {
+        self.width = width.clamp(0., 2.);
+    }
+}
+
+impl Filter for Width {
+    type In = Stereo;
+    type Out = Stereo;
+
+    fn filter(&mut self, [l, r]: Stereo) -> Stereo {
+        let mid = 0.5 * (l + r);
+        let side = 0.5 * (l - r) * self.width;
+        [mid + side, mid - side]
+    }
+}
+
+/// A mono filter for each channel of a stereo signal
+pub struct DualMono<F> {
+    channels: [F; 2],
+}
+
+impl<F: Filter<In = f32, Out = f32>> DualMono<F> {
+    /// Create the filters of both channels with `make`
+    pub fn new(mut make: impl FnMut() -> F) -> Self {
+        Self {
+            channels: [make(), make()],
+        }
+    }
+
+    /// Apply `f` to the filters of both channels, e.g. to set their cutoff
+    pub fn for_each(&mut self, f: impl FnMut(&mut F)) {
+        self.channels.iter_mut().for_each(f);
+    }
+}
+
+impl<F: Filter<In = f32, Out = f32>> Filter for DualMono<F> {
+    type In = Stereo;
+    type Out = Stereo;
+
+    fn filter(&mut self, [l, r]: Stereo) -> Stereo {
+        [self.channels[0].filter(l), self.channels[1].filter(r)]
+    }
+
+    fn process_block(&mut self, block: &mut [Stereo]) {
+        // one channel after the other through a mono buffer, so the block loop of each filter runs
+        let mut mono = [0.; BLOCK_SIZE];
+        for chunk in block.chunks_mut(BLOCK_SIZE) {
+            let mono = &mut mono[..chunk.len()];
+            for (c, channel) in self.channels.iter_mut().enumerate() {
+                for (y, x) in mono.iter_mut().zip(chunk.iter()) {
+                    *y = x[c];
+                }
+                channel.process_block(mono);
+                for (x, y) in chunk.iter_mut().zip(mono.iter()) {
+                    x[c] = *y;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filters::pass::BiquadLowPassFilter;
+
+    #[test]
+    fn dual_mono_blocks_match_the_samples() {
+        let mut by_sample = DualMono::new(BiquadLowPassFilter::new);
+        let mut by_block = DualMono::new(BiquadLowPassFilter::new);
+        let input: [Stereo; 100] = core::array::from_fn(|i| [(i % 7) as f32, (i % 3) as f32]);
+        let mut block = input;
+        by_block.process_block(&mut block);
+        for (x, y) in input.into_iter().zip(block) {
+            assert_eq!(by_sample.filter(x), y);
+        }
+    }
+}
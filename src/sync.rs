@@ -0,0 +1,186 @@
+use core::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+// Queue ================================
+
+/// Lock-free single-producer/single-consumer queue
+///
+/// Neither side ever waits for the other, so it can connect tasks on different cores without a
+/// critical section. [`Queue::split`] hands out the only [`Producer`] and [`Consumer`]. The
+/// queue holds up to `N - 1` items.
+pub struct Queue<T, const N: usize> {
+    slots: [UnsafeCell<MaybeUninit<T>>; N],
+    /// next slot to read, only written by the consumer
+    head: AtomicUsize,
+    /// next slot to write, only written by the producer
+    tail: AtomicUsize,
+}
+
+// The producer and the consumer never access the same slot at the same time, see `push` and `pop`
+unsafe impl<T: Send, const N: usize> Sync for Queue<T, N> {}
+
+impl<T, const N: usize> Queue<T, N> {
+    pub const fn new() -> Self {
+        Self {
+            slots: [const { UnsafeCell::new(MaybeUninit::uninit()) }; N],
+            head: AtomicUsize::new(0),
+            tail: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn split(&mut self) -> (Producer<'_, T, N>, Consumer<'_, T, N>) {
+        (Producer { queue: self }, Consumer { queue: self })
+    }
+}
+
+impl<T, const N: usize> Drop for Queue<T, N> {
+    fn drop(&mut self) {
+        // drop the items that were not taken
+        let mut consumer = Consumer { queue: self };
+        while consumer.pop().is_some() {}
+    }
+}
+
+/// Sending end of a [`Queue`]
+pub struct Producer<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
+
+impl<T, const N: usize> Producer<'_, T, N> {
+    /// Append `item`, or give it back if the queue is full
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        let tail = self.queue.tail.load(Ordering::Relaxed);
+        let next = (tail + 1) % N;
+        if next == self.queue.head.load(Ordering::Acquire) {
+            return Err(item);
+        }
+        // the consumer doesn't read the slot before `tail` moves past it
+        unsafe { (*self.queue.slots[tail].get()).write(item) };
+        self.queue.tail.store(next, Ordering::Release);
+        Ok(())
+    }
+}
+
+/// Receiving end of a [`Queue`]
+pub struct Consumer<'a, T, const N: usize> {
+    queue: &'a Queue<T, N>,
+}
+
+impl<T, const N: usize> Consumer<'_, T, N> {
+    /// Take the oldest item
+    pub fn pop(&mut self) -> Option<T> {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        if head == self.queue.tail.load(Ordering::Acquire) {
+            return None;
+        }
+        // the producer doesn't write the slot before `head` moves past it
+        let item = unsafe { (*self.queue.slots[head].get()).assume_init_read() };
+        self.queue.head.store((head + 1) % N, Ordering::Release);
+        Some(item)
+    }
+
+    /// Number of items in the queue, it may grow right after the call
+    pub fn len(&self) -> usize {
+        let head = self.queue.head.load(Ordering::Relaxed);
+        let tail = self.queue.tail.load(Ordering::Acquire);
+        (tail + N - head) % N
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+// Snapshot =============================
+
+/// Marks the published slot as not yet taken by the reader
+const FRESH: usize = 1 << 2;
+const INDEX: usize = FRESH - 1;
+
+/// Lock-free single-writer/single-reader snapshot of a value, e.g. a [`crate::params::Patch`]
+///
+/// A triple buffer: the writer fills its own slot and swaps it with the published one, the
+/// reader swaps the published slot with its own when it is newer. The reader always sees a
+/// complete value, the latest one at the time of [`SnapshotReader::update`], and neither side
+/// waits for the other.
+pub struct Snapshot<T> {
+    slots: [UnsafeCell<T>; 3],
+    /// index of the published slot, with [`FRESH`] until the reader takes it
+    middle: AtomicUsize,
+}
+
+// Each slot is owned by the writer, the reader or the `middle` index, which is swapped atomically
+unsafe impl<T: Send> Sync for Snapshot<T> {}
+
+impl<T: Clone> Snapshot<T> {
+    pub fn new(value: T) -> Self {
+        Self {
+            slots: [
+                UnsafeCell::new(value.clone()),
+                UnsafeCell::new(value.clone()),
+                UnsafeCell::new(value),
+            ],
+            middle: AtomicUsize::new(1),
+        }
+    }
+}
+
+impl<T> Snapshot<T> {
+    pub fn split(&mut self) -> (SnapshotWriter<'_, T>, SnapshotReader<'_, T>) {
+        (
+            SnapshotWriter {
+                snapshot: self,
+                back: 0,
+            },
+            SnapshotReader {
+                snapshot: self,
+                front: 2,
+            },
+        )
+    }
+}
+
+/// Writing end of a [`Snapshot`]
+pub struct SnapshotWriter<'a, T> {
+    snapshot: &'a Snapshot<T>,
+    back: usize,
+}
+
+impl<T> SnapshotWriter<'_, T> {
+    /// Make `value` the latest snapshot
+    pub fn publish(&mut self, value: T) {
+        // the back slot belongs to the writer alone
+        unsafe { *self.snapshot.slots[self.back].get() = value };
+        self.back = self
+            .snapshot
+            .middle
+            .swap(self.back | FRESH, Ordering::AcqRel)
+            & INDEX;
+    }
+}
+
+/// Reading end of a [`Snapshot`]
+pub struct SnapshotReader<'a, T> {
+    snapshot: &'a Snapshot<T>,
+    front: usize,
+}
+
+impl<T> SnapshotReader<'_, T> {
+    /// Take the latest snapshot, returns whether there was a new one since the last update
+    pub fn update(&mut self) -> bool {
+        if self.snapshot.middle.load(Ordering::Relaxed) & FRESH == 0 {
+            return false;
+        }
+        self.front = self.snapshot.middle.swap(self.front, Ordering::AcqRel) & INDEX;
+        true
+    }
+
+    /// The snapshot taken by the last update
+    pub fn get(&self) -> &T {
+        // the front slot belongs to the reader alone
+        unsafe { &*self.snapshot.slots[self.front].get() }
+    }
+}
@@ -0,0 +1,493 @@
+use embassy_usb::{
+    driver::{Driver, Endpoint, EndpointError, EndpointIn},
+    Builder,
+};
+use static_cell::StaticCell;
+
+use crate::{
+    audio::{AudioSink, Underrun},
+    config::{AudioConfig, SampleRate},
+    i2s::Sample,
+    sync::{Consumer, Producer, Queue},
+};
+
+pub mod descriptors;
+pub mod resampler;
+
+use descriptors::*;
+use resampler::DriftResampler;
+
+/// Capacity of the queue from the audio task to the USB stream, plus one
+pub const USB_AUDIO_QUEUE: usize = 1024;
+
+/// Bytes of the largest packet at any sample rate
+const MAX_PACKET_BYTES: usize = max_packet_size(SampleRate::Hz96000.hz()) as usize;
+
+/// Scale from a left-justified [`Sample`] to 16 bits
+const SAMPLE_SCALE: f32 = 1. / 65536.;
+
+static USB_AUDIO: StaticCell<Queue<Sample, USB_AUDIO_QUEUE>> = StaticCell::new();
+
+/// Create the queue from the audio task to the USB audio in static memory
+///
+/// The [`Producer`] goes into a [`UsbTap`] of the audio task, the [`UsbAudioStream`] to the
+/// [`UsbAudioClass`] of the USB task. Panics when it is called a second time.
+pub fn take() -> (
+    Producer<'static, Sample, USB_AUDIO_QUEUE>,
+    UsbAudioStream<'static>,
+) {
+    let (producer, consumer) = USB_AUDIO.init(Queue::new()).split();
+    (producer, UsbAudioStream::new(consumer))
+}
+
+/// [`AudioSink`] that passes the samples on to another sink and copies them to the USB audio
+///
+/// Only the samples that the inner sink took are copied, so the host records exactly what the
+/// DAC plays. While the host does not record, the queue is full and the samples are dropped.
+pub struct UsbTap<'a, S> {
+    sink: S,
+    frames: Producer<'a, Sample, USB_AUDIO_QUEUE>,
+}
+
+impl<'a, S> UsbTap<'a, S> {
+    pub fn new(sink: S, frames: Producer<'a, Sample, USB_AUDIO_QUEUE>) -> Self {
+        Self { sink, frames }
+    }
+
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+}
+
+impl<S: AudioSink> AudioSink for UsbTap<'_, S> {
+    async fn write(&mut self, samples: &[Sample]) -> Result<usize, Underrun> {
+        let written = self.sink.write(samples).await?;
+        for sample in &samples[..written.min(samples.len())] {
+            if self.frames.push(*sample).is_err() {
+                break;
+            }
+        }
+        Ok(written)
+    }
+}
+
+/// The USB end of the queue, it cuts the samples into 1 ms packets on the clock of the host
+///
+/// A [`DriftResampler`] keeps the queue about a chunk and two packets full, enough for the
+/// chunks of the audio task to arrive in bursts.
+pub struct UsbAudioStream<'a> {
+    frames: Consumer<'a, Sample, USB_AUDIO_QUEUE>,
+    resampler: DriftResampler,
+    sample_rate: u32,
+    /// thousandths of a frame that are left over from the last packets
+    remainder: u32,
+}
+
+impl<'a> UsbAudioStream<'a> {
+    pub fn new(frames: Consumer<'a, Sample, USB_AUDIO_QUEUE>) -> Self {
+        let config = AudioConfig::current();
+        let sample_rate = config.sample_rate.hz();
+        let target = config.chunk_size + 2 * max_packet_frames(sample_rate);
+        Self {
+            frames,
+            resampler: DriftResampler::new(target.min(USB_AUDIO_QUEUE / 2)),
+            sample_rate,
+            remainder: 0,
+        }
+    }
+
+    /// Start a stream, drops the samples that piled up in the queue since the last one
+    pub fn start(&mut self) {
+        while self.frames.len() > self.resampler.target() {
+            self.frames.pop();
+        }
+        self.resampler.reset();
+        self.remainder = 0;
+    }
+
+    /// Frames in the next packet, e.g. nine packets of 44 frames and one of 45 at 44.1 kHz
+    fn packet_frames(&mut self) -> usize {
+        self.remainder += self.sample_rate;
+        let frames = self.remainder / 1000;
+        self.remainder %= 1000;
+        frames as usize
+    }
+
+    /// Fill `packet` with the next 1 ms of 16-bit frames, returns the number of bytes
+    pub fn fill_packet(&mut self, packet: &mut [u8]) -> usize {
+        let len = self.packet_frames() * FRAME_BYTES;
+        self.resampler.adjust(self.frames.len());
+        for bytes in packet[..len].chunks_exact_mut(FRAME_BYTES) {
+            let frames = &mut self.frames;
+            let frame = self
+                .resampler
+                .next(|| frames.pop().map(|sample| sample.map(|x| x as f32)));
+            for (bytes, x) in bytes.chunks_exact_mut(SUBFRAME_BYTES).zip(frame) {
+                bytes.copy_from_slice(&((x * SAMPLE_SCALE) as i16).to_le_bytes());
+            }
+        }
+        len
+    }
+
+    pub fn resampler(&self) -> &DriftResampler {
+        &self.resampler
+    }
+}
+
+/// USB Audio Class 1.0 function that streams the synth to the host as a stereo 16-bit input
+///
+/// It sits next to the MIDI function of a composite device, with its own AudioControl interface,
+/// where the synth is an input terminal, and an AudioStreaming interface. The streaming
+/// interface has an empty alternate setting 0 and an isochronous IN endpoint in setting 1, which
+/// the host selects when it starts recording. The sample rate is the one of the I2S, so Linux
+/// records at that rate without any conversion.
+pub struct UsbAudioClass<'d, D: Driver<'d>> {
+    endpoint: D::EndpointIn,
+}
+
+impl<'d, D: Driver<'d>> UsbAudioClass<'d, D> {
+    pub fn new(builder: &mut Builder<'d, D>, sample_rate: u32) -> Self {
+        let mut function = builder.function(AUDIO_CLASS, AUDIOCONTROL_SUBCLASS, PROTOCOL_NONE);
+
+        // AudioControl interface
+        let mut interface = function.interface();
+        let streaming_interface = u8::from(interface.interface_number()) + 1;
+        let mut alt =
+            interface.alt_setting(AUDIO_CLASS, AUDIOCONTROL_SUBCLASS, PROTOCOL_NONE, None);
+        alt.descriptor(CS_INTERFACE, &ac_header(streaming_interface));
+        alt.descriptor(CS_INTERFACE, &input_terminal());
+        alt.descriptor(CS_INTERFACE, &output_terminal());
+
+        // AudioStreaming interface, without bandwidth until the host records
+        let mut interface = function.interface();
+        interface.alt_setting(AUDIO_CLASS, AUDIOSTREAMING_SUBCLASS, PROTOCOL_NONE, None);
+        let mut alt =
+            interface.alt_setting(AUDIO_CLASS, AUDIOSTREAMING_SUBCLASS, PROTOCOL_NONE, None);
+        alt.descriptor(CS_INTERFACE, &as_general());
+        alt.descriptor(CS_INTERFACE, &format_type_i(sample_rate));
+        let endpoint = alt.endpoint_isochronous_in(max_packet_size(sample_rate), 1);
+        alt.descriptor(CS_ENDPOINT, &as_endpoint());
+
+        Self { endpoint }
+    }
+
+    /// Send the packets of `stream` while the host records
+    pub async fn run(&mut self, stream: &mut UsbAudioStream<'_>) -> ! {
+        let mut packet = [0; MAX_PACKET_BYTES];
+        loop {
+            // the endpoint is enabled with alternate setting 1
+            self.endpoint.wait_enabled().await;
+            stream.start();
+            loop {
+                let len = stream.fill_packet(&mut packet);
+                match self.endpoint.write(&packet[..len]).await {
+                    Ok(()) => {}
+                    Err(EndpointError::Disabled) => break,
+                    Err(EndpointError::BufferOverflow) => panic!("Buffer overflow"),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::future::pending;
+
+    use embassy_usb::{
+        driver::{
+            Bus, ControlPipe, Direction, EndpointAddress, EndpointAllocError, EndpointInfo,
+            EndpointOut, EndpointType, Event, Unsupported,
+        },
+        Config,
+    };
+
+    use super::*;
+
+    fn stream(queue: &mut Queue<Sample, USB_AUDIO_QUEUE>, sample_rate: u32) -> UsbAudioStream<'_> {
+        let (_, frames) = queue.split();
+        let mut stream = UsbAudioStream::new(frames);
+        stream.sample_rate = sample_rate;
+        stream
+    }
+
+    #[test]
+    fn packets_follow_the_44100_hz_cadence() {
+        let mut queue = Queue::new();
+        let mut stream = stream(&mut queue, 44_100);
+        for _ in 0..10 {
+            let packets: [usize; 10] = core::array::from_fn(|_| stream.packet_frames());
+            assert_eq!(packets.iter().filter(|&&frames| frames == 44).count(), 9);
+            assert_eq!(packets.iter().filter(|&&frames| frames == 45).count(), 1);
+            assert_eq!(packets.iter().sum::<usize>(), 441);
+        }
+    }
+
+    #[test]
+    fn packets_are_even_at_48000_hz() {
+        let mut queue = Queue::new();
+        let mut stream = stream(&mut queue, 48_000);
+        let mut packet = [0xAA; MAX_PACKET_BYTES];
+        for _ in 0..10 {
+            assert_eq!(stream.fill_packet(&mut packet), 48 * FRAME_BYTES);
+        }
+        // the queue is empty, the resampler holds the initial silence
+        assert!(packet[..48 * FRAME_BYTES].iter().all(|&byte| byte == 0));
+    }
+
+    /// Driver that hands out endpoints and builds descriptors, but never talks to a host
+    struct MockDriver {
+        next_in: u8,
+    }
+
+    struct MockEndpoint(EndpointInfo);
+
+    struct Never;
+
+    impl<'a> Driver<'a> for MockDriver {
+        type EndpointOut = MockEndpoint;
+        type EndpointIn = MockEndpoint;
+        type ControlPipe = Never;
+        type Bus = Never;
+
+        fn alloc_endpoint_out(
+            &mut self,
+            _ep_type: EndpointType,
+            _max_packet_size: u16,
+            _interval_ms: u8,
+        ) -> Result<MockEndpoint, EndpointAllocError> {
+            Err(EndpointAllocError)
+        }
+
+        fn alloc_endpoint_in(
+            &mut self,
+            ep_type: EndpointType,
+            max_packet_size: u16,
+            interval_ms: u8,
+        ) -> Result<MockEndpoint, EndpointAllocError> {
+            self.next_in += 1;
+            Ok(MockEndpoint(EndpointInfo {
+                addr: EndpointAddress::from_parts(self.next_in as usize, Direction::In),
+                ep_type,
+                max_packet_size,
+                interval_ms,
+            }))
+        }
+
+        fn start(self, _control_max_packet_size: u16) -> (Never, Never) {
+            (Never, Never)
+        }
+    }
+
+    impl Endpoint for MockEndpoint {
+        fn info(&self) -> &EndpointInfo {
+            &self.0
+        }
+
+        async fn wait_enabled(&mut self) {
+            pending().await
+        }
+    }
+
+    impl EndpointIn for MockEndpoint {
+        async fn write(&mut self, _buf: &[u8]) -> Result<(), EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+    }
+
+    impl EndpointOut for MockEndpoint {
+        async fn read(&mut self, _buf: &mut [u8]) -> Result<usize, EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+    }
+
+    impl Bus for Never {
+        async fn enable(&mut self) {}
+
+        async fn disable(&mut self) {}
+
+        async fn poll(&mut self) -> Event {
+            pending().await
+        }
+
+        fn endpoint_set_enabled(&mut self, _ep_addr: EndpointAddress, _enabled: bool) {}
+
+        fn endpoint_set_stalled(&mut self, _ep_addr: EndpointAddress, _stalled: bool) {}
+
+        fn endpoint_is_stalled(&mut self, _ep_addr: EndpointAddress) -> bool {
+            false
+        }
+
+        async fn remote_wakeup(&mut self) -> Result<(), Unsupported> {
+            Err(Unsupported)
+        }
+    }
+
+    impl ControlPipe for Never {
+        fn max_packet_size(&self) -> usize {
+            64
+        }
+
+        async fn setup(&mut self) -> [u8; 8] {
+            pending().await
+        }
+
+        async fn data_out(
+            &mut self,
+            _buf: &mut [u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<usize, EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+
+        async fn data_in(
+            &mut self,
+            _data: &[u8],
+            _first: bool,
+            _last: bool,
+        ) -> Result<(), EndpointError> {
+            Err(EndpointError::Disabled)
+        }
+
+        async fn accept(&mut self) {}
+
+        async fn reject(&mut self) {}
+
+        async fn accept_set_address(&mut self, _addr: u8) {}
+    }
+
+    /// The configuration descriptor of a device with only the audio function
+    fn configuration(sample_rate: u32, buf: &mut [u8]) -> &[u8] {
+        let mut config = Config::new(0x1209, 0x0001);
+        config.device_class = 0xEF;
+        config.device_sub_class = 0x02;
+        config.device_protocol = 0x01;
+        config.composite_with_iads = true;
+
+        let mut bos_descriptor = [0; 256];
+        let mut control_buf = [0; 64];
+        let mut builder = Builder::new(
+            MockDriver { next_in: 0 },
+            config,
+            buf,
+            &mut bos_descriptor,
+            &mut [],
+            &mut control_buf,
+        );
+        let class = UsbAudioClass::new(&mut builder, sample_rate);
+        assert_eq!(
+            class.endpoint.info().max_packet_size,
+            max_packet_size(sample_rate)
+        );
+        let _ = builder.build();
+
+        let total = u16::from_le_bytes([buf[2], buf[3]]) as usize;
+        &buf[..total]
+    }
+
+    /// Split a configuration descriptor at the `bLength` of each descriptor
+    fn descriptors(mut bytes: &[u8]) -> impl Iterator<Item = &[u8]> {
+        core::iter::from_fn(move || {
+            let (descriptor, rest) = bytes.split_at(*bytes.first()? as usize);
+            bytes = rest;
+            Some(descriptor)
+        })
+    }
+
+    #[test]
+    fn configuration_describes_a_stereo_input() {
+        let mut buf = [0; 256];
+        let configuration = configuration(48_000, &mut buf);
+        let descriptors: Vec<&[u8]> = descriptors(configuration).collect();
+        let [config, iad, control, header, input, output, idle, streaming, general, format, endpoint, audio_endpoint] =
+            descriptors[..]
+        else {
+            panic!("unexpected descriptors {descriptors:02X?}");
+        };
+
+        // two interfaces, grouped into one function
+        assert_eq!(&config[..2], [9, 0x02]);
+        assert_eq!(config[4], 2);
+        assert_eq!(
+            iad,
+            [
+                8,
+                0x0B,
+                0,
+                2,
+                AUDIO_CLASS,
+                AUDIOCONTROL_SUBCLASS,
+                PROTOCOL_NONE,
+                0
+            ]
+        );
+
+        // AudioControl, the header counts the three class-specific descriptors
+        assert_eq!(
+            control,
+            [
+                9,
+                0x04,
+                0,
+                0,
+                0,
+                AUDIO_CLASS,
+                AUDIOCONTROL_SUBCLASS,
+                PROTOCOL_NONE,
+                0
+            ]
+        );
+        assert_eq!(
+            header[1..],
+            [[CS_INTERFACE].as_slice(), &ac_header(1)].concat()
+        );
+        let total = u16::from_le_bytes([header[5], header[6]]) as usize;
+        assert_eq!(total, header.len() + input.len() + output.len());
+        assert_eq!(input[2..], input_terminal());
+        assert_eq!(output[2..], output_terminal());
+
+        // AudioStreaming, setting 0 without an endpoint and setting 1 with the stream
+        assert_eq!(
+            idle,
+            [
+                9,
+                0x04,
+                1,
+                0,
+                0,
+                AUDIO_CLASS,
+                AUDIOSTREAMING_SUBCLASS,
+                PROTOCOL_NONE,
+                0
+            ]
+        );
+        assert_eq!(
+            streaming,
+            [
+                9,
+                0x04,
+                1,
+                1,
+                1,
+                AUDIO_CLASS,
+                AUDIOSTREAMING_SUBCLASS,
+                PROTOCOL_NONE,
+                0
+            ]
+        );
+        assert_eq!(general[2..], as_general());
+        assert_eq!(format[2..], format_type_i(48_000));
+        assert_eq!(format[8..], [0x80, 0xBB, 0x00]);
+
+        // isochronous IN, 48 stereo 16-bit frames every 1 ms
+        assert_eq!(endpoint[..3], [7, 0x05, 0x81]);
+        assert_eq!(endpoint[3] & 0x03, EndpointType::Isochronous as u8);
+        assert_eq!(endpoint[4..], [192, 0, 1]);
+        assert_eq!(
+            audio_endpoint,
+            [[7, CS_ENDPOINT].as_slice(), &as_endpoint()].concat()
+        );
+    }
+}
@@ -0,0 +1,175 @@
+pub const AUDIO_CLASS: u8 = 0x01;
+pub const AUDIOCONTROL_SUBCLASS: u8 = 0x01;
+pub const AUDIOSTREAMING_SUBCLASS: u8 = 0x02;
+pub const PROTOCOL_NONE: u8 = 0x00;
+
+pub const CS_INTERFACE: u8 = 0x24;
+pub const CS_ENDPOINT: u8 = 0x25;
+
+// AudioControl interface descriptor subtypes
+const HEADER: u8 = 0x01;
+const INPUT_TERMINAL: u8 = 0x02;
+const OUTPUT_TERMINAL: u8 = 0x03;
+
+// AudioStreaming interface and endpoint descriptor subtypes
+const AS_GENERAL: u8 = 0x01;
+const FORMAT_TYPE: u8 = 0x02;
+const EP_GENERAL: u8 = 0x01;
+
+const FORMAT_TYPE_I: u8 = 0x01;
+const FORMAT_PCM: u16 = 0x0001;
+
+const TERMINAL_SYNTHESIZER: u16 = 0x0713;
+const TERMINAL_USB_STREAMING: u16 = 0x0101;
+const INPUT_TERMINAL_ID: u8 = 1;
+const OUTPUT_TERMINAL_ID: u8 = 2;
+
+/// Left and right front
+const CHANNEL_CONFIG: u16 = 0x0003;
+pub const CHANNELS: usize = 2;
+pub const SUBFRAME_BYTES: usize = 2;
+pub const FRAME_BYTES: usize = CHANNELS * SUBFRAME_BYTES;
+
+/// Length of a descriptor with a body of `len` bytes
+///
+/// The functions below return the bodies of the USB Audio Class 1.0 descriptors, the embassy-usb
+/// builder prepends `bLength` and `bDescriptorType`.
+const fn descriptor_len(len: usize) -> usize {
+    len + 2
+}
+
+/// Header of the AudioControl interface, it links the AudioStreaming interface
+pub fn ac_header(streaming_interface: u8) -> [u8; 7] {
+    let total = (descriptor_len(7) + descriptor_len(10) + descriptor_len(7)) as u16;
+    let [total_lo, total_hi] = total.to_le_bytes();
+    [
+        HEADER,
+        0x00,
+        0x01, // bcdADC 1.0
+        total_lo,
+        total_hi,
+        1, // bInCollection
+        streaming_interface,
+    ]
+}
+
+/// The synth, where the audio comes from
+pub fn input_terminal() -> [u8; 10] {
+    let [terminal_lo, terminal_hi] = TERMINAL_SYNTHESIZER.to_le_bytes();
+    let [config_lo, config_hi] = CHANNEL_CONFIG.to_le_bytes();
+    [
+        INPUT_TERMINAL,
+        INPUT_TERMINAL_ID,
+        terminal_lo,
+        terminal_hi,
+        0, // bAssocTerminal
+        CHANNELS as u8,
+        config_lo,
+        config_hi,
+        0, // iChannelNames
+        0, // iTerminal
+    ]
+}
+
+/// The streaming endpoint, where the audio goes to the host
+pub fn output_terminal() -> [u8; 7] {
+    let [terminal_lo, terminal_hi] = TERMINAL_USB_STREAMING.to_le_bytes();
+    [
+        OUTPUT_TERMINAL,
+        OUTPUT_TERMINAL_ID,
+        terminal_lo,
+        terminal_hi,
+        0, // bAssocTerminal
+        INPUT_TERMINAL_ID,
+        0, // iTerminal
+    ]
+}
+
+/// General AudioStreaming descriptor, PCM from the output terminal
+pub fn as_general() -> [u8; 5] {
+    let [format_lo, format_hi] = FORMAT_PCM.to_le_bytes();
+    [
+        AS_GENERAL,
+        OUTPUT_TERMINAL_ID,
+        1, // bDelay in frames
+        format_lo,
+        format_hi,
+    ]
+}
+
+/// Type I format with a single sample rate in Hz
+pub fn format_type_i(sample_rate: u32) -> [u8; 9] {
+    let [rate_0, rate_1, rate_2, _] = sample_rate.to_le_bytes();
+    [
+        FORMAT_TYPE,
+        FORMAT_TYPE_I,
+        CHANNELS as u8,
+        SUBFRAME_BYTES as u8,
+        8 * SUBFRAME_BYTES as u8,
+        1, // bSamFreqType, one discrete rate
+        rate_0,
+        rate_1,
+        rate_2,
+    ]
+}
+
+/// Class-specific descriptor of the isochronous endpoint, without controls
+pub fn as_endpoint() -> [u8; 5] {
+    [
+        EP_GENERAL, 0, // bmAttributes, no sampling frequency or pitch control
+        0, // bLockDelayUnits
+        0, 0, // wLockDelay
+    ]
+}
+
+/// Frames in the largest packet at `sample_rate`, one packet per 1 ms USB frame
+pub const fn max_packet_frames(sample_rate: u32) -> usize {
+    sample_rate.div_ceil(1000) as usize
+}
+
+/// Bytes of the largest packet at `sample_rate`
+pub const fn max_packet_size(sample_rate: u32) -> u16 {
+    (max_packet_frames(sample_rate) * FRAME_BYTES) as u16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ac_header_covers_the_audiocontrol_descriptors() {
+        let header = ac_header(3);
+        assert_eq!(header, [HEADER, 0x00, 0x01, 30, 0, 1, 3]);
+        let total = u16::from_le_bytes([header[3], header[4]]) as usize;
+        let bodies = header.len() + input_terminal().len() + output_terminal().len();
+        assert_eq!(total, bodies + 3 * 2);
+    }
+
+    #[test]
+    fn terminals_connect_the_synth_to_the_stream() {
+        assert_eq!(
+            input_terminal(),
+            [INPUT_TERMINAL, 1, 0x13, 0x07, 0, 2, 0x03, 0x00, 0, 0]
+        );
+        assert_eq!(output_terminal(), [OUTPUT_TERMINAL, 2, 0x01, 0x01, 0, 1, 0]);
+        assert_eq!(as_general(), [AS_GENERAL, 2, 1, 0x01, 0x00]);
+    }
+
+    #[test]
+    fn format_type_i_has_a_24_bit_rate() {
+        assert_eq!(
+            format_type_i(48_000),
+            [FORMAT_TYPE, FORMAT_TYPE_I, 2, 2, 16, 1, 0x80, 0xBB, 0x00]
+        );
+        assert_eq!(format_type_i(44_100)[6..], [0x44, 0xAC, 0x00]);
+        assert_eq!(format_type_i(96_000)[6..], [0x00, 0x77, 0x01]);
+    }
+
+    #[test]
+    fn packets_hold_the_frames_of_a_millisecond() {
+        assert_eq!(max_packet_frames(48_000), 48);
+        assert_eq!(max_packet_frames(44_100), 45);
+        assert_eq!(max_packet_size(44_100), 180);
+        assert_eq!(max_packet_size(96_000), 384);
+    }
+}
@@ -0,0 +1,155 @@
+use crate::stereo::Stereo;
+
+/// Largest deviation of the ratio from 1, far beyond the tolerance of the crystals
+const MAX_DRIFT: f32 = 0.002;
+/// Change of the ratio per frame that the queue is off its target
+const GAIN: f32 = 1e-5;
+/// Smoothing of the fill level per packet, it saws up and down with the chunks of the audio task
+const FILL_SMOOTHING: f32 = 0.01;
+
+/// Adaptive resampler between two clocks that run at nearly the same rate
+///
+/// The audio task fills a queue on the clock of the I2S, the USB host empties it on its own
+/// clock. Both are nominally the sample rate, but they drift apart by some ppm, so the queue
+/// would eventually run dry or overflow. The resampler reads the queue slightly faster or slower,
+/// by linear interpolation, to keep its fill level at the target.
+///
+/// The fill level is smoothed over about 100 packets and steers the ratio proportionally. It
+/// settles within a few seconds, 10 frames off the target for every 100 ppm of drift.
+pub struct DriftResampler {
+    /// fill level of the queue to keep, in frames
+    target: f32,
+    /// smoothed fill level
+    fill: f32,
+    /// input frames per output frame
+    ratio: f32,
+    /// position between `previous` and `current`, range = [0, 1)
+    phase: f32,
+    previous: Stereo,
+    current: Stereo,
+}
+
+impl DriftResampler {
+    pub fn new(target: usize) -> Self {
+        Self {
+            target: target as f32,
+            fill: target as f32,
+            ratio: 1.,
+            phase: 0.,
+            previous: [0.; 2],
+            current: [0.; 2],
+        }
+    }
+
+    /// Start over, e.g. when a stream starts after a pause
+    pub fn reset(&mut self) {
+        *self = Self::new(self.target());
+    }
+
+    /// Fill level of the queue to keep, in frames
+    pub fn target(&self) -> usize {
+        self.target as usize
+    }
+
+    /// Input frames per output frame
+    pub fn ratio(&self) -> f32 {
+        self.ratio
+    }
+
+    /// Steer the ratio towards the target, call it once per packet with the frames in the queue
+    pub fn adjust(&mut self, fill: usize) {
+        self.fill += FILL_SMOOTHING * (fill as f32 - self.fill);
+        self.ratio = (1. + GAIN * (self.fill - self.target)).clamp(1. - MAX_DRIFT, 1. + MAX_DRIFT);
+    }
+
+    /// The next output frame, takes input frames from `pop` as needed
+    ///
+    /// When `pop` runs dry the last frame is held.
+    pub fn next(&mut self, mut pop: impl FnMut() -> Option<Stereo>) -> Stereo {
+        self.phase += self.ratio;
+        while self.phase >= 1. {
+            self.phase -= 1.;
+            self.previous = self.current;
+            if let Some(frame) = pop() {
+                self.current = frame;
+            }
+        }
+        let (previous, current, phase) = (self.previous, self.current, self.phase);
+        [0, 1].map(|c| previous[c] + phase * (current[c] - previous[c]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TARGET: usize = 300;
+    const PACKET_FRAMES: usize = 48;
+
+    /// Stream packets for `seconds` while the queue is filled `ppm` faster than it is read
+    ///
+    /// Returns the mean ratio of the last second and the fill level range over all packets after
+    /// the first second.
+    fn run(ppm: f32, seconds: usize) -> (f32, usize, usize) {
+        let mut resampler = DriftResampler::new(TARGET);
+        let rate = 1. + ppm * 1e-6;
+        let (mut fill, mut produced) = (TARGET, 0.);
+        let (mut min, mut max) = (usize::MAX, 0);
+        let mut ratio_sum = 0.;
+        for packet in 0..1000 * seconds {
+            // the audio task fills the queue on its own clock
+            produced += PACKET_FRAMES as f32 * rate;
+            fill += produced as usize;
+            produced -= produced as usize as f32;
+
+            resampler.adjust(fill);
+            for _ in 0..PACKET_FRAMES {
+                resampler.next(|| {
+                    fill = fill.checked_sub(1)?;
+                    Some([0.; 2])
+                });
+            }
+            if packet >= 1000 {
+                min = min.min(fill);
+                max = max.max(fill);
+            }
+            if packet >= 1000 * (seconds - 1) {
+                ratio_sum += resampler.ratio();
+            }
+        }
+        (ratio_sum / 1000., min, max)
+    }
+
+    /// The ratio settles at the drift and the queue stays within a packet or two of the target
+    fn assert_follows(ppm: f32) {
+        let (ratio, min, max) = run(ppm, 20);
+        let error = (ratio - 1.) * 1e6 - ppm;
+        assert!(error.max(-error) < 20., "ratio {ratio}");
+        let range = TARGET - 2 * PACKET_FRAMES..TARGET + PACKET_FRAMES;
+        assert!(
+            range.contains(&min) && range.contains(&max),
+            "fill {min}..{max}"
+        );
+    }
+
+    #[test]
+    fn follows_a_faster_source() {
+        assert_follows(100.);
+    }
+
+    #[test]
+    fn follows_a_slower_source() {
+        assert_follows(-100.);
+    }
+
+    #[test]
+    fn holds_the_last_frame_when_the_queue_runs_dry() {
+        let mut resampler = DriftResampler::new(TARGET);
+        let mut frames = [[0.5, -0.5]].into_iter();
+        // one frame of latency, the first output is still the initial silence
+        assert_eq!(resampler.next(|| frames.next()), [0.; 2]);
+        for _ in 0..10 {
+            assert_eq!(resampler.next(|| frames.next()), [0.5, -0.5]);
+        }
+    }
+}
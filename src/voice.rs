@@ -1,54 +1,474 @@
+mod glide;
+mod note_stack;
+
+pub use glide::{Glide, GlideMode};
+pub use note_stack::{NotePriority, NoteStack, MAX_HELD_NOTES};
+
 use crate::{
+    config,
     envelope::{ADSREnvelope, Envelope},
-    filters::{traits::Filter, BiquadHighPassFilter, BiquadLowPassFilter},
+    filters::{
+        traits::Filter, BiquadHighPassFilter, BiquadLowPassFilter, LadderFilter, LadderMode,
+    },
+    midi::{
+        control::{ControlTarget, ParamControls},
+        learn::MidiLearn,
+    },
     oscillators::{
+        modulation::{fm_factor, ring_mod, HardSync, OscId},
+        phaser::Phased,
         scales::{freq, notes},
-        traits::Oscillator,
+        traits::{Generator, Oscillator},
         *,
     },
+    params::{ParamId, Patch},
+    stereo::{self, DualMono, Stereo},
 };
-use esp_println::println;
-#[allow(unused_imports)]
-use helpers::{linear_map, log_map};
+use micromath::F32Ext;
 use midi_msg::{Channel, ChannelVoiceMsg, ControlChange, MidiMsg};
 
-use alloc::{boxed::Box, vec, vec::Vec};
+/// Something that renders audio and is played and configured through MIDI
+pub trait Instrument {
+    fn generate(&mut self) -> Stereo;
+
+    /// Fill `block` with the next frames
+    ///
+    /// MIDI and parameter changes between two calls apply at the start of the next block, so a
+    /// shared instrument only has to be locked once per block.
+    fn generate_block(&mut self, block: &mut [Stereo]) {
+        for frame in block.iter_mut() {
+            *frame = self.generate();
+        }
+    }
+
+    fn handle_midi(&mut self, msg: MidiMsg);
+
+    /// Capture the current sound parameters
+    fn patch(&self) -> Patch;
+
+    /// Apply all sound parameters of `patch`
+    fn load_patch(&mut self, patch: &Patch);
+
+    /// Learned bindings of control changes to parameters
+    fn learn(&mut self) -> &mut MidiLearn;
+}
+
+/// Amplitude reduction at zero pressure, range: [0, 1]
+const PRESSURE_AMP_DEPTH: f32 = 0.5;
+/// Cutoff reduction at zero pressure in octaves
+const PRESSURE_CUTOFF_OCTAVES: f32 = 1.;
+/// Cutoff change from the lowest to the highest timbre value in octaves
+const TIMBRE_CUTOFF_OCTAVES: f32 = 4.;
+/// Number of samples between pitch updates while gliding
+const GLIDE_INTERVAL: u8 = 16;
+/// Number of frames that the envelope and the filters process at once in `generate_block`
+pub const BLOCK_SIZE: usize = 32;
 
 pub struct Voice {
-    osc: Vec<Box<dyn Oscillator<Out = f32>>>,
+    unison: Unison<WaveformOscillator>,
+    sub: SubOscillator<WaveformOscillator>,
+    /// second oscillator, synced to the centre voice of the unison
+    osc2: HardSync<WaveformOscillator>,
+    /// last outputs of osc 1 (mono), osc 2 and the sub, indexed by [`OscId`]
+    ///
+    /// The FM source modulates the frequency of the target with them, one sample late.
+    outputs: [f32; 3],
+    noise: Noise,
     env: ADSREnvelope,
-    lp: BiquadLowPassFilter,
-    hp: BiquadHighPassFilter,
+    lp: DualMono<BiquadLowPassFilter>,
+    ladder: DualMono<LadderFilter>,
+    hp: DualMono<BiquadHighPassFilter>,
+    /// the note that is currently playing
     note: Option<u8>,
+    /// the note the oscillators are tuned to, kept after release
+    pitch_note: Option<u8>,
+    held: NoteStack,
+    glide: Glide,
+    glide_counter: u8,
+    patch: Patch,
+    controls: ParamControls,
+
+    /// pitch offset in semitones
+    bend: f32,
+    /// range: [0, 1], default = 1.0
+    pressure: f32,
+    /// range: [0, 1], default = 0.5
+    timbre: f32,
 }
 
 impl Voice {
     pub fn new() -> Self {
-        Self {
-            osc: vec![
-                Box::new(SawToothOscillator::new(freq(notes::A4))),
-                Box::new(SawToothOscillator::new(freq(notes::A4))),
-                Box::new(SawToothOscillator::new(freq(notes::A3))),
-                Box::new(SawToothOscillator::new(freq(notes::A3))),
-                Box::new(Noise::new(0xBAD_5EED)),
-            ],
+        let mut voice = Self {
+            unison: Unison::new(
+                Patch::DEFAULT.get(ParamId::UnisonVoices) as usize,
+                || WaveformOscillator::new(freq(notes::A4), Waveform::Saw),
+                0x5EED_CAFE,
+            ),
+            sub: SubOscillator::new(
+                WaveformOscillator::new(freq(notes::A4), Waveform::Square),
+                1,
+            ),
+            osc2: HardSync::new(WaveformOscillator::new(freq(notes::A4), Waveform::Saw)),
+            outputs: [0.; 3],
+            noise: Noise::new(0xBAD_5EED),
             env: ADSREnvelope::new(0.01, 0.01, 0.6, 0.2),
-            lp: BiquadLowPassFilter::new(),
-            hp: BiquadHighPassFilter::new(),
+            lp: DualMono::new(BiquadLowPassFilter::new),
+            ladder: DualMono::new(LadderFilter::new),
+            hp: DualMono::new(BiquadHighPassFilter::new),
             note: None,
+            pitch_note: None,
+            held: NoteStack::new(),
+            glide: Glide::new(),
+            glide_counter: 0,
+            patch: Patch::DEFAULT,
+            controls: ParamControls::new(),
+            bend: 0.,
+            pressure: 1.,
+            timbre: 0.5,
+        };
+        voice.load_patch(&Patch::DEFAULT);
+        voice
+    }
+
+    /// Current value of parameter `id`
+    pub fn param(&self, id: ParamId) -> f32 {
+        self.patch.get(id)
+    }
+
+    /// Set parameter `id`, the value is clamped to the range of the parameter
+    pub fn set_param(&mut self, id: ParamId, value: f32) {
+        self.patch.set(id, value);
+        let value = self.patch.get(id);
+        match id {
+            ParamId::Detune => self.unison.set_detune(value),
+            ParamId::LpCutoff => self.update_cutoff(),
+            ParamId::LpQ => self.lp.for_each(|lp| lp.set_q(value)),
+            ParamId::FilterType => {
+                let mode = match value < 1.5 {
+                    true => LadderMode::Pole2,
+                    false => LadderMode::Pole4,
+                };
+                self.ladder.for_each(|ladder| ladder.set_mode(mode));
+            }
+            ParamId::Resonance => self.ladder.for_each(|ladder| ladder.set_resonance(value)),
+            ParamId::Drive => self.ladder.for_each(|ladder| ladder.set_drive(value)),
+            ParamId::HpCutoff => self.hp.for_each(|hp| hp.set_cutoff(value)),
+            ParamId::HpQ => self.hp.for_each(|hp| hp.set_q(value)),
+            ParamId::AttackTime => self.env.attack_time = value,
+            ParamId::DecayTime => self.env.decay_time = value,
+            ParamId::SustainLevel => self.env.sustain_level = value,
+            ParamId::ReleaseTime => self.env.release_time = value,
+            ParamId::Portamento if value < 0.5 => {
+                self.glide.stop();
+                self.update_pitch();
+            }
+            ParamId::UnisonVoices => self.unison.set_voices(value as usize),
+            ParamId::UnisonMix => self.unison.set_mix(value),
+            ParamId::StereoWidth => self.unison.set_width(value),
+            ParamId::UnisonPhase => self.unison.set_phase_mode(if value < 0.5 {
+                PhaseMode::Fixed
+            } else {
+                PhaseMode::Random
+            }),
+            ParamId::Waveform => {
+                let waveform = Waveform::from_param(value);
+                self.unison
+                    .oscillators_mut()
+                    .for_each(|o| o.waveform = waveform);
+            }
+            ParamId::Morph => {
+                self.unison.oscillators_mut().for_each(|o| o.morph = value);
+                self.sub.osc.morph = value;
+                self.osc2.osc.morph = value;
+            }
+            ParamId::Osc2Waveform => self.osc2.osc.waveform = Waveform::from_param(value),
+            ParamId::Osc2Pitch => self.update_pitch(),
+            ParamId::SubWaveform => self.sub.osc.waveform = Waveform::from_param(value),
+            ParamId::SubOctave => self.sub.set_octaves(value as u8),
+            // read when they are needed
+            ParamId::Pan
+            | ParamId::GlideTime
+            | ParamId::Portamento
+            | ParamId::GlideMode
+            | ParamId::NotePriority
+            | ParamId::SubLevel
+            | ParamId::Osc2Level
+            | ParamId::Sync
+            | ParamId::RingMix
+            | ParamId::FmAmount
+            | ParamId::FmSource
+            | ParamId::FmTarget
+            | ParamId::RingSource
+            | ParamId::RingTarget => {}
+            // effect parameters, applied by `effects::Effects`
+            ParamId::DelayTime
+            | ParamId::DelayDivision
+            | ParamId::DelayFeedback
+            | ParamId::DelayMix
+            | ParamId::DelayPingPong
+            | ParamId::DelayHighCut
+            | ParamId::DelayLowCut
+            | ParamId::ReverbSize
+            | ParamId::ReverbDamping
+            | ParamId::ReverbPreDelay
+            | ParamId::ReverbMix
+            | ParamId::ModType
+            | ParamId::ModRate
+            | ParamId::ModDivision
+            | ParamId::ModDepth
+            | ParamId::ModFeedback
+            | ParamId::ModMix
+            | ParamId::DistShape
+            | ParamId::DistDrive
+            | ParamId::DistTone
+            | ParamId::Oversampling
+            | ParamId::CrushBits
+            | ParamId::CrushRate
+            | ParamId::OutputWidth => {}
+            // master bus parameters, applied by `master::MasterBus`
+            ParamId::MasterGain
+            | ParamId::CompThreshold
+            | ParamId::CompRatio
+            | ParamId::CompAttack
+            | ParamId::CompRelease
+            | ParamId::CompMakeup => {}
+        }
+    }
+
+    /// Set the pitch offset in semitones, e.g. from a per-note pitch bend
+    pub fn set_pitch_bend(&mut self, semitones: f32) {
+        self.bend = semitones;
+        self.update_pitch();
+    }
+
+    /// Set the pressure in [0, 1], which controls amplitude and cutoff
+    ///
+    /// Full pressure leaves the sound unchanged.
+    pub fn set_pressure(&mut self, pressure: f32) {
+        self.pressure = pressure.clamp(0., 1.);
+        self.update_cutoff();
+    }
+
+    /// Set the timbre in [0, 1], which controls the cutoff around the patch setting at 0.5
+    pub fn set_timbre(&mut self, timbre: f32) {
+        self.timbre = timbre.clamp(0., 1.);
+        self.update_cutoff();
+    }
+
+    /// The note that is currently playing
+    pub fn note(&self) -> Option<u8> {
+        self.note
+    }
+
+    fn update_pitch(&mut self) {
+        if let Some(note) = self.pitch_note {
+            let semitones = self.bend + self.glide.offset();
+            let frequency = freq(note) * 2f32.powf(semitones / 12.);
+            self.unison.set_frequency(frequency);
+            self.sub.set_frequency(frequency);
+            let osc2_pitch = self.patch.get(ParamId::Osc2Pitch);
+            self.osc2
+                .osc
+                .set_frequency(frequency * 2f32.powf(osc2_pitch / 12.));
+        }
+    }
+
+    /// Tune the oscillators to `note`, gliding from the previous note if portamento is on
+    fn set_pitch_note(&mut self, note: u8) {
+        match self.pitch_note {
+            Some(previous) if self.patch.get(ParamId::Portamento) >= 0.5 => {
+                let mode = GlideMode::from_param(self.patch.get(ParamId::GlideMode));
+                let interval = note as f32 - previous as f32;
+                self.glide
+                    .start(interval, mode, self.patch.get(ParamId::GlideTime));
+            }
+            _ => self.glide.stop(),
+        }
+        self.pitch_note = Some(note);
+        self.update_pitch();
+    }
+
+    fn priority(&self) -> NotePriority {
+        NotePriority::from_param(self.patch.get(ParamId::NotePriority))
+    }
+
+    fn update_cutoff(&mut self) {
+        let octaves = PRESSURE_CUTOFF_OCTAVES * (self.pressure - 1.)
+            + TIMBRE_CUTOFF_OCTAVES * (self.timbre - 0.5);
+        let spec = ParamId::LpCutoff.spec();
+        let cutoff = self.patch.get(ParamId::LpCutoff) * 2f32.powf(octaves);
+        let cutoff = cutoff.clamp(spec.min, spec.max);
+        self.lp.for_each(|lp| lp.set_cutoff(cutoff));
+        self.ladder.for_each(|ladder| ladder.set_cutoff(cutoff));
+    }
+
+    /// Press a key
+    ///
+    /// While other keys are held the envelope is not retriggered (legato). Which of the held keys
+    /// sounds is decided by the note priority. The oscillators only restart at their start phase
+    /// when the envelope is idle, a jump of the phase during the release of the last note clicks.
+    pub fn handle_note_on(&mut self, note: u8, velocity: u8) {
+        let legato = !self.held.is_empty();
+        self.held.push(note);
+        let selected = self.held.select(self.priority());
+        if selected != self.note {
+            self.note = selected;
+            if let Some(selected) = selected {
+                self.set_pitch_note(selected);
+            }
+        }
+        if !legato {
+            if self.env.is_idle() {
+                self.unison.reset();
+                self.sub.reset();
+                self.osc2.osc.reset();
+            }
+            self.env.note_on(note, velocity)
+        }
+    }
+
+    /// Release a key
+    ///
+    /// If other keys are still held, the voice returns to the one selected by the note priority.
+    pub fn handle_note_off(&mut self, note: u8, velocity: u8) {
+        self.held.remove(note);
+        match self.held.select(self.priority()) {
+            Some(next) => {
+                if self.note != Some(next) {
+                    self.note = Some(next);
+                    self.set_pitch_note(next);
+                }
+            }
+            None => {
+                self.note = None;
+                self.env.note_off(note, velocity);
+            }
+        }
+    }
+
+    /// Release all keys
+    pub fn release_all(&mut self) {
+        self.held.clear();
+        if let Some(note) = self.note.take() {
+            self.env.note_off(note, 0);
+        }
+    }
+
+    fn handle_control_change(&mut self, cc: ControlChange) {
+        if let ControlChange::CC { control, value } = cc {
+            if let Some(ControlTarget::Params(targets, x)) = self.controls.handle_cc(control, value)
+            {
+                for id in targets.iter() {
+                    self.set_param(id, id.spec().from_normalized(x));
+                }
+            }
         }
     }
 
-    pub fn generate(&mut self) -> f32 {
-        let osc_output = self.osc.iter_mut().map(|o| o.generate()).sum::<f32>();
-        let env_output = self.env.filter(osc_output);
-        let lp_output = self.lp.filter(env_output);
+    /// Mix of all oscillators before the envelope and the filters
+    ///
+    /// The oscillators modulate and sync each other from sample to sample, so they always run
+    /// one frame at a time.
+    fn generate_sources(&mut self) -> Stereo {
+        if self.glide.is_active() {
+            self.glide_counter += 1;
+            if self.glide_counter >= GLIDE_INTERVAL {
+                self.glide_counter = 0;
+                self.glide.advance(config::dt() * GLIDE_INTERVAL as f32);
+                self.update_pitch();
+            }
+        }
+
+        // the FM source modulates the frequency of the target, one sample late
+        let fm_source = OscId::from_param(self.patch.get(ParamId::FmSource));
+        let fm_target = OscId::from_param(self.patch.get(ParamId::FmTarget));
+        let fm = fm_factor(
+            self.outputs[fm_source as usize],
+            self.patch.get(ParamId::FmAmount),
+        );
+        let fm_of = |id: OscId| match id == fm_target {
+            true => fm,
+            false => 1.,
+        };
+        self.unison
+            .oscillators_mut()
+            .for_each(|o| o.get_phase_generator().set_fm(fm_of(OscId::Osc1)));
+        self.osc2
+            .osc
+            .get_phase_generator()
+            .set_fm(fm_of(OscId::Osc2));
+        self.sub.osc.get_phase_generator().set_fm(fm_of(OscId::Sub));
+
+        let mut unison = self.unison.generate();
+        let master_wrap = match self.patch.get(ParamId::Sync) >= 0.5 {
+            true => self.unison.centre_mut().get_phase_generator().wrapped(),
+            false => None,
+        };
+        let mut osc2 = self.osc2.generate_synced(master_wrap);
+        let mut sub = self.sub.generate();
+        self.outputs = [stereo::mono(unison), osc2, sub];
+
+        // the ring source modulates the amplitude of the target
+        let ring = self.patch.get(ParamId::RingMix);
+        let ring_source =
+            self.outputs[OscId::from_param(self.patch.get(ParamId::RingSource)) as usize];
+        match OscId::from_param(self.patch.get(ParamId::RingTarget)) {
+            OscId::Osc1 => unison = unison.map(|x| ring_mod(x, ring_source, ring)),
+            OscId::Osc2 => osc2 = ring_mod(osc2, ring_source, ring),
+            OscId::Sub => sub = ring_mod(sub, ring_source, ring),
+        }
+
+        let osc2_output = osc2 * self.patch.get(ParamId::Osc2Level);
+        let sub_output = sub * self.patch.get(ParamId::SubLevel);
+        // the unison is spread across the stereo field, the other sources sit in the centre
+        let centre = stereo::pan(osc2_output + sub_output + self.noise.generate(), 0.);
+        [unison[0] + centre[0], unison[1] + centre[1]]
+    }
+
+    /// Gain of the pressure, 1 at full pressure
+    fn pressure_gain(&self) -> f32 {
+        1. - PRESSURE_AMP_DEPTH * (1. - self.pressure)
+    }
+}
+
+impl Instrument for Voice {
+    fn generate(&mut self) -> Stereo {
+        let sources = self.generate_sources();
+        let gain = self.env.filter(1.) * self.pressure_gain();
+        let osc_output = sources.map(|x| gain * x);
+        // filter type 0 is the biquad, 1 and 2 are the 12 and 24 dB ladder
+        let lp_output = match self.patch.get(ParamId::FilterType) < 0.5 {
+            true => self.lp.filter(osc_output),
+            false => self.ladder.filter(osc_output),
+        };
         let hp_output = self.hp.filter(lp_output);
 
-        hp_output
+        stereo::balance(hp_output, self.patch.get(ParamId::Pan))
     }
 
-    pub fn handle_midi(&mut self, msg: MidiMsg) {
+    fn generate_block(&mut self, block: &mut [Stereo]) {
+        let [left, right] = stereo::balance([1., 1.], self.patch.get(ParamId::Pan));
+        let mut gains = [0.; BLOCK_SIZE];
+        for chunk in block.chunks_mut(BLOCK_SIZE) {
+            let gains = &mut gains[..chunk.len()];
+            gains.fill(self.pressure_gain());
+            self.env.process_block(gains);
+            for (frame, gain) in chunk.iter_mut().zip(gains.iter()) {
+                *frame = self.generate_sources().map(|x| gain * x);
+            }
+            match self.patch.get(ParamId::FilterType) < 0.5 {
+                true => self.lp.process_block(chunk),
+                false => self.ladder.process_block(chunk),
+            }
+            self.hp.process_block(chunk);
+            for frame in chunk.iter_mut() {
+                frame[0] *= left;
+                frame[1] *= right;
+            }
+        }
+    }
+
+    fn handle_midi(&mut self, msg: MidiMsg) {
         if let MidiMsg::ChannelVoice {
             channel: Channel::Ch1,
             msg,
@@ -69,83 +489,49 @@ impl Voice {
         }
     }
 
-    fn handle_note_on(&mut self, note: u8, velocity: u8) {
-        println!("on {}", note);
-        self.note = Some(note);
-        self.osc.iter_mut().for_each(|o| o.set_note(note));
-        self.env.note_on(note, velocity)
+    fn patch(&self) -> Patch {
+        self.patch
     }
 
-    fn handle_note_off(&mut self, note: u8, velocity: u8) {
-        println!("off {}", note);
-        self.note = None;
-        self.env.note_off(note, velocity);
+    fn load_patch(&mut self, patch: &Patch) {
+        for id in ParamId::all() {
+            self.set_param(id, patch.get(id));
+        }
     }
 
-    fn handle_control_change(&mut self, cc: ControlChange) {
-        match cc {
-            // Oscillators
-            ControlChange::CC { control: 14, value } => {
-                let tuning_factor = log_map(value, 1.1, 0., 5.);
-                for i in (0..self.osc.len() - 1).step_by(2) {
-                    self.osc[i].tune(tuning_factor);
-                    self.osc[i + 1].tune(1. / tuning_factor);
-                }
-            }
-            // Low-pass filter
-            ControlChange::CC { control: 15, value } => {
-                let cutoff_freq = log_map(value, 2.0, 7., 14.);
-                self.lp.set_cutoff(cutoff_freq);
-            }
-            ControlChange::CC { control: 16, value } => {
-                let q = log_map(value, 2., -4., 2.);
-                self.lp.set_q(q);
-            }
-            // High-pass filter
-            ControlChange::CC { control: 17, value } => {
-                let cutoff_freq = log_map(value, 2.0, 7., 14.);
-                self.hp.set_cutoff(cutoff_freq);
-            }
-            ControlChange::CC { control: 18, value } => {
-                let q = log_map(value, 2., -4., 2.);
-                self.hp.set_q(q);
-            }
-            // Envelope
-            ControlChange::CC { control: 19, value } => {
-                self.env.attack_time = log_map(value, 10., -4., 0.);
-            }
-            ControlChange::CC { control: 20, value } => {
-                self.env.decay_time = log_map(value, 10., -4., 0.);
-            }
-            ControlChange::CC { control: 21, value } => {
-                self.env.sustain_level = log_map(value, 10., -4., 0.);
-            }
-            ControlChange::CC { control: 22, value } => {
-                self.env.release_time = log_map(value, 10., -4., 0.);
-            }
-            ControlChange::CC { control: 23, value } => {
-                let delta_t = log_map(value, 10., -4., 0.);
-                self.env.attack_time = delta_t;
-                self.env.decay_time = delta_t;
-                self.env.release_time = delta_t;
-            }
-
-            _ => {}
-        }
+    fn learn(&mut self) -> &mut MidiLearn {
+        &mut self.controls.learn
     }
 }
 
-mod helpers {
-    use micromath::F32Ext;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    /// Linearly maps a value from the range [0, 127] to the range [vmin, vmax].
-    pub fn linear_map(value: u8, vmin: f32, vmax: f32) -> f32 {
-        vmin + (vmax - vmin) * (value as f32 / 127.0)
+    fn osc2_phase(voice: &mut Voice) -> f32 {
+        voice.osc2.osc.get_phase_generator().phase()
     }
 
-    /// Logarithmically maps a value from the range [0, 127] to the range [vmin, vmax].
-    pub fn log_map(value: u8, base: f32, expmin: f32, expmax: f32) -> f32 {
-        let x = linear_map(value, expmin, expmax);
-        base.powf(x)
+    #[test]
+    fn retrigger_keeps_the_phases_until_the_release_is_over() {
+        let mut voice = Voice::new();
+        let mut block = [[0.; 2]; 64];
+        voice.handle_note_on(60, 100);
+        voice.generate_block(&mut block);
+        voice.handle_note_off(60, 0);
+        voice.generate_block(&mut block);
+
+        // the release still rings, the oscillators run on
+        let phase = osc2_phase(&mut voice);
+        assert_ne!(phase, 0.);
+        voice.handle_note_on(62, 100);
+        assert_eq!(osc2_phase(&mut voice), phase);
+
+        voice.handle_note_off(62, 0);
+        while !voice.env.is_idle() {
+            voice.generate_block(&mut block);
+        }
+        voice.handle_note_on(64, 100);
+        assert_eq!(osc2_phase(&mut voice), 0.);
     }
 }
@@ -0,0 +1,78 @@
+/// How the duration of a glide depends on the interval
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlideMode {
+    /// Every glide takes the glide time, regardless of the interval
+    ConstantTime,
+    /// Glides move one octave per glide time
+    ConstantRate,
+}
+
+impl GlideMode {
+    /// Decode the value of [`crate::params::ParamId::GlideMode`]
+    pub fn from_param(value: f32) -> Self {
+        if value < 0.5 {
+            GlideMode::ConstantTime
+        } else {
+            GlideMode::ConstantRate
+        }
+    }
+}
+
+/// Slews the pitch from the previous note to the current one (portamento)
+///
+/// The glide is an offset in semitones from the pitch of the current note that moves linearly
+/// towards zero, so the frequency changes exponentially, like on analog synths.
+pub struct Glide {
+    /// pitch offset from the current note in semitones
+    offset: f32,
+    /// change of the offset in semitones per second, range = (0, inf)
+    rate: f32,
+}
+
+impl Glide {
+    pub fn new() -> Self {
+        Self {
+            offset: 0.,
+            rate: 1.,
+        }
+    }
+
+    /// Start gliding to a note `interval` semitones away from the previous one
+    ///
+    /// A glide that is still in progress continues from its current pitch.
+    pub fn start(&mut self, interval: f32, mode: GlideMode, time: f32) {
+        self.offset -= interval;
+        let distance = match mode {
+            GlideMode::ConstantTime if self.offset < 0. => -self.offset,
+            GlideMode::ConstantTime => self.offset,
+            GlideMode::ConstantRate => 12.,
+        };
+        self.rate = distance / time.max(1e-3);
+    }
+
+    /// Jump to the current note
+    pub fn stop(&mut self) {
+        self.offset = 0.;
+    }
+
+    /// Current pitch offset in semitones
+    pub fn offset(&self) -> f32 {
+        self.offset
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.offset != 0.
+    }
+
+    /// Advance the glide by `dt` seconds
+    pub fn advance(&mut self, dt: f32) {
+        let step = self.rate * dt;
+        self.offset = if self.offset > step {
+            self.offset - step
+        } else if self.offset < -step {
+            self.offset + step
+        } else {
+            0.
+        };
+    }
+}
@@ -0,0 +1,73 @@
+use alloc::vec::Vec;
+use micromath::F32Ext;
+
+/// Maximum number of held keys that are remembered
+pub const MAX_HELD_NOTES: usize = 16;
+
+/// Which of the held keys a monophonic voice plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NotePriority {
+    /// The most recently pressed key
+    Last,
+    /// The lowest held key
+    Low,
+    /// The highest held key
+    High,
+}
+
+impl NotePriority {
+    /// Decode the value of [`crate::params::ParamId::NotePriority`]
+    pub fn from_param(value: f32) -> Self {
+        match value.round() as u8 {
+            0 => NotePriority::Last,
+            1 => NotePriority::Low,
+            _ => NotePriority::High,
+        }
+    }
+}
+
+/// Keys that are currently held, in the order they were pressed
+pub struct NoteStack {
+    notes: Vec<u8>,
+}
+
+impl NoteStack {
+    pub fn new() -> Self {
+        Self {
+            notes: Vec::with_capacity(MAX_HELD_NOTES),
+        }
+    }
+
+    /// Add a pressed key, forgetting the oldest one if the stack is full
+    pub fn push(&mut self, note: u8) {
+        self.remove(note);
+        if self.notes.len() >= MAX_HELD_NOTES {
+            self.notes.remove(0);
+        }
+        self.notes.push(note);
+    }
+
+    /// Remove a released key, returns false if it wasn't held
+    pub fn remove(&mut self, note: u8) -> bool {
+        let len = self.notes.len();
+        self.notes.retain(|&n| n != note);
+        self.notes.len() != len
+    }
+
+    pub fn clear(&mut self) {
+        self.notes.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.notes.is_empty()
+    }
+
+    /// The key to play according to `priority`
+    pub fn select(&self, priority: NotePriority) -> Option<u8> {
+        match priority {
+            NotePriority::Last => self.notes.last().copied(),
+            NotePriority::Low => self.notes.iter().min().copied(),
+            NotePriority::High => self.notes.iter().max().copied(),
+        }
+    }
+}